@@ -0,0 +1,588 @@
+//! Support for editing posts in the browser.
+//!
+//! A post being edited is markdown with YAML-style front matter holding its metadata,
+//! the same shape the server gives for `?front_matter=true`. The [`Editor`] re-renders
+//! only the top level blocks that changed since the last update, and reports which
+//! source lines each block of the preview came from.
+
+use crate::{
+    lint::{lint, Diagnostic, LintOptions, Rule, Severity, Span},
+    number_sidenotes, process_images, table_of_contents, word_count, ImageAsset, TocEntry,
+};
+use anyhow::format_err;
+use markdown::mdast::Node;
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+use std::collections::HashMap;
+
+const FRONT_MATTER_FENCE: &str = "---";
+
+/// A problem with the front matter, covering the whole of its line
+fn problem(severity: Severity, line: usize, text: &str, message: impl Into<String>) -> Diagnostic {
+    Diagnostic {
+        rule: Rule::FrontMatter,
+        severity,
+        span: Span::line(line, text.chars().count()),
+        message: message.into(),
+    }
+}
+
+/// A post's metadata, from the front matter at the top of its source
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FrontMatter {
+    pub title: Option<String>,
+    pub slug: Option<String>,
+    /// `YYYY-MM-DD`, optionally followed by a time as in RFC 3339
+    pub date: Option<String>,
+    pub tags: Vec<String>,
+}
+
+/// A source split into its front matter and the markdown after it
+pub struct SplitSource<'a> {
+    pub front_matter: Option<FrontMatter>,
+    pub body: &'a str,
+    /// Lines before the body starts, to turn body line numbers into source line numbers
+    pub body_line_offset: usize,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// A front matter value: a quoted or bare string, or a `[list, of, strings]`
+fn parse_value(value: &str) -> Result<Vec<String>, String> {
+    let unquote = |s: &str| -> Result<String, String> {
+        let s = s.trim();
+        match s.chars().next() {
+            Some('"') => serde_json::from_str(s).map_err(|_| format!("Bad quoted string {s}")),
+            Some('\'') if s.len() >= 2 && s.ends_with('\'') => {
+                Ok(s[1..s.len() - 1].replace("''", "'"))
+            }
+            _ => Ok(s.to_string()),
+        }
+    };
+
+    match value.strip_prefix('[') {
+        Some(list) => {
+            let list = list
+                .strip_suffix(']')
+                .ok_or_else(|| "Lists need a closing `]`".to_string())?;
+
+            list.split(',')
+                .filter(|item| !item.trim().is_empty())
+                .map(unquote)
+                .collect()
+        }
+        None => Ok(vec![unquote(value)?]),
+    }
+}
+
+fn is_valid_date(date: &str) -> bool {
+    let re = regex::Regex::new(
+        r"^\d{4}-\d{2}-\d{2}([T ]\d{2}:\d{2}(:\d{2}(\.\d+)?)?(Z|[+-]\d{2}:\d{2})?)?$",
+    )
+    .unwrap();
+
+    re.is_match(date)
+}
+
+/// Same rule as the server's
+fn is_valid_slug(slug: &str) -> bool {
+    !slug.is_empty()
+        && slug
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn parse_front_matter(lines: &[&str], diagnostics: &mut Vec<Diagnostic>) -> FrontMatter {
+    let mut front_matter = FrontMatter::default();
+    let mut seen = Vec::new();
+
+    // The opening fence is line 1
+    for (i, line) in lines.iter().enumerate() {
+        let line_number = i + 2;
+
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once(':') else {
+            diagnostics.push(problem(
+                Severity::Error,
+                line_number,
+                line,
+                "Expected `key: value`",
+            ));
+            continue;
+        };
+
+        let key = key.trim();
+
+        if seen.contains(&key) {
+            diagnostics.push(problem(
+                Severity::Warning,
+                line_number,
+                line,
+                format!("`{key}` is set more than once, so only the last one counts"),
+            ));
+        }
+        seen.push(key);
+
+        let values = match parse_value(value.trim()) {
+            Ok(v) => v,
+            Err(e) => {
+                diagnostics.push(problem(Severity::Error, line_number, line, e));
+                continue;
+            }
+        };
+
+        let single = || values.first().cloned().filter(|v| !v.is_empty());
+
+        match key {
+            "title" => front_matter.title = single(),
+            "slug" => front_matter.slug = single(),
+            "date" => front_matter.date = single(),
+            "tags" => front_matter.tags = values.into_iter().filter(|t| !t.is_empty()).collect(),
+            _ => diagnostics.push(problem(
+                Severity::Warning,
+                line_number,
+                line,
+                format!("Unknown key `{key}` is ignored"),
+            )),
+        }
+    }
+
+    front_matter
+}
+
+/// Check the metadata is enough to publish the post
+fn validate_front_matter(front_matter: &FrontMatter, diagnostics: &mut Vec<Diagnostic>) {
+    if front_matter.title.is_none() {
+        diagnostics.push(problem(
+            Severity::Error,
+            1,
+            FRONT_MATTER_FENCE,
+            "Posts need a `title`",
+        ));
+    }
+
+    match &front_matter.slug {
+        None => diagnostics.push(problem(
+            Severity::Error,
+            1,
+            FRONT_MATTER_FENCE,
+            "Posts need a `slug`",
+        )),
+        Some(slug) if !is_valid_slug(slug) => diagnostics.push(problem(
+            Severity::Error,
+            1,
+            FRONT_MATTER_FENCE,
+            format!("The slug `{slug}` can only have letters, numbers, `-` and `_`"),
+        )),
+        _ => (),
+    }
+
+    match &front_matter.date {
+        None => diagnostics.push(problem(
+            Severity::Warning,
+            1,
+            FRONT_MATTER_FENCE,
+            "No `date`, so the post is dated when it's published",
+        )),
+        Some(date) if !is_valid_date(date) => diagnostics.push(problem(
+            Severity::Error,
+            1,
+            FRONT_MATTER_FENCE,
+            format!("The date `{date}` should look like 2023-06-21 or 2023-06-21T09:00:00Z"),
+        )),
+        _ => (),
+    }
+}
+
+/// Separate the front matter, if any, from the markdown and check it
+pub fn split_front_matter(source: &str) -> SplitSource<'_> {
+    let mut diagnostics = Vec::new();
+
+    let no_front_matter = |diagnostics| SplitSource {
+        front_matter: None,
+        body: source,
+        body_line_offset: 0,
+        diagnostics,
+    };
+
+    let first_line = source.lines().next().unwrap_or_default();
+
+    if first_line.trim_end() != FRONT_MATTER_FENCE {
+        return no_front_matter(vec![problem(
+            Severity::Error,
+            1,
+            first_line,
+            "Posts start with front matter between `---` lines, with at least a title and slug",
+        )]);
+    }
+
+    let lines = source.lines().collect::<Vec<_>>();
+
+    let Some(close) = lines
+        .iter()
+        .skip(1)
+        .position(|l| l.trim_end() == FRONT_MATTER_FENCE)
+    else {
+        return no_front_matter(vec![problem(
+            Severity::Error,
+            1,
+            first_line,
+            "The front matter needs a closing `---`",
+        )]);
+    };
+
+    let front_matter = parse_front_matter(&lines[1..close + 1], &mut diagnostics);
+    validate_front_matter(&front_matter, &mut diagnostics);
+
+    // Both fences and everything between them
+    let body_line_offset = close + 2;
+    let body_start = source
+        .match_indices('\n')
+        .nth(body_line_offset - 1)
+        .map(|(i, _)| i + 1)
+        .unwrap_or(source.len());
+
+    SplitSource {
+        front_matter: Some(front_matter),
+        body: &source[body_start..],
+        body_line_offset,
+        diagnostics,
+    }
+}
+
+/// The html of one top level block of the source
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PreviewBlock {
+    /// First and last source lines of the block, 1-indexed and inclusive
+    pub start_line: usize,
+    pub end_line: usize,
+    pub html: String,
+}
+
+/// The result of an edit
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EditorUpdate {
+    pub front_matter: Option<FrontMatter>,
+    /// The preview, block by block. Joined with newlines they're the post's html
+    pub blocks: Vec<PreviewBlock>,
+    pub toc: Vec<TocEntry>,
+    pub word_count: usize,
+    pub diagnostics: Vec<Diagnostic>,
+    /// How many blocks had to be rendered, rather than coming from the last update
+    pub rendered_blocks: usize,
+}
+
+/// Renders a post as it's edited, keeping the html of each block between updates
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct Editor {
+    sidenotes: bool,
+    lint_options: LintOptions,
+    images: HashMap<String, ImageAsset>,
+    /// Rendered html, by the markdown it came from
+    cache: HashMap<String, String>,
+    /// Line ranges of the blocks from the last update
+    block_lines: Vec<(usize, usize)>,
+}
+
+impl Editor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Render sidenotes, which posts on the server don't have by default
+    pub fn sidenotes(&mut self, sidenotes: bool) -> &mut Self {
+        self.sidenotes = sidenotes;
+        self
+    }
+
+    /// Warn about lines longer than this, if given
+    pub fn max_line_length(&mut self, max_line_length: Option<usize>) -> &mut Self {
+        self.lint_options.max_line_length = max_line_length;
+        self
+    }
+
+    /// Uploaded images, so previews get the same `srcset`s as the server's pages
+    pub fn images(&mut self, images: &[ImageAsset]) -> &mut Self {
+        self.images = images.iter().map(|i| (i.src.clone(), i.clone())).collect();
+        self
+    }
+
+    /// Which block of the last update `line` is in, or the nearest one before it
+    pub fn block_for_line(&self, line: usize) -> Option<usize> {
+        self.block_lines
+            .iter()
+            .rposition(|(start, _)| *start <= line)
+            .or((!self.block_lines.is_empty()).then_some(0))
+    }
+
+    fn render_block(&mut self, md: &str, rendered: &mut usize) -> anyhow::Result<String> {
+        if let Some(html) = self.cache.get(md) {
+            return Ok(html.clone());
+        }
+
+        let html = markdown::to_html_with_options(md, &markdown::Options::gfm())
+            .map_err(|e| format_err!("{}", e))?;
+
+        *rendered += 1;
+        self.cache.insert(md.to_string(), html.clone());
+        Ok(html)
+    }
+
+    pub fn update(&mut self, source: &str) -> anyhow::Result<EditorUpdate> {
+        let SplitSource {
+            front_matter,
+            body,
+            body_line_offset,
+            mut diagnostics,
+        } = split_front_matter(source);
+
+        diagnostics.extend(
+            lint(body, &self.lint_options)?
+                .into_iter()
+                .map(|d| Diagnostic {
+                    span: d.span.offset_lines(body_line_offset),
+                    ..d
+                }),
+        );
+
+        let root = markdown::to_mdast(body, &markdown::ParseOptions::gfm())
+            .map_err(|e| format_err!("{}", e))?;
+        let nodes = root.children().map(Vec::as_slice).unwrap_or_default();
+
+        let slice = |node: &Node| {
+            node.position().map(|p| {
+                (
+                    p.start.line,
+                    p.end.line,
+                    &body[p.start.offset..p.end.offset],
+                )
+            })
+        };
+
+        // Link definitions can be used from any block, so every block is rendered with them.
+        // Footnotes are numbered across the whole post, so they can't be split up at all
+        let has_footnotes = nodes
+            .iter()
+            .any(|n| matches!(n, Node::FootnoteDefinition(_)));
+
+        let definitions = nodes
+            .iter()
+            .filter(|n| matches!(n, Node::Definition(_)))
+            .filter_map(slice)
+            .map(|(_, _, md)| md)
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let sources = match has_footnotes {
+            true => vec![(1, body.lines().count().max(1), body.to_string())],
+            false => nodes
+                .iter()
+                .filter(|n| !matches!(n, Node::Definition(_)))
+                .filter_map(slice)
+                .map(|(start, end, md)| (start, end, format!("{md}\n\n{definitions}")))
+                .collect(),
+        };
+
+        let previous = std::mem::take(&mut self.cache);
+        let mut rendered_blocks = 0;
+        let mut next_sidenote = 1;
+        let mut blocks = Vec::new();
+
+        for (start, end, md) in sources {
+            // Put back what's still in use, so the cache doesn't grow as the post changes
+            if let Some(html) = previous.get(&md) {
+                self.cache.insert(md.clone(), html.clone());
+            }
+
+            let mut html = self.render_block(&md, &mut rendered_blocks)?;
+
+            if self.sidenotes {
+                (html, next_sidenote) = number_sidenotes(&html, next_sidenote);
+            }
+
+            if !self.images.is_empty() {
+                html = process_images(&html, &self.images);
+            }
+
+            blocks.push(PreviewBlock {
+                start_line: start + body_line_offset,
+                end_line: end + body_line_offset,
+                html: html.trim_end().to_string(),
+            });
+        }
+
+        self.block_lines = blocks.iter().map(|b| (b.start_line, b.end_line)).collect();
+
+        Ok(EditorUpdate {
+            front_matter,
+            blocks,
+            toc: table_of_contents(body)?,
+            word_count: word_count(body)?,
+            diagnostics,
+            rendered_blocks,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The severity, line and message of each diagnostic, which should all be about front matter
+    fn problems(split: &SplitSource) -> Vec<(Severity, usize, String)> {
+        split
+            .diagnostics
+            .iter()
+            .inspect(|d| assert_eq!(d.rule, Rule::FrontMatter))
+            .map(|d| (d.severity, d.span.start_line, d.message.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn splits_front_matter_from_the_body() {
+        let source = "---\ntitle: \"A \\\"post\\\"\"\nslug: a-post\ndate: 2023-06-21T09:00:00Z\n# a comment\ntags: [one, 'it''s', \"three\", ]\n---\n# Body\n";
+        let split = split_front_matter(source);
+
+        assert_eq!(
+            split.front_matter,
+            Some(FrontMatter {
+                title: Some("A \"post\"".into()),
+                slug: Some("a-post".into()),
+                date: Some("2023-06-21T09:00:00Z".into()),
+                tags: vec!["one".into(), "it's".into(), "three".into()],
+            })
+        );
+        assert_eq!(split.body, "# Body\n");
+        assert_eq!(split.body_line_offset, 7);
+        assert!(split.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn missing_front_matter_is_an_error() {
+        for source in ["", "# Just a post\n", "--- \ntitle: x\n"] {
+            let split = split_front_matter(source);
+
+            assert!(split.front_matter.is_none());
+            assert_eq!(split.body, source);
+            assert_eq!(split.body_line_offset, 0);
+
+            let expected = match source {
+                "--- \ntitle: x\n" => "The front matter needs a closing `---`",
+                _ => "Posts start with front matter between `---` lines, with at least a title and slug",
+            };
+            assert_eq!(problems(&split), [(Severity::Error, 1, expected.into())]);
+        }
+    }
+
+    #[test]
+    fn bad_lines_are_reported_where_they_are() {
+        let source = "---\ntitle: One\ntitle: Two\nslug: ok\ndate: 2023-06-21\nnot a pair\ntags: [a, b\nauthor: me\nslug: \"unclosed\n---\n";
+        let split = split_front_matter(source);
+
+        assert_eq!(
+            problems(&split),
+            [
+                (
+                    Severity::Warning,
+                    3,
+                    "`title` is set more than once, so only the last one counts".into()
+                ),
+                (Severity::Error, 6, "Expected `key: value`".into()),
+                (Severity::Error, 7, "Lists need a closing `]`".into()),
+                (
+                    Severity::Warning,
+                    8,
+                    "Unknown key `author` is ignored".into()
+                ),
+                (
+                    Severity::Warning,
+                    9,
+                    "`slug` is set more than once, so only the last one counts".into()
+                ),
+                (Severity::Error, 9, "Bad quoted string \"unclosed".into()),
+            ]
+        );
+
+        // Spans cover the whole of the line
+        assert_eq!(split.diagnostics[1].span, Span::line(6, "not a pair".len()));
+
+        let front_matter = split.front_matter.unwrap();
+        assert_eq!(front_matter.title.as_deref(), Some("Two"));
+        assert_eq!(front_matter.slug.as_deref(), Some("ok"));
+        assert!(front_matter.tags.is_empty());
+    }
+
+    #[test]
+    fn metadata_is_checked_for_publishing() {
+        let split = split_front_matter("---\ntags: []\n---\n");
+        assert_eq!(
+            problems(&split),
+            [
+                (Severity::Error, 1, "Posts need a `title`".into()),
+                (Severity::Error, 1, "Posts need a `slug`".into()),
+                (
+                    Severity::Warning,
+                    1,
+                    "No `date`, so the post is dated when it's published".into()
+                ),
+            ]
+        );
+        assert_eq!(split.body, "");
+
+        let split = split_front_matter("---\ntitle: t\nslug: not/ok\ndate: June 21\n---\nBody");
+        assert_eq!(
+            problems(&split),
+            [
+                (
+                    Severity::Error,
+                    1,
+                    "The slug `not/ok` can only have letters, numbers, `-` and `_`".into()
+                ),
+                (
+                    Severity::Error,
+                    1,
+                    "The date `June 21` should look like 2023-06-21 or 2023-06-21T09:00:00Z".into()
+                ),
+            ]
+        );
+        assert_eq!(split.body, "Body");
+    }
+
+    #[test]
+    fn updates_only_render_changed_blocks() {
+        let mut editor = Editor::new();
+        let front_matter = "---\ntitle: t\nslug: s\ndate: 2023-06-21\n---\n";
+
+        let first = editor
+            .update(&format!("{front_matter}# One\n\nTwo\n"))
+            .unwrap();
+        assert_eq!(first.rendered_blocks, 2);
+        assert_eq!(
+            first.blocks,
+            [
+                PreviewBlock {
+                    start_line: 6,
+                    end_line: 6,
+                    html: "<h1>One</h1>".into()
+                },
+                PreviewBlock {
+                    start_line: 8,
+                    end_line: 8,
+                    html: "<p>Two</p>".into()
+                },
+            ]
+        );
+
+        let second = editor
+            .update(&format!("{front_matter}# One\n\nThree\n"))
+            .unwrap();
+        assert_eq!(second.rendered_blocks, 1);
+        assert_eq!(second.blocks[1].html, "<p>Three</p>");
+        assert_eq!(editor.block_for_line(7), Some(0));
+        assert_eq!(editor.block_for_line(9), Some(1));
+    }
+}
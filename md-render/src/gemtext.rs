@@ -0,0 +1,482 @@
+//! Render markdown as gemtext, for serving posts over Gemini.
+//! See <https://geminiprotocol.net/docs/gemtext-specification.gmi>
+//!
+//! Gemtext has no inline formatting or inline links, so emphasis is dropped and
+//! links are listed on their own lines after the block they appear in.
+//! Sidenotes and footnotes are numbered and collected at the end.
+
+use crate::{collapse_whitespace, collect_definitions, SIDENOTE_PATTERN};
+use anyhow::format_err;
+use markdown::mdast::Node;
+
+use std::collections::HashMap;
+
+/// Marks where a footnote reference was, until notes are numbered
+const FOOTNOTE_START: char = '\x01';
+const FOOTNOTE_END: char = '\x02';
+
+#[derive(Default)]
+struct Renderer {
+    lines: Vec<String>,
+    /// Link reference definitions, by identifier
+    definitions: HashMap<String, String>,
+    /// Footnote numbers, by identifier
+    footnote_numbers: HashMap<String, usize>,
+    /// The text of each note, in number order
+    notes: Vec<String>,
+}
+
+/// An inline run of text, with the links found in it
+#[derive(Default)]
+struct Inline {
+    text: String,
+    links: Vec<(String, String)>,
+}
+
+fn link_line(url: &str, label: &str) -> String {
+    match label.trim() {
+        "" => format!("=> {url}"),
+        l if l == url => format!("=> {url}"),
+        l => format!("=> {url} {l}"),
+    }
+}
+
+impl Renderer {
+    /// The number of the note for a footnote, assigning the next one on first use
+    fn footnote_number(&mut self, identifier: &str) -> usize {
+        if let Some(n) = self.footnote_numbers.get(identifier) {
+            return *n;
+        }
+
+        self.notes.push(String::new());
+        let n = self.notes.len();
+        self.footnote_numbers.insert(identifier.to_string(), n);
+        n
+    }
+
+    fn inline(&mut self, node: &Node, out: &mut Inline) {
+        match node {
+            Node::Text(t) => out.text.push_str(&t.value),
+            Node::InlineCode(c) => out.text.push_str(&format!("`{}`", c.value)),
+            Node::InlineMath(m) => out.text.push_str(&m.value),
+            Node::Break(_) => out.text.push(' '),
+            Node::Html(_) => (),
+
+            Node::Link(l) => {
+                let mut label = Inline::default();
+                for child in &l.children {
+                    self.inline(child, &mut label);
+                }
+
+                out.text.push_str(&label.text);
+                out.links
+                    .push((l.url.clone(), collapse_whitespace(&label.text)));
+                out.links.extend(label.links);
+            }
+
+            Node::LinkReference(l) => {
+                let mut label = Inline::default();
+                for child in &l.children {
+                    self.inline(child, &mut label);
+                }
+
+                out.text.push_str(&label.text);
+
+                if let Some(url) = self.definitions.get(&l.identifier).cloned() {
+                    out.links.push((url, collapse_whitespace(&label.text)));
+                }
+                out.links.extend(label.links);
+            }
+
+            Node::Image(i) => out.links.push((i.url.clone(), i.alt.clone())),
+
+            Node::ImageReference(i) => {
+                if let Some(url) = self.definitions.get(&i.identifier).cloned() {
+                    out.links.push((url, i.alt.clone()));
+                }
+            }
+
+            // Numbered along with sidenotes in `inline_line`, so notes stay in reading order
+            Node::FootnoteReference(f) => out
+                .text
+                .push_str(&format!("{FOOTNOTE_START}{}{FOOTNOTE_END}", f.identifier)),
+
+            other => {
+                for child in other.children().into_iter().flatten() {
+                    self.inline(child, out);
+                }
+            }
+        }
+    }
+
+    /// Flatten inline content into one line, numbering its sidenotes and footnote references
+    fn inline_line(&mut self, children: &[Node]) -> Inline {
+        let mut inline = Inline::default();
+
+        for child in children {
+            self.inline(child, &mut inline);
+        }
+
+        let notes = regex::RegexBuilder::new(&format!(
+            r"\s*{SIDENOTE_PATTERN}|{FOOTNOTE_START}(?<footnote>[^{FOOTNOTE_END}]*){FOOTNOTE_END}"
+        ))
+        .dot_matches_new_line(true)
+        .build()
+        .unwrap();
+
+        let text = notes
+            .replace_all(&inline.text, |caps: &regex::Captures| {
+                let n = match caps.name("footnote") {
+                    Some(id) => self.footnote_number(id.as_str()),
+                    None => {
+                        self.notes.push(collapse_whitespace(&caps["text"]));
+                        self.notes.len()
+                    }
+                };
+                format!("[{n}]")
+            })
+            .to_string();
+
+        inline.text = collapse_whitespace(&text);
+        inline
+    }
+
+    fn push_links(&mut self, links: &[(String, String)]) {
+        self.lines
+            .extend(links.iter().map(|(url, label)| link_line(url, label)));
+    }
+
+    fn blank(&mut self) {
+        if self.lines.last().is_some_and(|l| !l.is_empty()) {
+            self.lines.push(String::new());
+        }
+    }
+
+    /// The text of a list item or quote, as lines, with any links found in it
+    fn flow_lines(
+        &mut self,
+        children: &[Node],
+        lines: &mut Vec<String>,
+        links: &mut Vec<(String, String)>,
+    ) {
+        for child in children {
+            match child {
+                Node::Paragraph(p) => {
+                    let inline = self.inline_line(&p.children);
+                    lines.push(inline.text);
+                    links.extend(inline.links);
+                }
+                Node::Heading(h) => {
+                    let inline = self.inline_line(&h.children);
+                    lines.push(inline.text);
+                    links.extend(inline.links);
+                }
+                Node::Code(c) => lines.extend(c.value.lines().map(String::from)),
+                other => self.flow_lines(
+                    other.children().map(Vec::as_slice).unwrap_or_default(),
+                    lines,
+                    links,
+                ),
+            }
+        }
+    }
+
+    fn list(&mut self, list: &markdown::mdast::List, links: &mut Vec<(String, String)>) {
+        let mut number = list.start.unwrap_or(1);
+
+        for item in &list.children {
+            let Node::ListItem(item) = item else { continue };
+
+            let mut text = Vec::new();
+            let mut nested = Vec::new();
+
+            for child in &item.children {
+                match child {
+                    Node::List(l) => nested.push(l),
+                    other => self.flow_lines(std::slice::from_ref(other), &mut text, links),
+                }
+            }
+
+            let checkbox = match item.checked {
+                Some(true) => "[x] ",
+                Some(false) => "[ ] ",
+                None => "",
+            };
+
+            // Gemtext lists are flat and unnumbered, so ordered items keep their number in the text
+            let line = match list.ordered {
+                true => format!("{number}. {checkbox}{}", text.join(" ")),
+                false => format!("* {checkbox}{}", text.join(" ")),
+            };
+
+            self.lines.push(line);
+            number += 1;
+
+            for l in nested {
+                self.list(l, links);
+            }
+        }
+    }
+
+    fn table(&mut self, table: &markdown::mdast::Table) {
+        let rows = table
+            .children
+            .iter()
+            .map(|row| {
+                row.children()
+                    .into_iter()
+                    .flatten()
+                    .map(|cell| {
+                        self.inline_line(cell.children().map(Vec::as_slice).unwrap_or_default())
+                            .text
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+        let widths = (0..columns)
+            .map(|i| {
+                rows.iter()
+                    .filter_map(|r| r.get(i))
+                    .map(|c| c.chars().count())
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect::<Vec<_>>();
+
+        let format_row = |row: &Vec<String>| {
+            widths
+                .iter()
+                .enumerate()
+                .map(|(i, w)| format!("{:<w$}", row.get(i).map(String::as_str).unwrap_or_default()))
+                .collect::<Vec<_>>()
+                .join(" | ")
+                .trim_end()
+                .to_string()
+        };
+
+        self.lines.push("```table".into());
+
+        for (i, row) in rows.iter().enumerate() {
+            self.lines.push(format_row(row));
+
+            if i == 0 {
+                let rule = widths
+                    .iter()
+                    .map(|w| "-".repeat(*w))
+                    .collect::<Vec<_>>()
+                    .join("-+-");
+                self.lines.push(rule);
+            }
+        }
+
+        self.lines.push("```".into());
+    }
+
+    fn block(&mut self, node: &Node) {
+        match node {
+            Node::Root(r) => {
+                for child in &r.children {
+                    self.block(child);
+                }
+            }
+
+            Node::Heading(h) => {
+                let inline = self.inline_line(&h.children);
+                let level = "#".repeat(h.depth.clamp(1, 3) as usize);
+
+                self.lines.push(format!("{level} {}", inline.text));
+                self.push_links(&inline.links);
+                self.blank();
+            }
+
+            Node::Paragraph(p) => {
+                let inline = self.inline_line(&p.children);
+
+                if !inline.text.is_empty() {
+                    self.lines.push(inline.text);
+                }
+                self.push_links(&inline.links);
+                self.blank();
+            }
+
+            Node::List(l) => {
+                let mut links = Vec::new();
+                self.list(l, &mut links);
+                self.push_links(&links);
+                self.blank();
+            }
+
+            Node::BlockQuote(q) => {
+                let mut lines = Vec::new();
+                let mut links = Vec::new();
+                self.flow_lines(&q.children, &mut lines, &mut links);
+
+                self.lines.extend(lines.iter().map(|l| format!("> {l}")));
+                self.push_links(&links);
+                self.blank();
+            }
+
+            Node::Code(c) => {
+                self.lines
+                    .push(format!("```{}", c.lang.as_deref().unwrap_or_default()));
+                self.lines.extend(c.value.lines().map(String::from));
+                self.lines.push("```".into());
+                self.blank();
+            }
+
+            Node::Math(m) => {
+                self.lines.push("```math".into());
+                self.lines.extend(m.value.lines().map(String::from));
+                self.lines.push("```".into());
+                self.blank();
+            }
+
+            Node::Table(t) => {
+                self.table(t);
+                self.blank();
+            }
+
+            Node::ThematicBreak(_) => {
+                self.lines.push("---".into());
+                self.blank();
+            }
+
+            Node::FootnoteDefinition(f) => {
+                let n = self.footnote_number(&f.identifier);
+                let mut lines = Vec::new();
+                let mut links = Vec::new();
+                self.flow_lines(&f.children, &mut lines, &mut links);
+
+                self.notes[n - 1] = lines.join(" ");
+            }
+
+            // Raw html, link definitions and front matter have nothing to show
+            _ => (),
+        }
+    }
+}
+
+/// Render a markdown document as gemtext
+pub fn md_to_gemtext(md_content: &str) -> anyhow::Result<String> {
+    let root = markdown::to_mdast(md_content, &markdown::ParseOptions::gfm())
+        .map_err(|e| format_err!("{}", e))?;
+
+    let mut renderer = Renderer::default();
+    collect_definitions(&root, &mut renderer.definitions);
+    renderer.block(&root);
+
+    if !renderer.notes.is_empty() {
+        renderer.blank();
+        renderer.lines.push("## Notes".into());
+
+        let notes = std::mem::take(&mut renderer.notes);
+        for (i, note) in notes.iter().enumerate() {
+            renderer.lines.push(format!("[{}] {note}", i + 1));
+        }
+    }
+
+    let mut gemtext = renderer.lines.join("\n").trim_end().to_string();
+    gemtext.push('\n');
+
+    Ok(gemtext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links_follow_their_paragraph() {
+        let gemtext = md_to_gemtext(
+            "See [the spec](https://example.com/spec) and <https://example.com>.\n\n\
+             Then [a reference][ref].\n\n\
+             ![a cat](/cat.png)\n\n\
+             [ref]: https://example.com/ref\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            gemtext,
+            "See the spec and https://example.com.\n\
+             => https://example.com/spec the spec\n\
+             => https://example.com\n\
+             \n\
+             Then a reference.\n\
+             => https://example.com/ref a reference\n\
+             \n\
+             => /cat.png a cat\n"
+        );
+    }
+
+    #[test]
+    fn links_in_lists_and_quotes_follow_the_block() {
+        let gemtext = md_to_gemtext(
+            "- [one](https://example.com/1)\n- two\n\n> quoted [link](https://example.com/q)\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            gemtext,
+            "* one\n\
+             * two\n\
+             => https://example.com/1 one\n\
+             \n\
+             > quoted link\n\
+             => https://example.com/q link\n"
+        );
+    }
+
+    #[test]
+    fn code_blocks_are_preformatted() {
+        let gemtext =
+            md_to_gemtext("Some code:\n\n```rust\nfn main() {\n    [not](a link)\n}\n```\n")
+                .unwrap();
+
+        assert_eq!(
+            gemtext,
+            "Some code:\n\
+             \n\
+             ```rust\n\
+             fn main() {\n    [not](a link)\n}\n\
+             ```\n"
+        );
+    }
+
+    #[test]
+    fn headings_lists_and_breaks() {
+        let gemtext =
+            md_to_gemtext("# Title\n\n#### Deep *heading*\n\n3. three\n4. [x] four\n\n---\n")
+                .unwrap();
+
+        assert_eq!(
+            gemtext,
+            "# Title\n\
+             \n\
+             ### Deep heading\n\
+             \n\
+             3. three\n\
+             4. [x] four\n\
+             \n\
+             ---\n"
+        );
+    }
+
+    #[test]
+    fn notes_are_numbered_in_reading_order() {
+        let gemtext = md_to_gemtext(
+            "A sidenote (:sidenote first :sidenote) and a footnote[^a].\n\n[^a]: second\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            gemtext,
+            "A sidenote[1] and a footnote[2].\n\
+             \n\
+             ## Notes\n\
+             [1] first\n\
+             [2] second\n"
+        );
+    }
+}
@@ -1,9 +1,9 @@
 use anyhow::format_err;
 use handlebars::Handlebars;
 
-use js_sys;
 use markdown::to_html_with_options;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs::File;
 use std::include_str;
 use std::io::Read;
@@ -11,21 +11,37 @@ use std::path::{Path, PathBuf};
 
 use wasm_bindgen::prelude::*;
 
+pub mod editor;
+pub mod gemtext;
+pub mod lint;
+pub mod sanitize;
+pub mod text;
+mod wasm;
+pub use gemtext::md_to_gemtext;
+pub use sanitize::{SanitizePolicy, TrustLevel};
+pub use text::{md_to_text, TEXT_WIDTH};
+
 #[derive(Deserialize, Serialize)]
 struct RenderParams {
     pub title: String,
     pub home_url: String,
     pub content: String,
     pub favicon_path: String,
-    pub quotes_list_json: &'static str,
+    /// Shown in the header, if there is one
+    pub quote: Option<String>,
     pub css: &'static str,
+    /// Content-Security-Policy nonce for the inline style and script
+    pub nonce: Option<String>,
 }
 
 pub const FAVICON_URL: &str = "/static/favicon.io";
 static CSS: &str = include_str!("../../assets/style.css");
-static QUOTES: &str = include_str!("../../assets/quotes.json");
 
 static BASE_TEMPLATE: &str = include_str!("../../assets/templates/base.html");
+
+/// Matches a `(:sidenote ... :sidenote)` marker, capturing its contents as `text`
+pub(crate) const SIDENOTE_PATTERN: &str = r"\(:sidenote(?<text>.*?):sidenote\)";
+
 impl Default for RenderParams {
     fn default() -> Self {
         RenderParams {
@@ -33,8 +49,9 @@ impl Default for RenderParams {
             home_url: "/".into(),
             content: String::new(),
             favicon_path: FAVICON_URL.into(),
-            quotes_list_json: QUOTES,
+            quote: None,
             css: CSS,
+            nonce: None,
         }
     }
 }
@@ -49,14 +66,105 @@ impl RenderParams {
     }
 }
 
+/// What the rendered html is wrapped in
+#[wasm_bindgen]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Template {
+    /// Just the content, for embedding in another page
+    #[default]
+    Bare,
+    /// A full page with the site's styles, see `assets/templates/base.html`
+    Base,
+}
+
 #[wasm_bindgen]
-#[derive(Default)]
 pub struct RenderBuilder {
     title: Option<String>,
     md_content: Option<String>,
     html_content: Option<String>,
     sidenotes: bool,
-    into_base_template: bool,
+    template: Template,
+    trust: TrustLevel,
+    sanitize_policy: SanitizePolicy,
+    heading_ids: bool,
+    images: HashMap<String, ImageAsset>,
+    nonce: Option<String>,
+    quote: Option<String>,
+}
+
+impl Default for RenderBuilder {
+    fn default() -> Self {
+        Self {
+            title: None,
+            md_content: None,
+            html_content: None,
+            sidenotes: false,
+            template: Template::default(),
+            trust: TrustLevel::Untrusted,
+            sanitize_policy: SanitizePolicy::default(),
+            heading_ids: false,
+            images: HashMap::new(),
+            nonce: None,
+            quote: None,
+        }
+    }
+}
+
+/// A heading in a markdown document, for building a table of contents
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TocEntry {
+    pub depth: u8,
+    pub text: String,
+    /// What the heading's `id` is when rendered with `heading_ids`
+    pub id: String,
+}
+
+/// Everything known about a render, beyond the html itself
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RenderOutput {
+    pub html: String,
+    /// Headings in document order. Empty when rendering html content
+    pub toc: Vec<TocEntry>,
+    /// Only known for markdown content
+    pub word_count: Option<usize>,
+    /// Problems with the content that didn't stop it rendering
+    pub warnings: Vec<String>,
+}
+
+/// A resized copy of an uploaded image
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImageVariant {
+    pub src: String,
+    pub width: u32,
+}
+
+/// An uploaded image and its resized variants, as known to the server.
+/// Markdown images whose `src` matches an asset get dimensions and a `srcset`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImageAsset {
+    pub src: String,
+    pub width: u32,
+    pub height: u32,
+    pub variants: Vec<ImageVariant>,
+}
+
+impl ImageAsset {
+    pub fn srcset(&self) -> String {
+        let mut candidates = self
+            .variants
+            .iter()
+            .map(|v| (v.src.as_str(), v.width))
+            .collect::<Vec<_>>();
+
+        candidates.push((&self.src, self.width));
+        candidates.sort_by_key(|(_, w)| *w);
+
+        candidates
+            .iter()
+            .map(|(src, w)| format!("{src} {w}w"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
 }
 
 impl RenderBuilder {
@@ -64,6 +172,13 @@ impl RenderBuilder {
         Self::default()
     }
     pub fn render(&self) -> anyhow::Result<String> {
+        Ok(self.render_output()?.html)
+    }
+
+    /// Render, along with a table of contents, word count and any warnings about the content
+    pub fn render_output(&self) -> anyhow::Result<RenderOutput> {
+        let mut warnings = Vec::new();
+
         //if html was given directly, use that,
         //Otherwise get the string markdown content to render
         let mut html_str = if let Some(ref html_content) = self.html_content {
@@ -83,23 +198,79 @@ impl RenderBuilder {
                 .map_err(|e| format_err!("{}", e))
         }?;
 
+        let (toc, word_count) = match &self.md_content {
+            Some(md) => (table_of_contents(md)?, Some(word_count(md)?)),
+            None => (Vec::new(), None),
+        };
+
         //if applicable, do postprocessing
 
+        let unclosed_sidenotes = html_str
+            .matches("(:sidenote")
+            .count()
+            .saturating_sub(html_str.matches(":sidenote)").count());
+
+        if unclosed_sidenotes > 0 {
+            warnings.push(format!(
+                "{unclosed_sidenotes} sidenote(s) are missing their closing `:sidenote)`"
+            ));
+        }
+
         if self.sidenotes {
             html_str = process_sidenotes(&html_str);
+        } else if html_str.contains("(:sidenote") {
+            warnings.push("Sidenote markers are shown as text, since sidenotes are off".into());
         }
 
-        if self.into_base_template {
+        if !self.images.is_empty() {
+            for src in image_sources(&html_str) {
+                if !self.images.contains_key(&src) {
+                    warnings.push(format!("Image {src} isn't a known upload"));
+                }
+            }
+
+            html_str = process_images(&html_str, &self.images);
+        }
+
+        // Last, so nothing added by processing gets past it
+        if self.trust == TrustLevel::Untrusted {
+            html_str = self.sanitize_policy.clean(&html_str);
+        }
+
+        // After sanitizing, which strips ids. These are generated, so they're safe
+        if self.heading_ids && !toc.is_empty() {
+            html_str = add_heading_ids(&html_str, &toc);
+        }
+
+        if self.template == Template::Base {
             let mut hb = Handlebars::new();
 
             hb.register_template_string("base", BASE_TEMPLATE)?;
 
-            let render_params = RenderParams::new(&self.title.clone().unwrap(), &html_str);
+            let title = match (&self.title, toc.iter().find(|t| t.depth == 1)) {
+                (Some(title), _) => title.clone(),
+                (None, Some(heading)) => heading.text.clone(),
+                (None, None) => {
+                    warnings.push("No title was given for the page".into());
+                    RenderParams::default().title
+                }
+            };
+
+            let render_params = RenderParams {
+                nonce: self.nonce.clone(),
+                quote: self.quote.clone(),
+                ..RenderParams::new(&title, &html_str)
+            };
 
             html_str = hb.render("base", &serde_json::to_value(render_params)?)?;
         }
 
-        Ok(html_str)
+        Ok(RenderOutput {
+            html: html_str,
+            toc,
+            word_count,
+            warnings,
+        })
     }
 
     pub fn html_content<'a>(&'a mut self, html_content: &str) -> &'a mut Self {
@@ -119,7 +290,63 @@ impl RenderBuilder {
 
     pub fn into_base_template(&mut self, title: &str) -> &mut Self {
         self.title = Some(title.into());
-        self.into_base_template = true;
+        self.template = Template::Base;
+        self
+    }
+
+    /// The page title, when rendering into a template.
+    /// Defaults to the first top level heading
+    pub fn title(&mut self, title: &str) -> &mut Self {
+        self.title = Some(title.into());
+        self
+    }
+
+    pub fn template(&mut self, template: Template) -> &mut Self {
+        self.template = template;
+        self
+    }
+
+    /// Content-Security-Policy nonce the template's inline style and script carry,
+    /// so they're allowed by a policy without `'unsafe-inline'`
+    pub fn nonce(&mut self, nonce: &str) -> &mut Self {
+        self.nonce = Some(nonce.into());
+        self
+    }
+
+    /// The quote for the template's header. There's no header quote without one
+    pub fn quote(&mut self, quote: &str) -> &mut Self {
+        self.quote = Some(quote.into());
+        self
+    }
+
+    /// Give headings ids matching the table of contents, so they can be linked to
+    pub fn heading_ids(&mut self) -> &mut Self {
+        self.heading_ids = true;
+        self
+    }
+
+    /// Strip anything unsafe from the rendered html, for content from untrusted sources
+    pub fn sanitize(&mut self) -> &mut Self {
+        self.trust(TrustLevel::Untrusted)
+    }
+
+    /// How far to trust the content, which decides whether it's sanitized.
+    /// Content is untrusted unless a caller opts in, so forgetting to set this
+    /// can't let unsanitized html through
+    pub fn trust(&mut self, trust: TrustLevel) -> &mut Self {
+        self.trust = trust;
+        self
+    }
+
+    /// What sanitizing untrusted content keeps
+    pub fn sanitize_policy(&mut self, policy: SanitizePolicy) -> &mut Self {
+        self.sanitize_policy = policy;
+        self
+    }
+
+    pub fn images(&mut self, images: &[ImageAsset]) -> &mut Self {
+        self.images
+            .extend(images.iter().map(|i| (i.src.clone(), i.clone())));
         self
     }
 }
@@ -144,10 +371,15 @@ pub fn read_file_contents(file_path: impl AsRef<Path>) -> anyhow::Result<String>
 
 #[wasm_bindgen]
 pub fn process_sidenotes(document_input: &str) -> String {
+    number_sidenotes(document_input, 1).0
+}
+
+/// Turn sidenote markers into margin notes with ids counting up from `first`,
+/// returning the next unused number, so a document can be processed in pieces
+pub(crate) fn number_sidenotes(document_input: &str, first: usize) -> (String, usize) {
     let mut document = String::from(document_input);
-    let pattern_str = r"\(:sidenote(?<text>.*?):sidenote\)".to_string();
-    let mut counter: usize = 1;
-    let re = regex::RegexBuilder::new(&pattern_str)
+    let mut counter = first;
+    let re = regex::RegexBuilder::new(SIDENOTE_PATTERN)
         .dot_matches_new_line(true)
         .build()
         .unwrap();
@@ -167,7 +399,170 @@ pub fn process_sidenotes(document_input: &str) -> String {
         document = re.replace(&document, replacement).to_string();
     }
 
-    document
+    (document, counter)
+}
+
+/// Clean html down to a conservative set of tags and attributes, dropping scripts,
+/// event handlers and `javascript:` urls
+pub fn sanitize_html(document: &str) -> String {
+    SanitizePolicy::default().clean(document)
+}
+
+/// Add dimensions, a `srcset` and lazy loading to `<img>` tags that point at a known upload.
+/// Images that aren't in `images` are left alone.
+pub fn process_images(document: &str, images: &HashMap<String, ImageAsset>) -> String {
+    let re = regex::Regex::new(r#"<img src="(?<src>[^"]*)"(?<rest>[^>]*?)\s*/?>"#).unwrap();
+
+    re.replace_all(document, |caps: &regex::Captures| {
+        let src = &caps["src"];
+        let rest = &caps["rest"];
+
+        match images.get(src) {
+            None => caps[0].to_string(),
+            Some(img) => format!(
+                r#"<img src="{src}"{rest} width="{}" height="{}" srcset="{}" sizes="(max-width: {}px) 100vw, {}px" loading="lazy" />"#,
+                img.width,
+                img.height,
+                img.srcset(),
+                img.width,
+                img.width
+            ),
+        }
+    })
+    .to_string()
+}
+
+/// Link reference definitions in a document, by identifier
+pub(crate) fn collect_definitions(
+    node: &markdown::mdast::Node,
+    definitions: &mut HashMap<String, String>,
+) {
+    if let markdown::mdast::Node::Definition(d) = node {
+        definitions.insert(d.identifier.clone(), d.url.clone());
+    }
+
+    for child in node.children().into_iter().flatten() {
+        collect_definitions(child, definitions);
+    }
+}
+
+pub(crate) fn collapse_whitespace(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn collect_text(node: &markdown::mdast::Node, buf: &mut String) {
+    use markdown::mdast::Node;
+
+    match node {
+        Node::Text(t) => buf.push_str(&t.value),
+        Node::InlineCode(c) => buf.push_str(&c.value),
+        Node::Code(_) | Node::Html(_) => return,
+        _ => (),
+    }
+
+    buf.push(' ');
+
+    if let Some(children) = node.children() {
+        for child in children {
+            collect_text(child, buf);
+        }
+    }
+}
+
+/// Count the words of prose in a markdown document,
+/// skipping code blocks, raw html and sidenote markers
+pub fn word_count(md_content: &str) -> anyhow::Result<usize> {
+    let root = markdown::to_mdast(md_content, &markdown::ParseOptions::gfm())
+        .map_err(|e| format_err!("{}", e))?;
+
+    let mut text = String::new();
+    collect_text(&root, &mut text);
+
+    Ok(text
+        .split_whitespace()
+        .filter(|w| !w.starts_with("(:sidenote") && !w.ends_with(":sidenote)"))
+        .filter(|w| w.chars().any(char::is_alphanumeric))
+        .count())
+}
+
+/// A heading's text as an html id, e.g. `Why shave?` becomes `why-shave`
+pub(crate) fn heading_id(text: &str) -> String {
+    let mut id = String::new();
+
+    for c in text.chars() {
+        match c {
+            c if c.is_alphanumeric() => id.extend(c.to_lowercase()),
+            _ if id.ends_with('-') || id.is_empty() => (),
+            _ => id.push('-'),
+        }
+    }
+
+    match id.trim_end_matches('-') {
+        "" => "section".into(),
+        id => id.into(),
+    }
+}
+
+fn collect_headings(node: &markdown::mdast::Node, toc: &mut Vec<TocEntry>) {
+    if let markdown::mdast::Node::Heading(h) = node {
+        let mut text = String::new();
+        h.children.iter().for_each(|c| collect_text(c, &mut text));
+        let text = collapse_whitespace(&text);
+
+        toc.push(TocEntry {
+            depth: h.depth,
+            id: heading_id(&text),
+            text,
+        });
+        return;
+    }
+
+    for child in node.children().into_iter().flatten() {
+        collect_headings(child, toc);
+    }
+}
+
+/// The headings of a markdown document, with ids made unique by numbering repeats
+pub fn table_of_contents(md_content: &str) -> anyhow::Result<Vec<TocEntry>> {
+    let root = markdown::to_mdast(md_content, &markdown::ParseOptions::gfm())
+        .map_err(|e| format_err!("{}", e))?;
+
+    let mut toc = Vec::new();
+    collect_headings(&root, &mut toc);
+
+    let mut seen = HashMap::<String, usize>::new();
+
+    for entry in &mut toc {
+        let count = seen.entry(entry.id.clone()).or_default();
+        *count += 1;
+
+        if *count > 1 {
+            entry.id = format!("{}-{}", entry.id, *count - 1);
+        }
+    }
+
+    Ok(toc)
+}
+
+/// Give the html's headings the ids from `toc`, in order
+fn add_heading_ids(document: &str, toc: &[TocEntry]) -> String {
+    let re = regex::Regex::new(r"<h(?<depth>[1-6])>").unwrap();
+    let mut entries = toc.iter();
+
+    re.replace_all(document, |caps: &regex::Captures| match entries.next() {
+        Some(entry) => format!(r#"<h{} id="{}">"#, &caps["depth"], entry.id),
+        None => caps[0].to_string(),
+    })
+    .to_string()
+}
+
+/// The `src` of every `<img>` tag in the html
+fn image_sources(document: &str) -> Vec<String> {
+    let re = regex::Regex::new(r#"<img src="(?<src>[^"]*)""#).unwrap();
+
+    re.captures_iter(document)
+        .map(|caps| caps["src"].to_string())
+        .collect()
 }
 
 #[wasm_bindgen]
@@ -182,6 +577,7 @@ impl MdRenderOpts {
     /// Take a JS object of the form: {with_template: true, with_sidenotes_false},
     /// and initializes MdRenderOpts from those values.
     /// Extra values are ignored
+    #[allow(clippy::redundant_closure, clippy::unnecessary_fallible_conversions)]
     pub fn from_obj(obj: js_sys::Object) -> Self {
         let mut base_opts = Self::default();
 
@@ -0,0 +1,552 @@
+//! Checks for mistakes in a post's markdown that still render, but not as intended.
+//!
+//! Errors are things that break the page, like a sidenote that swallows the rest of the
+//! post. Warnings are things worth a second look.
+
+use crate::heading_id;
+use anyhow::format_err;
+use markdown::mdast::Node;
+use serde::{Deserialize, Serialize};
+
+use std::{collections::HashSet, ops::Range};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// The post shouldn't be published like this
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Rule {
+    UnclosedSidenote,
+    DuplicateHeadingId,
+    EmptyLink,
+    MissingAltText,
+    HeadingJump,
+    BareUrl,
+    LongLine,
+    /// Problems with a post's metadata, see `editor::split_front_matter`
+    FrontMatter,
+}
+
+/// Where a problem is in the source. Lines and columns are 1-indexed, columns count
+/// characters, and the end is exclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Span {
+    pub start_line: usize,
+    pub start_column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+}
+
+impl Span {
+    /// All of a line that's `len` characters long
+    pub fn line(line: usize, len: usize) -> Self {
+        Self {
+            start_line: line,
+            start_column: 1,
+            end_line: line,
+            end_column: len + 1,
+        }
+    }
+
+    /// The same span in a document with `lines` more lines before it
+    pub fn offset_lines(self, lines: usize) -> Self {
+        Self {
+            start_line: self.start_line + lines,
+            end_line: self.end_line + lines,
+            ..self
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub rule: Rule,
+    pub severity: Severity,
+    pub span: Span,
+    pub message: String,
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.span.start_line, self.span.start_column, self.message
+        )
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LintOptions {
+    /// Longest a line outside of code can be, if there's a limit.
+    /// Off by default, since plenty of posts keep each paragraph on one line
+    pub max_line_length: Option<usize>,
+}
+
+/// Turns byte offsets into lines and character columns
+struct Source<'a> {
+    text: &'a str,
+    line_starts: Vec<usize>,
+}
+
+impl<'a> Source<'a> {
+    fn new(text: &'a str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+
+        Self { text, line_starts }
+    }
+
+    fn point(&self, offset: usize) -> (usize, usize) {
+        let line = self.line_starts.partition_point(|start| *start <= offset);
+        let column = self.text[self.line_starts[line - 1]..offset]
+            .chars()
+            .count()
+            + 1;
+        (line, column)
+    }
+
+    fn span(&self, range: Range<usize>) -> Span {
+        let (start_line, start_column) = self.point(range.start);
+        let (end_line, end_column) = self.point(range.end);
+
+        Span {
+            start_line,
+            start_column,
+            end_line,
+            end_column,
+        }
+    }
+}
+
+struct Linter<'a> {
+    source: Source<'a>,
+    diagnostics: Vec<Diagnostic>,
+    /// Byte ranges of code, where markers and long lines don't count
+    code: Vec<Range<usize>>,
+    heading_ids: HashSet<String>,
+    last_heading_depth: Option<u8>,
+}
+
+fn range(node: &Node) -> Option<Range<usize>> {
+    node.position().map(|p| p.start.offset..p.end.offset)
+}
+
+fn has_text(nodes: &[Node]) -> bool {
+    nodes.iter().any(|n| match n {
+        Node::Text(t) => !t.value.trim().is_empty(),
+        Node::InlineCode(_) | Node::Image(_) | Node::ImageReference(_) => true,
+        other => has_text(other.children().map(Vec::as_slice).unwrap_or_default()),
+    })
+}
+
+fn heading_text(nodes: &[Node], text: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(t) => text.push_str(&t.value),
+            Node::InlineCode(c) => text.push_str(&c.value),
+            other => heading_text(
+                other.children().map(Vec::as_slice).unwrap_or_default(),
+                text,
+            ),
+        }
+        text.push(' ');
+    }
+}
+
+impl<'a> Linter<'a> {
+    fn report(&mut self, rule: Rule, severity: Severity, range: Range<usize>, message: String) {
+        self.diagnostics.push(Diagnostic {
+            rule,
+            severity,
+            span: self.source.span(range),
+            message,
+        });
+    }
+
+    fn in_code(&self, offset: usize) -> bool {
+        self.code.iter().any(|r| r.contains(&offset))
+    }
+
+    fn node(&mut self, node: &Node) {
+        let Some(range) = range(node) else {
+            return self.children(node);
+        };
+
+        match node {
+            Node::Code(_) | Node::InlineCode(_) | Node::Math(_) | Node::InlineMath(_) => {
+                self.code.push(range);
+            }
+
+            Node::Heading(h) => {
+                let mut text = String::new();
+                heading_text(&h.children, &mut text);
+                let id = heading_id(&text);
+
+                if !self.heading_ids.insert(id.clone()) {
+                    self.report(
+                        Rule::DuplicateHeadingId,
+                        Severity::Warning,
+                        range.clone(),
+                        format!("Another heading already has the id `{id}`, so links to it are ambiguous"),
+                    );
+                }
+
+                if let Some(last) = self.last_heading_depth.filter(|d| h.depth > d + 1) {
+                    self.report(
+                        Rule::HeadingJump,
+                        Severity::Warning,
+                        range.clone(),
+                        format!("Heading skips from level {last} to level {}", h.depth),
+                    );
+                }
+
+                self.last_heading_depth = Some(h.depth);
+            }
+
+            Node::Link(l) => {
+                let source = &self.source.text[range.clone()];
+
+                if l.url.trim().is_empty() {
+                    self.report(
+                        Rule::EmptyLink,
+                        Severity::Error,
+                        range.clone(),
+                        "Link has no url".into(),
+                    );
+                } else if !source.starts_with('[') && !source.starts_with('<') {
+                    self.report(
+                        Rule::BareUrl,
+                        Severity::Warning,
+                        range.clone(),
+                        format!("Bare url, which reads better as <{source}> or [text]({source})"),
+                    );
+                } else if !has_text(&l.children) {
+                    self.report(
+                        Rule::EmptyLink,
+                        Severity::Error,
+                        range.clone(),
+                        "Link has no text, so there's nothing to click".into(),
+                    );
+                }
+            }
+
+            Node::LinkReference(l) if !has_text(&l.children) => {
+                self.report(
+                    Rule::EmptyLink,
+                    Severity::Error,
+                    range.clone(),
+                    "Link has no text, so there's nothing to click".into(),
+                );
+            }
+
+            Node::Definition(d) if d.url.trim().is_empty() => {
+                self.report(
+                    Rule::EmptyLink,
+                    Severity::Error,
+                    range.clone(),
+                    format!(
+                        "Link definition `{}` has no url",
+                        d.label.as_deref().unwrap_or_default()
+                    ),
+                );
+            }
+
+            Node::Image(i) if i.alt.trim().is_empty() => {
+                self.report(
+                    Rule::MissingAltText,
+                    Severity::Warning,
+                    range.clone(),
+                    "Image has no alt text for readers who can't see it".into(),
+                );
+            }
+
+            Node::ImageReference(i) if i.alt.trim().is_empty() => {
+                self.report(
+                    Rule::MissingAltText,
+                    Severity::Warning,
+                    range.clone(),
+                    "Image has no alt text for readers who can't see it".into(),
+                );
+            }
+
+            _ => (),
+        }
+
+        self.children(node);
+    }
+
+    fn children(&mut self, node: &Node) {
+        for child in node.children().into_iter().flatten() {
+            self.node(child);
+        }
+    }
+
+    /// Markers are paired up in order, since sidenotes can't be nested
+    fn sidenotes(&mut self) {
+        let markers = regex::Regex::new(r"\(:sidenote|:sidenote\)").unwrap();
+        let mut open: Option<Range<usize>> = None;
+
+        let found = markers
+            .find_iter(self.source.text)
+            .filter(|m| !self.in_code(m.start()))
+            .map(|m| (m.range(), m.as_str().starts_with('(')))
+            .collect::<Vec<_>>();
+
+        for (range, is_open) in found {
+            match (is_open, open.take()) {
+                (true, Some(unclosed)) => self.report(
+                    Rule::UnclosedSidenote,
+                    Severity::Error,
+                    unclosed,
+                    "Sidenote isn't closed with `:sidenote)` before the next one starts".into(),
+                ),
+                (false, None) => self.report(
+                    Rule::UnclosedSidenote,
+                    Severity::Error,
+                    range.clone(),
+                    "`:sidenote)` has no sidenote to close".into(),
+                ),
+                _ => (),
+            }
+
+            if is_open {
+                open = Some(range);
+            }
+        }
+
+        if let Some(unclosed) = open {
+            self.report(
+                Rule::UnclosedSidenote,
+                Severity::Error,
+                unclosed,
+                "Sidenote is missing its closing `:sidenote)`, so it would swallow the rest of the post".into(),
+            );
+        }
+    }
+
+    fn long_lines(&mut self, max: usize) {
+        for (i, start) in self.source.line_starts.clone().into_iter().enumerate() {
+            let line = self.source.text[start..].lines().next().unwrap_or_default();
+            let len = line.chars().count();
+
+            if len > max && !self.in_code(start + line.len().saturating_sub(1)) {
+                self.diagnostics.push(Diagnostic {
+                    rule: Rule::LongLine,
+                    severity: Severity::Warning,
+                    span: Span::line(i + 1, len),
+                    message: format!("Line is {len} characters long, over the limit of {max}"),
+                });
+            }
+        }
+    }
+}
+
+/// Check a post's markdown, returning problems in the order they appear
+pub fn lint(md_content: &str, options: &LintOptions) -> anyhow::Result<Vec<Diagnostic>> {
+    let root = markdown::to_mdast(md_content, &markdown::ParseOptions::gfm())
+        .map_err(|e| format_err!("{}", e))?;
+
+    let mut linter = Linter {
+        source: Source::new(md_content),
+        diagnostics: Vec::new(),
+        code: Vec::new(),
+        heading_ids: HashSet::new(),
+        last_heading_depth: None,
+    };
+
+    linter.node(&root);
+    linter.sidenotes();
+
+    if let Some(max) = options.max_line_length {
+        linter.long_lines(max);
+    }
+
+    let mut diagnostics = linter.diagnostics;
+    diagnostics.sort_by_key(|d| d.span);
+
+    Ok(diagnostics)
+}
+
+/// Whether any of `diagnostics` should stop a post being published
+pub fn has_errors(diagnostics: &[Diagnostic]) -> bool {
+    diagnostics.iter().any(|d| d.severity == Severity::Error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(start_line: usize, start_column: usize, end_line: usize, end_column: usize) -> Span {
+        Span {
+            start_line,
+            start_column,
+            end_line,
+            end_column,
+        }
+    }
+
+    /// The rule and span of each problem found, with the default options
+    fn found(md: &str) -> Vec<(Rule, Span)> {
+        found_with(md, &LintOptions::default())
+    }
+
+    fn found_with(md: &str, options: &LintOptions) -> Vec<(Rule, Span)> {
+        lint(md, options)
+            .unwrap()
+            .into_iter()
+            .map(|d| (d.rule, d.span))
+            .collect()
+    }
+
+    #[test]
+    fn clean_posts_have_no_problems() {
+        let md = "# Title\n\nSome [text](https://example.com) and <https://example.com>.\n\n\
+                  ## Section\n\n![A cat](/cat.png) (:sidenote a note :sidenote)\n\n\
+                  ### Sub\n\n# Back up\n";
+
+        assert_eq!(found(md), []);
+        assert!(!has_errors(&lint(md, &LintOptions::default()).unwrap()));
+    }
+
+    #[test]
+    fn unclosed_sidenotes() {
+        assert_eq!(
+            found("Text (:sidenote never closed\n"),
+            [(Rule::UnclosedSidenote, span(1, 6, 1, 16))]
+        );
+        assert_eq!(
+            found("a :sidenote) b\n"),
+            [(Rule::UnclosedSidenote, span(1, 3, 1, 13))]
+        );
+        assert_eq!(
+            found("(:sidenote a (:sidenote b :sidenote)\n"),
+            [(Rule::UnclosedSidenote, span(1, 1, 1, 11))]
+        );
+
+        assert_eq!(found("a (:sidenote b\nc :sidenote) d\n"), []);
+        assert_eq!(found("`(:sidenote`\n\n```\n:sidenote)\n```\n"), []);
+
+        let diagnostics = lint("(:sidenote", &LintOptions::default()).unwrap();
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert!(has_errors(&diagnostics));
+    }
+
+    #[test]
+    fn duplicate_heading_ids() {
+        assert_eq!(
+            found("# Intro\n\n## Intro\n"),
+            [(Rule::DuplicateHeadingId, span(3, 1, 3, 9))]
+        );
+        assert_eq!(
+            found("# Intro\n\n## *Intro*?\n"),
+            [(Rule::DuplicateHeadingId, span(3, 1, 3, 12))]
+        );
+        assert_eq!(found("# Intro\n\n## Intro two\n"), []);
+    }
+
+    #[test]
+    fn heading_jumps() {
+        assert_eq!(
+            found("# A\n\n### B\n"),
+            [(Rule::HeadingJump, span(3, 1, 3, 6))]
+        );
+        assert_eq!(found("### A\n\n# B\n\n## C\n\n### D\n\n# E\n"), []);
+    }
+
+    #[test]
+    fn empty_links() {
+        assert_eq!(found("[text]()\n"), [(Rule::EmptyLink, span(1, 1, 1, 9))]);
+        assert_eq!(
+            found("See [](https://example.com)\n"),
+            [(Rule::EmptyLink, span(1, 5, 1, 28))]
+        );
+        assert_eq!(
+            found("[][ref]\n\n[ref]: https://example.com\n"),
+            [(Rule::EmptyLink, span(1, 1, 1, 8))]
+        );
+        assert_eq!(
+            found("[a][ref]\n\n[ref]: <>\n"),
+            [(Rule::EmptyLink, span(3, 1, 3, 10))]
+        );
+
+        assert_eq!(found("[`code`](https://example.com)\n"), []);
+        assert_eq!(found("[![A cat](/cat.png)](/cats)\n"), []);
+        assert!(has_errors(
+            &lint("[text]()", &LintOptions::default()).unwrap()
+        ));
+    }
+
+    #[test]
+    fn missing_alt_text() {
+        assert_eq!(
+            found("![](/cat.png)\n"),
+            [(Rule::MissingAltText, span(1, 1, 1, 14))]
+        );
+        assert_eq!(
+            found("![ ][cat]\n\n[cat]: /cat.png\n"),
+            [(Rule::MissingAltText, span(1, 1, 1, 10))]
+        );
+        assert_eq!(found("![A cat](/cat.png)\n"), []);
+    }
+
+    #[test]
+    fn columns_count_characters() {
+        assert_eq!(
+            found("Café ![](/a.png)\n"),
+            [(Rule::MissingAltText, span(1, 6, 1, 17))]
+        );
+    }
+
+    #[test]
+    fn bare_urls() {
+        let diagnostics = lint("See https://example.com today\n", &LintOptions::default()).unwrap();
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].rule, Rule::BareUrl);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert_eq!(diagnostics[0].span, span(1, 5, 1, 24));
+        assert!(!has_errors(&diagnostics));
+
+        assert_eq!(found("See <https://example.com> today\n"), []);
+        assert_eq!(found("`https://example.com`\n"), []);
+    }
+
+    #[test]
+    fn long_lines() {
+        let md = "short\nthis line is too long\n\n```\nthis code line is long too\n```\n";
+        let options = LintOptions {
+            max_line_length: Some(10),
+        };
+
+        assert_eq!(
+            found_with(md, &options),
+            [(Rule::LongLine, span(2, 1, 2, 22))]
+        );
+        assert_eq!(found(md), []);
+    }
+
+    #[test]
+    fn problems_are_in_source_order() {
+        let rules = found("![](/a.png)\n\n# A\n\n### B (:sidenote\n\n[x]()\n")
+            .into_iter()
+            .map(|(rule, _)| rule)
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            rules,
+            [
+                Rule::MissingAltText,
+                Rule::HeadingJump,
+                Rule::UnclosedSidenote,
+                Rule::EmptyLink
+            ]
+        );
+    }
+}
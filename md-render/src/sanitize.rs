@@ -0,0 +1,376 @@
+//! Cleaning rendered html down to what's safe to show from a source that isn't trusted.
+//!
+//! The default policy allows everything the markdown renderer, sidenotes and image
+//! processing produce, and nothing else: no scripts, styles, event handlers, forms or
+//! iframes, and only `http`, `https` and `mailto` urls.
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    sync::OnceLock,
+};
+
+/// How much content is trusted, by where it came from
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum TrustLevel {
+    /// Comments, webmentions and anything else from outside the site. Always sanitized
+    Untrusted,
+    /// Written by the site's admins, so rendered as is
+    Trusted,
+}
+
+const TAGS: &[&str] = &[
+    "a",
+    "abbr",
+    "b",
+    "blockquote",
+    "br",
+    "code",
+    "del",
+    "details",
+    "em",
+    "figcaption",
+    "figure",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "hr",
+    "i",
+    "img",
+    "input",
+    "ins",
+    "kbd",
+    "label",
+    "li",
+    "mark",
+    "ol",
+    "p",
+    "pre",
+    "q",
+    "s",
+    "section",
+    "small",
+    "span",
+    "strong",
+    "sub",
+    "summary",
+    "sup",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "tr",
+    "u",
+    "ul",
+];
+
+const TAG_ATTRIBUTES: &[(&str, &[&str])] = &[
+    (
+        "a",
+        &[
+            "href",
+            "id",
+            "class",
+            "aria-describedby",
+            "aria-label",
+            "data-footnote-ref",
+            "data-footnote-backref",
+        ],
+    ),
+    (
+        "img",
+        &[
+            "src", "alt", "width", "height", "srcset", "sizes", "loading",
+        ],
+    ),
+    ("input", &["type", "id", "class", "checked", "disabled"]),
+    ("label", &["for", "class"]),
+    ("span", &["class"]),
+    ("section", &["class", "data-footnotes"]),
+    ("h2", &["id", "class"]),
+    ("li", &["id"]),
+    ("code", &["class"]),
+    ("ol", &["start"]),
+    ("th", &["align"]),
+    ("td", &["align"]),
+];
+
+/// Classes the site's styles and scripts give meaning to
+const CLASSES: &[&str] = &[
+    "margin-toggle",
+    "marginnote",
+    "footnotes",
+    "sr-only",
+    "data-footnote-backref",
+];
+
+/// Ids the renderer makes, which can't clobber anything else on the page
+fn is_allowed_id(id: &str) -> bool {
+    id.starts_with("user-content-") || id.starts_with("mn-") || id == "footnote-label"
+}
+
+/// Drop attribute values that are allowed by name but not with that value
+fn filter_attribute<'u>(element: &str, attribute: &str, value: &'u str) -> Option<Cow<'u, str>> {
+    match (element, attribute) {
+        (_, "id") | ("label", "for") => is_allowed_id(value).then_some(value.into()),
+
+        ("input", "type") => (value == "checkbox").then_some(value.into()),
+
+        (_, "class") => {
+            let classes = value
+                .split_whitespace()
+                .filter(|c| CLASSES.contains(c) || c.starts_with("language-"))
+                .collect::<Vec<_>>();
+
+            (!classes.is_empty()).then(|| classes.join(" ").into())
+        }
+
+        _ => Some(value.into()),
+    }
+}
+
+fn as_strs(set: &HashSet<String>) -> HashSet<&str> {
+    set.iter().map(String::as_str).collect()
+}
+
+/// What sanitizing keeps. Start from the default and widen or narrow it as needed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SanitizePolicy {
+    tags: HashSet<String>,
+    tag_attributes: HashMap<String, HashSet<String>>,
+    generic_attributes: HashSet<String>,
+    url_schemes: HashSet<String>,
+    /// Links to other hosts than this one get `external_link_rel`
+    site_host: Option<String>,
+    external_link_rel: Option<String>,
+}
+
+impl Default for SanitizePolicy {
+    fn default() -> Self {
+        Self {
+            tags: TAGS.iter().map(|t| t.to_string()).collect(),
+            tag_attributes: TAG_ATTRIBUTES
+                .iter()
+                .map(|(tag, attributes)| {
+                    (
+                        tag.to_string(),
+                        attributes.iter().map(|a| a.to_string()).collect(),
+                    )
+                })
+                .collect(),
+            generic_attributes: ["title", "lang"].iter().map(|a| a.to_string()).collect(),
+            url_schemes: ["http", "https", "mailto"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+            site_host: None,
+            external_link_rel: Some("noopener noreferrer".into()),
+        }
+    }
+}
+
+impl SanitizePolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn allow_tags(&mut self, tags: &[&str]) -> &mut Self {
+        self.tags.extend(tags.iter().map(|t| t.to_string()));
+        self
+    }
+
+    pub fn remove_tags(&mut self, tags: &[&str]) -> &mut Self {
+        for tag in tags {
+            self.tags.remove(*tag);
+        }
+        self
+    }
+
+    pub fn allow_attributes(&mut self, tag: &str, attributes: &[&str]) -> &mut Self {
+        self.tag_attributes
+            .entry(tag.to_string())
+            .or_default()
+            .extend(attributes.iter().map(|a| a.to_string()));
+        self
+    }
+
+    /// Replace the url schemes links and images can use.
+    /// Relative urls are always allowed
+    pub fn url_schemes(&mut self, schemes: &[&str]) -> &mut Self {
+        self.url_schemes = schemes.iter().map(|s| s.to_string()).collect();
+        self
+    }
+
+    /// The site's own host, so links to it aren't treated as external
+    pub fn site_host(&mut self, host: &str) -> &mut Self {
+        self.site_host = Some(host.to_ascii_lowercase());
+        self
+    }
+
+    /// The `rel` given to external links, or `None` to leave them alone
+    pub fn external_link_rel(&mut self, rel: Option<&str>) -> &mut Self {
+        self.external_link_rel = rel.map(String::from);
+        self
+    }
+
+    fn is_external(&self, href: &str) -> bool {
+        let Some(rest) = href
+            .strip_prefix("https://")
+            .or_else(|| href.strip_prefix("http://"))
+            .or_else(|| href.strip_prefix("//"))
+        else {
+            return false;
+        };
+
+        let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+        let host = authority.rsplit('@').next().unwrap_or_default();
+        let host = host.split(':').next().unwrap_or_default();
+
+        self.site_host
+            .as_ref()
+            .is_none_or(|site| !host.eq_ignore_ascii_case(site))
+    }
+
+    /// Add `rel` to links that leave the site
+    fn mark_external_links(&self, document: &str, rel: &str) -> String {
+        static LINK: OnceLock<regex::Regex> = OnceLock::new();
+        static HREF: OnceLock<regex::Regex> = OnceLock::new();
+
+        let re = LINK.get_or_init(|| {
+            regex::Regex::new(r#"<a (?<attributes>[^>]*?)(?<end>\s*/?)>"#).unwrap()
+        });
+        let href = HREF.get_or_init(|| regex::Regex::new(r#"href="(?<href>[^"]*)""#).unwrap());
+
+        re.replace_all(document, |caps: &regex::Captures| {
+            let attributes = &caps["attributes"];
+
+            match href.captures(attributes) {
+                Some(h) if self.is_external(&h["href"]) => {
+                    format!(r#"<a {attributes} rel="{rel}"{}>"#, &caps["end"])
+                }
+                _ => caps[0].to_string(),
+            }
+        })
+        .to_string()
+    }
+
+    pub fn clean(&self, document: &str) -> String {
+        let cleaned = ammonia::Builder::empty()
+            .tags(as_strs(&self.tags))
+            .tag_attributes(
+                self.tag_attributes
+                    .iter()
+                    .map(|(tag, attributes)| (tag.as_str(), as_strs(attributes)))
+                    .collect(),
+            )
+            .generic_attributes(as_strs(&self.generic_attributes))
+            .url_schemes(as_strs(&self.url_schemes))
+            .attribute_filter(filter_attribute)
+            .link_rel(None)
+            .clean(document)
+            .to_string();
+
+        match &self.external_link_rel {
+            Some(rel) => self.mark_external_links(&cleaned, rel),
+            None => cleaned,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> SanitizePolicy {
+        let mut policy = SanitizePolicy::default();
+        policy.site_host("example.com");
+        policy
+    }
+
+    #[test]
+    fn removes_scripts_handlers_and_styles() {
+        let cleaned = policy().clean(
+            r#"<p style="color: red">hi<script>alert(1)</script></p><img src="a.png" onerror="alert(1)">"#,
+        );
+
+        assert_eq!(cleaned, r#"<p>hi</p><img src="a.png">"#);
+    }
+
+    #[test]
+    fn removes_unsafe_urls() {
+        let cleaned = policy().clean(
+            r#"<a href="javascript:alert(1)">a</a><img src="data:image/png;base64,AAAA"><a href="mailto:me@example.com">b</a>"#,
+        );
+
+        assert_eq!(
+            cleaned,
+            r#"<a>a</a><img><a href="mailto:me@example.com">b</a>"#
+        );
+    }
+
+    #[test]
+    fn keeps_only_renderer_ids_and_classes() {
+        let cleaned = policy().clean(
+            r#"<h2 id="main">a</h2><li id="user-content-fn-1">b</li><label for="mn-1" class="margin-toggle evil">c</label><input type="text" id="mn-1">"#,
+        );
+
+        assert_eq!(
+            cleaned,
+            r#"<h2>a</h2><li id="user-content-fn-1">b</li><label for="mn-1" class="margin-toggle">c</label><input id="mn-1">"#
+        );
+    }
+
+    #[test]
+    fn marks_external_links() {
+        let rel = r#" rel="noopener noreferrer""#;
+        let cases = [
+            ("https://other.org/post", true),
+            ("//other.org/post", true),
+            ("http://example.com@other.org/", true),
+            ("https://EXAMPLE.com:443/post", false),
+            ("//example.com/post", false),
+            ("https://other.org@example.com/", false),
+            ("/blog/post", false),
+            ("#section", false),
+        ];
+
+        for (href, external) in cases {
+            let cleaned = policy().clean(&format!(r#"<a href="{href}">link</a>"#));
+            assert_eq!(cleaned.contains(rel), external, "{href}: {cleaned}");
+        }
+    }
+
+    #[test]
+    fn builder_sanitizes_unless_trusted() {
+        let html = "<p>hi</p><script>alert(1)</script>";
+
+        let by_default = crate::RenderBuilder::new()
+            .html_content(html)
+            .render()
+            .unwrap();
+        assert_eq!(by_default, "<p>hi</p>");
+
+        let trusted = crate::RenderBuilder::new()
+            .html_content(html)
+            .trust(TrustLevel::Trusted)
+            .render()
+            .unwrap();
+        assert_eq!(trusted, html);
+    }
+
+    #[test]
+    fn every_link_is_external_without_a_site_host() {
+        let cleaned = SanitizePolicy::default().clean(r#"<a href="https://example.com/">a</a>"#);
+
+        assert!(cleaned.contains(r#"rel="noopener noreferrer""#));
+    }
+}
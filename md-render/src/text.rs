@@ -0,0 +1,510 @@
+//! Render markdown as wrapped plain text, for serving posts over Gopher.
+//!
+//! Links are numbered like `text[1]`, with the urls listed at the end.
+//! Sidenotes and footnotes share the same numbering, so every reference is in one list.
+
+use crate::{collapse_whitespace, collect_definitions, SIDENOTE_PATTERN};
+use anyhow::format_err;
+use markdown::mdast::{self, Node};
+
+use std::collections::HashMap;
+
+/// Column plain text is wrapped at unless told otherwise
+pub const TEXT_WIDTH: usize = 70;
+
+/// Marks a reference in flattened text until it's numbered. The first character after
+/// the start is `l` for a link (followed by its url) or `f` for a footnote (followed by its id)
+const REFERENCE_START: char = '\x01';
+const REFERENCE_END: char = '\x02';
+/// Marks a hard line break, since newlines in text are just soft breaks from the source
+const HARD_BREAK: char = '\x03';
+
+#[derive(Default)]
+struct Renderer {
+    /// Link reference definitions, by identifier
+    definitions: HashMap<String, String>,
+    /// Reference numbers, by url for links and by identifier for footnotes
+    numbers: HashMap<String, usize>,
+    /// The url or note text of each reference, in number order
+    references: Vec<String>,
+    /// Site relative links are resolved against this, since they're read elsewhere
+    base_url: Option<String>,
+}
+
+/// Break `text` into lines of at most `width` columns, where possible.
+/// The first line starts with `first_prefix` and the rest with `rest_prefix`
+fn wrap(text: &str, width: usize, first_prefix: &str, rest_prefix: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = first_prefix.to_string();
+    let mut line_is_empty = true;
+
+    for word in text.split_whitespace() {
+        let fits = line.chars().count() + 1 + word.chars().count() <= width;
+
+        if !line_is_empty && !fits {
+            lines.push(std::mem::replace(&mut line, rest_prefix.to_string()));
+            line_is_empty = true;
+        }
+
+        if !line_is_empty {
+            line.push(' ');
+        }
+
+        line.push_str(word);
+        line_is_empty = false;
+    }
+
+    if !line_is_empty || lines.is_empty() {
+        lines.push(line.trim_end().to_string());
+    }
+
+    lines
+}
+
+/// Blocks separated by blank lines
+fn join_blocks(blocks: Vec<Vec<String>>) -> Vec<String> {
+    blocks
+        .into_iter()
+        .filter(|b| !b.is_empty())
+        .collect::<Vec<_>>()
+        .join(&String::new())
+}
+
+fn children(node: &Node) -> &[Node] {
+    node.children().map(Vec::as_slice).unwrap_or_default()
+}
+
+impl Renderer {
+    fn reference_number(&mut self, key: String, value: String) -> usize {
+        if let Some(n) = self.numbers.get(&key) {
+            return *n;
+        }
+
+        self.references.push(value);
+        let n = self.references.len();
+        self.numbers.insert(key, n);
+        n
+    }
+
+    fn inline(&self, node: &Node, out: &mut String) {
+        match node {
+            Node::Text(t) => out.push_str(&t.value),
+            Node::InlineCode(c) => out.push_str(&format!("`{}`", c.value)),
+            Node::InlineMath(m) => out.push_str(&m.value),
+            Node::Break(_) => out.push(HARD_BREAK),
+            Node::Html(_) => (),
+
+            Node::Emphasis(e) => {
+                out.push('_');
+                e.children.iter().for_each(|c| self.inline(c, out));
+                out.push('_');
+            }
+
+            Node::Strong(s) => {
+                out.push('*');
+                s.children.iter().for_each(|c| self.inline(c, out));
+                out.push('*');
+            }
+
+            Node::Link(l) => self.link(&l.children, Some(&l.url), out),
+
+            Node::LinkReference(l) => {
+                self.link(&l.children, self.definitions.get(&l.identifier), out)
+            }
+
+            Node::Image(i) => self.image(&i.alt, Some(&i.url), out),
+
+            Node::ImageReference(i) => self.image(&i.alt, self.definitions.get(&i.identifier), out),
+
+            Node::FootnoteReference(f) => out.push_str(&format!(
+                "{REFERENCE_START}f{}{REFERENCE_END}",
+                f.identifier
+            )),
+
+            other => children(other).iter().for_each(|c| self.inline(c, out)),
+        }
+    }
+
+    fn link(&self, label: &[Node], url: Option<&String>, out: &mut String) {
+        let mut text = String::new();
+        label.iter().for_each(|c| self.inline(c, &mut text));
+
+        out.push_str(&text);
+
+        match url {
+            // Autolinks already show their url
+            Some(url) if url.trim_start_matches("mailto:") == text => (),
+            Some(url) => out.push_str(&format!("{REFERENCE_START}l{url}{REFERENCE_END}")),
+            None => (),
+        }
+    }
+
+    fn image(&self, alt: &str, url: Option<&String>, out: &mut String) {
+        out.push_str(&format!("[image: {alt}]"));
+
+        if let Some(url) = url {
+            out.push_str(&format!("{REFERENCE_START}l{url}{REFERENCE_END}"));
+        }
+    }
+
+    /// Number the references in flattened text, in reading order
+    fn number_references(&mut self, text: &str) -> String {
+        let references = regex::RegexBuilder::new(&format!(
+            r"\s*{SIDENOTE_PATTERN}|{REFERENCE_START}(?<kind>[lf])(?<id>[^{REFERENCE_END}]*){REFERENCE_END}"
+        ))
+        .dot_matches_new_line(true)
+        .build()
+        .unwrap();
+
+        references
+            .replace_all(text, |caps: &regex::Captures| {
+                let n = match (caps.name("kind").map(|k| k.as_str()), caps.name("id")) {
+                    (Some("l"), Some(url)) => {
+                        let url = match (&self.base_url, url.as_str()) {
+                            (Some(base), u) if u.starts_with('/') && !u.starts_with("//") => {
+                                format!("{}{u}", base.trim_end_matches('/'))
+                            }
+                            (_, u) => u.to_string(),
+                        };
+                        self.reference_number(format!("l{url}"), url)
+                    }
+                    (Some(_), Some(id)) => {
+                        self.reference_number(format!("f{}", id.as_str()), String::new())
+                    }
+                    _ => {
+                        // Numbered before its contents, so links inside a note come after it
+                        self.references.push(String::new());
+                        let n = self.references.len();
+                        let note = self.number_references(&caps["text"]);
+                        self.references[n - 1] = collapse_whitespace(&note);
+                        n
+                    }
+                };
+
+                format!("[{n}]")
+            })
+            .to_string()
+    }
+
+    /// Inline content as text to wrap, keeping hard line breaks
+    fn inline_text(&mut self, nodes: &[Node]) -> Vec<String> {
+        let mut text = String::new();
+        nodes.iter().for_each(|c| self.inline(c, &mut text));
+
+        self.number_references(&text)
+            .split(HARD_BREAK)
+            .map(collapse_whitespace)
+            .collect()
+    }
+
+    fn wrap_inline(&mut self, nodes: &[Node], width: usize) -> Vec<String> {
+        self.inline_text(nodes)
+            .iter()
+            .flat_map(|line| wrap(line, width, "", ""))
+            .collect()
+    }
+
+    fn list(&mut self, list: &mdast::List, width: usize) -> Vec<String> {
+        let mut lines = Vec::new();
+        let mut number = list.start.unwrap_or(1);
+
+        for item in &list.children {
+            let Node::ListItem(item) = item else { continue };
+
+            let checkbox = match item.checked {
+                Some(true) => "[x] ",
+                Some(false) => "[ ] ",
+                None => "",
+            };
+
+            let marker = match list.ordered {
+                true => format!("{number}. {checkbox}"),
+                false => format!("* {checkbox}"),
+            };
+            let indent = " ".repeat(marker.chars().count());
+
+            let blocks = item
+                .children
+                .iter()
+                .map(|c| self.block(c, width.saturating_sub(indent.len())))
+                .collect();
+
+            // Tight lists don't get blank lines between their items' paragraphs
+            let content = match list.spread {
+                true => join_blocks(blocks),
+                false => blocks.concat(),
+            };
+
+            for (i, line) in content.into_iter().enumerate() {
+                let prefix = if i == 0 { &marker } else { &indent };
+
+                match line.is_empty() {
+                    true => lines.push(String::new()),
+                    false => lines.push(format!("{prefix}{line}")),
+                }
+            }
+
+            number += 1;
+        }
+
+        lines
+    }
+
+    fn table(&mut self, table: &mdast::Table) -> Vec<String> {
+        let rows = table
+            .children
+            .iter()
+            .map(|row| {
+                children(row)
+                    .iter()
+                    .map(|cell| self.inline_text(children(cell)).join(" "))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+        let widths = (0..columns)
+            .map(|i| {
+                rows.iter()
+                    .filter_map(|r| r.get(i))
+                    .map(|c| c.chars().count())
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect::<Vec<_>>();
+
+        let mut lines = Vec::new();
+
+        for (i, row) in rows.iter().enumerate() {
+            let line = widths
+                .iter()
+                .enumerate()
+                .map(|(col, w)| {
+                    format!(
+                        "{:<w$}",
+                        row.get(col).map(String::as_str).unwrap_or_default()
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(" | ");
+
+            lines.push(line.trim_end().to_string());
+
+            if i == 0 {
+                lines.push(
+                    widths
+                        .iter()
+                        .map(|w| "-".repeat(*w))
+                        .collect::<Vec<_>>()
+                        .join("-+-"),
+                );
+            }
+        }
+
+        lines
+    }
+
+    fn block(&mut self, node: &Node, width: usize) -> Vec<String> {
+        match node {
+            Node::Root(_) | Node::ListItem(_) => join_blocks(
+                children(node)
+                    .iter()
+                    .map(|c| self.block(c, width))
+                    .collect(),
+            ),
+
+            Node::Heading(h) => {
+                let text = self.inline_text(&h.children).join(" ");
+
+                match h.depth {
+                    1 | 2 => {
+                        let underline = if h.depth == 1 { "=" } else { "-" };
+                        let mut lines = wrap(&text, width, "", "");
+                        let len = lines.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+                        lines.push(underline.repeat(len));
+                        lines
+                    }
+                    _ => wrap(&text, width, "", ""),
+                }
+            }
+
+            Node::Paragraph(p) => self.wrap_inline(&p.children, width),
+
+            Node::List(l) => self.list(l, width),
+
+            Node::BlockQuote(q) => {
+                let inner = join_blocks(
+                    q.children
+                        .iter()
+                        .map(|c| self.block(c, width.saturating_sub(2)))
+                        .collect(),
+                );
+
+                inner
+                    .iter()
+                    .map(|l| format!("> {l}").trim_end().to_string())
+                    .collect()
+            }
+
+            // Code is indented rather than wrapped, since its line breaks matter
+            Node::Code(mdast::Code { value, .. }) | Node::Math(mdast::Math { value, .. }) => value
+                .lines()
+                .map(|l| format!("    {l}").trim_end().to_string())
+                .collect(),
+
+            Node::Table(t) => self.table(t),
+
+            Node::ThematicBreak(_) => {
+                let rule = "* * *";
+                vec![format!(
+                    "{}{rule}",
+                    " ".repeat(width.saturating_sub(rule.len()) / 2)
+                )]
+            }
+
+            Node::FootnoteDefinition(f) => {
+                let n = self.reference_number(format!("f{}", f.identifier), String::new());
+
+                let text = f
+                    .children
+                    .iter()
+                    .map(|c| {
+                        let mut text = String::new();
+                        self.inline(c, &mut text);
+                        text
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" ");
+
+                let note = self.number_references(&text);
+                self.references[n - 1] = collapse_whitespace(&note);
+
+                Vec::new()
+            }
+
+            // Raw html, link definitions and front matter have nothing to show
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Render a markdown document as plain text wrapped at `width` columns.
+/// Links starting with `/` are listed relative to `base_url`, when it's given
+pub fn md_to_text(
+    md_content: &str,
+    width: usize,
+    base_url: Option<&str>,
+) -> anyhow::Result<String> {
+    let root = markdown::to_mdast(md_content, &markdown::ParseOptions::gfm())
+        .map_err(|e| format_err!("{}", e))?;
+
+    let mut renderer = Renderer {
+        base_url: base_url.map(String::from),
+        ..Renderer::default()
+    };
+    collect_definitions(&root, &mut renderer.definitions);
+
+    let mut lines = renderer.block(&root, width);
+
+    if !renderer.references.is_empty() {
+        lines.extend([String::new(), "References".into(), "----------".into()]);
+
+        for (i, reference) in renderer.references.iter().enumerate() {
+            let marker = format!("[{}] ", i + 1);
+            let indent = " ".repeat(marker.len());
+            lines.extend(wrap(reference, width, &marker, &indent));
+        }
+    }
+
+    Ok(lines.join("\n") + "\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_at_the_width() {
+        assert_eq!(
+            wrap("the quick brown fox jumps over the lazy dog", 15, "", ""),
+            ["the quick brown", "fox jumps over", "the lazy dog"]
+        );
+        assert_eq!(
+            wrap("one two three", 9, "[1] ", "    "),
+            ["[1] one", "    two", "    three"]
+        );
+    }
+
+    #[test]
+    fn long_words_get_their_own_line() {
+        assert_eq!(
+            wrap("a https://example.com/a/long/url b", 10, "", ""),
+            ["a", "https://example.com/a/long/url", "b"]
+        );
+        assert_eq!(wrap("", 10, "> ", ""), [">"]);
+    }
+
+    #[test]
+    fn paragraphs_are_wrapped_and_code_is_not() {
+        let text = md_to_text(
+            "# A title\n\nSome *words* that go on\nfor a while.  \nBroken.\n\n    let long_line = \"is left alone\";\n",
+            20,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(
+            text,
+            "A title\n\
+             =======\n\
+             \n\
+             Some _words_ that go\n\
+             on for a while.\n\
+             Broken.\n\
+             \n    \
+             let long_line = \"is left alone\";\n"
+        );
+    }
+
+    #[test]
+    fn list_items_wrap_under_their_marker() {
+        let text = md_to_text(
+            "- an item that wraps\n- [x] done\n\n> quoted text here\n",
+            14,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(
+            text,
+            "* an item that\n  \
+             wraps\n\
+             * [x] done\n\
+             \n\
+             > quoted text\n\
+             > here\n"
+        );
+    }
+
+    #[test]
+    fn links_and_notes_are_listed_as_references() {
+        let text = md_to_text(
+            "[Home](/), [elsewhere](https://example.org) and [home again](/) with a note[^n].\n\n[^n]: A [note link](/notes)\n",
+            80,
+            Some("https://example.com/"),
+        )
+        .unwrap();
+
+        assert_eq!(
+            text,
+            "Home[1], elsewhere[2] and home again[1] with a note[3].\n\
+             \n\
+             References\n\
+             ----------\n\
+             [1] https://example.com/\n\
+             [2] https://example.org\n\
+             [3] A note link[4]\n\
+             [4] https://example.com/notes\n"
+        );
+    }
+}
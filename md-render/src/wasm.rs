@@ -0,0 +1,261 @@
+//! The JavaScript side of `RenderBuilder` and the `Editor`, used by the editor in `web/`.
+//!
+//! Setters take the builder and hand it back, so calls chain like they do in Rust:
+//! `new RenderBuilder().md_content(md).sidenotes().render()`.
+//! Images and results cross as plain objects, typed by the definitions below.
+
+use crate::{
+    editor::Editor,
+    lint::{lint, LintOptions},
+    ImageAsset, RenderBuilder, Template, TrustLevel,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen(typescript_custom_section)]
+const TS_TYPES: &'static str = r#"
+export interface ImageVariant {
+  src: string;
+  width: number;
+}
+
+export interface ImageAsset {
+  src: string;
+  width: number;
+  height: number;
+  variants: ImageVariant[];
+}
+
+export interface TocEntry {
+  depth: number;
+  text: string;
+  id: string;
+}
+
+export interface RenderOutput {
+  html: string;
+  toc: TocEntry[];
+  word_count: number | null;
+  warnings: string[];
+}
+
+export interface FrontMatter {
+  title: string | null;
+  slug: string | null;
+  date: string | null;
+  tags: string[];
+}
+
+export type LintRule =
+  | "unclosed-sidenote"
+  | "duplicate-heading-id"
+  | "empty-link"
+  | "missing-alt-text"
+  | "heading-jump"
+  | "bare-url"
+  | "long-line"
+  | "front-matter";
+
+/** 1-indexed lines and columns, with the end exclusive */
+export interface Span {
+  start_line: number;
+  start_column: number;
+  end_line: number;
+  end_column: number;
+}
+
+export interface Diagnostic {
+  rule: LintRule;
+  severity: "error" | "warning";
+  span: Span;
+  message: string;
+}
+
+export interface PreviewBlock {
+  start_line: number;
+  end_line: number;
+  html: string;
+}
+
+export interface EditorUpdate {
+  front_matter: FrontMatter | null;
+  blocks: PreviewBlock[];
+  toc: TocEntry[];
+  word_count: number;
+  diagnostics: Diagnostic[];
+  rendered_blocks: number;
+}
+"#;
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(typescript_type = "ImageAsset[]")]
+    pub type ImageAssetArray;
+
+    #[wasm_bindgen(typescript_type = "RenderOutput")]
+    pub type JsRenderOutput;
+
+    #[wasm_bindgen(typescript_type = "EditorUpdate")]
+    pub type JsEditorUpdate;
+
+    #[wasm_bindgen(typescript_type = "Diagnostic[]")]
+    pub type DiagnosticArray;
+}
+
+fn js_error(e: impl std::fmt::Display) -> JsError {
+    JsError::new(&e.to_string())
+}
+
+/// Round trip through JSON, which is plenty fast for the sizes passed around here
+fn to_js<T: Serialize>(value: &T) -> Result<JsValue, JsError> {
+    let json = serde_json::to_string(value).map_err(js_error)?;
+    js_sys::JSON::parse(&json).map_err(|_| JsError::new("Could not convert to a JS value"))
+}
+
+fn from_js<T: DeserializeOwned>(value: &JsValue) -> Result<T, JsError> {
+    let json = js_sys::JSON::stringify(value)
+        .map_err(|_| JsError::new("Could not convert from a JS value"))?;
+    serde_json::from_str(&String::from(json)).map_err(js_error)
+}
+
+#[wasm_bindgen]
+impl RenderBuilder {
+    #[wasm_bindgen(constructor)]
+    pub fn js_new() -> Self {
+        Self::new()
+    }
+
+    #[wasm_bindgen(js_name = md_content)]
+    pub fn js_md_content(mut self, content: &str) -> Self {
+        self.md_content(content);
+        self
+    }
+
+    #[wasm_bindgen(js_name = html_content)]
+    pub fn js_html_content(mut self, content: &str) -> Self {
+        self.html_content(content);
+        self
+    }
+
+    #[wasm_bindgen(js_name = sidenotes)]
+    pub fn js_sidenotes(mut self) -> Self {
+        self.sidenotes();
+        self
+    }
+
+    #[wasm_bindgen(js_name = sanitize)]
+    pub fn js_sanitize(mut self) -> Self {
+        self.sanitize();
+        self
+    }
+
+    /// Untrusted unless set, like `RenderBuilder::trust`
+    #[wasm_bindgen(js_name = trust)]
+    pub fn js_trust(mut self, trust: TrustLevel) -> Self {
+        self.trust(trust);
+        self
+    }
+
+    #[wasm_bindgen(js_name = heading_ids)]
+    pub fn js_heading_ids(mut self) -> Self {
+        self.heading_ids();
+        self
+    }
+
+    #[wasm_bindgen(js_name = title)]
+    pub fn js_title(mut self, title: &str) -> Self {
+        self.title(title);
+        self
+    }
+
+    #[wasm_bindgen(js_name = template)]
+    pub fn js_template(mut self, template: Template) -> Self {
+        self.template(template);
+        self
+    }
+
+    #[wasm_bindgen(js_name = nonce)]
+    pub fn js_nonce(mut self, nonce: &str) -> Self {
+        self.nonce(nonce);
+        self
+    }
+
+    #[wasm_bindgen(js_name = quote)]
+    pub fn js_quote(mut self, quote: &str) -> Self {
+        self.quote(quote);
+        self
+    }
+
+    #[wasm_bindgen(js_name = images)]
+    pub fn js_images(mut self, images: ImageAssetArray) -> Result<RenderBuilder, JsError> {
+        let images: Vec<ImageAsset> = from_js(&images)?;
+        self.images(&images);
+        Ok(self)
+    }
+
+    /// Render without using up the builder, so it can be rendered again
+    #[wasm_bindgen(js_name = render)]
+    pub fn js_render(&self) -> Result<JsRenderOutput, JsError> {
+        let output = self.render_output().map_err(js_error)?;
+        Ok(to_js(&output)?.unchecked_into())
+    }
+
+    /// Just the html, for when nothing else about the render is needed
+    #[wasm_bindgen(js_name = render_html)]
+    pub fn js_render_html(&self) -> Result<String, JsError> {
+        self.render().map_err(js_error)
+    }
+}
+
+#[wasm_bindgen]
+impl Editor {
+    #[wasm_bindgen(constructor)]
+    pub fn js_new() -> Self {
+        Self::new()
+    }
+
+    #[wasm_bindgen(js_name = set_sidenotes)]
+    pub fn js_set_sidenotes(&mut self, sidenotes: bool) {
+        self.sidenotes(sidenotes);
+    }
+
+    #[wasm_bindgen(js_name = set_max_line_length)]
+    pub fn js_set_max_line_length(&mut self, max_line_length: Option<usize>) {
+        self.max_line_length(max_line_length);
+    }
+
+    #[wasm_bindgen(js_name = set_images)]
+    pub fn js_set_images(&mut self, images: ImageAssetArray) -> Result<(), JsError> {
+        let images: Vec<ImageAsset> = from_js(&images)?;
+        self.images(&images);
+        Ok(())
+    }
+
+    /// Render the whole source, reusing blocks that haven't changed
+    #[wasm_bindgen(js_name = update)]
+    pub fn js_update(&mut self, source: &str) -> Result<JsEditorUpdate, JsError> {
+        let update = self.update(source).map_err(js_error)?;
+        Ok(to_js(&update)?.unchecked_into())
+    }
+
+    #[wasm_bindgen(js_name = block_for_line)]
+    pub fn js_block_for_line(&self, line: usize) -> Option<usize> {
+        self.block_for_line(line)
+    }
+}
+
+/// The markdown of a post without its front matter, as it's stored on the server
+#[wasm_bindgen]
+pub fn strip_front_matter(source: &str) -> String {
+    crate::editor::split_front_matter(source).body.to_string()
+}
+
+/// Problems with a post's markdown, in the order they appear
+#[wasm_bindgen(js_name = lint)]
+pub fn js_lint(
+    md_content: &str,
+    max_line_length: Option<usize>,
+) -> Result<DiagnosticArray, JsError> {
+    let diagnostics = lint(md_content, &LintOptions { max_line_length }).map_err(js_error)?;
+    Ok(to_js(&diagnostics)?.unchecked_into())
+}
@@ -0,0 +1,71 @@
+// The post editor's side of the md-render wasm module, plus publishing through the admin API
+import { Editor, strip_front_matter } from "lib-rs";
+import type { Diagnostic, EditorUpdate, FrontMatter, PreviewBlock } from "lib-rs";
+
+export { Editor };
+export type { Diagnostic, EditorUpdate, FrontMatter, PreviewBlock };
+
+const TOKEN_KEY = "md-editor-token";
+const DRAFT_KEY = "md-editor-draft";
+
+export const NEW_POST = `---
+title: ""
+slug: ""
+date: ${new Date().toISOString().slice(0, 10)}
+tags: []
+---
+
+`;
+
+export const savedToken = (): string => localStorage.getItem(TOKEN_KEY) ?? "";
+export const saveToken = (token: string) => localStorage.setItem(TOKEN_KEY, token);
+
+export const savedDraft = (): string => localStorage.getItem(DRAFT_KEY) ?? NEW_POST;
+export const saveDraft = (source: string) => localStorage.setItem(DRAFT_KEY, source);
+
+/** 1-indexed line of a character offset */
+export function lineAt(source: string, offset: number): number {
+  return source.slice(0, offset).split("\n").length;
+}
+
+/** Character offset where a 1-indexed line starts */
+export function lineStart(source: string, line: number): number {
+  return source
+    .split("\n")
+    .slice(0, line - 1)
+    .reduce((offset, l) => offset + l.length + 1, 0);
+}
+
+export const canPublish = (update: EditorUpdate | null): boolean =>
+  !!update?.front_matter && !update.diagnostics.some((d) => d.severity === "error");
+
+/** Send the post to the server, which is proxied to the same origin in development */
+export async function publish(
+  source: string,
+  frontMatter: FrontMatter,
+  token: string,
+  overwrite: boolean,
+): Promise<void> {
+  const date = frontMatter.date ? Date.parse(frontMatter.date) : Date.now();
+
+  const response = await fetch("/admin/add", {
+    method: "POST",
+    headers: {
+      "Content-Type": "application/json",
+      Authorization: `Bearer ${token}`,
+    },
+    body: JSON.stringify({
+      title: frontMatter.title,
+      slug: frontMatter.slug,
+      timestamp: Math.floor(date / 1000),
+      tags: frontMatter.tags,
+      file_content: strip_front_matter(source),
+      overwrite,
+    }),
+  });
+
+  if (!response.ok) {
+    const message = response.status === 403 ? "The token was rejected" : await response.text();
+    throw new Error(message || `Publishing failed with ${response.status}`);
+  }
+}
@@ -0,0 +1,35 @@
+// Markdown rendering through the md-render crate. Types are generated into ../pkg
+// alongside the wasm by `npm run wasm`.
+import { RenderBuilder, Template } from "lib-rs";
+import type { ImageAsset, RenderOutput, TocEntry } from "lib-rs";
+
+export { RenderBuilder, Template };
+export type { ImageAsset, RenderOutput, TocEntry };
+
+export interface RenderOptions {
+  sidenotes?: boolean;
+  sanitize?: boolean;
+  headingIds?: boolean;
+  template?: Template;
+  /** Page title for templates. Defaults to the first top level heading */
+  title?: string;
+  images?: ImageAsset[];
+}
+
+/** Render markdown like the server does, throwing if it can't be parsed */
+export function renderMarkdown(md: string, opts: RenderOptions = {}): RenderOutput {
+  let builder = new RenderBuilder().md_content(md);
+
+  if (opts.sidenotes) builder = builder.sidenotes();
+  if (opts.sanitize) builder = builder.sanitize();
+  if (opts.headingIds) builder = builder.heading_ids();
+  if (opts.template !== undefined) builder = builder.template(opts.template);
+  if (opts.title !== undefined) builder = builder.title(opts.title);
+  if (opts.images?.length) builder = builder.images(opts.images);
+
+  try {
+    return builder.render();
+  } finally {
+    builder.free();
+  }
+}
@@ -4,6 +4,10 @@ import { svelte } from '@sveltejs/vite-plugin-svelte'
 import wasm from 'vite-plugin-wasm';
 import topLevelAwait from 'vite-plugin-top-level-await';
 
+// The blog server that posts are published to. The app expects to share its origin,
+// so in development requests for it are proxied
+const site = process.env.SITE_URL ?? 'http://127.0.0.1:8000'
+
 // https://vitejs.dev/config/
 export default defineConfig({
   plugins: [
@@ -11,4 +15,13 @@ export default defineConfig({
     wasm(),
     topLevelAwait()
   ],
+  server: {
+    // The preview uses the site's stylesheet from assets/
+    fs: { allow: ['..', '../../assets'] },
+    proxy: {
+      '/admin': site,
+      '/media': site,
+      '/static': site,
+    },
+  },
 })
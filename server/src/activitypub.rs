@@ -0,0 +1,1068 @@
+//! ActivityPub, so the blog can be followed from Mastodon and similar servers.
+//! The whole site is a single actor. Its outbox is the posts, and its inbox
+//! only cares about follows. See <https://www.w3.org/TR/activitypub/>
+//!
+//! Requests both ways are authenticated with HTTP signatures (rsa-sha256),
+//! as described in <https://docs.joinmastodon.org/spec/security/>
+
+use crate::{
+    blog::{
+        db::{self, Follower},
+        render::{self, read_file_contents},
+    },
+    common::{self, Post},
+    config,
+    fetch::{self, check_url, http_client},
+    metrics::TimedRender,
+    route::{PageQuery, SiteError},
+    webmention::post_url,
+};
+use anyhow::format_err;
+use axum::{
+    body::Bytes,
+    extract::{self, Query},
+    http::{header, HeaderMap, StatusCode, Uri},
+    response::{IntoResponse, Response},
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::{DateTime, SecondsFormat, Utc};
+use rsa::{
+    pkcs1::DecodeRsaPublicKey,
+    pkcs1v15::{Signature, SigningKey, VerifyingKey},
+    pkcs8::{DecodePrivateKey, DecodePublicKey, EncodePrivateKey, EncodePublicKey, LineEnding},
+    signature::{SignatureEncoding, Signer, Verifier},
+    RsaPrivateKey, RsaPublicKey,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use url::Url;
+
+use std::{collections::BTreeSet, sync::OnceLock, time::Duration};
+
+pub const ACTIVITY_JSON: &str = "application/activity+json";
+
+const ACTIVITYSTREAMS_CONTEXT: &str = "https://www.w3.org/ns/activitystreams";
+const SECURITY_CONTEXT: &str = "https://w3id.org/security/v1";
+const PUBLIC_COLLECTION: &str = "https://www.w3.org/ns/activitystreams#Public";
+
+const KEY_BITS: usize = 2048;
+
+/// How far a signed request's Date can be from now, either way
+const MAX_SIGNATURE_AGE_SECS: i64 = 12 * 60 * 60;
+
+/// Actor and key documents are small, anything bigger isn't one
+const MAX_OBJECT_BYTES: usize = 256 * 1024;
+
+/// Activities on each page of the outbox
+const OUTBOX_PAGE_SIZE: usize = 20;
+
+/// Waits before each delivery attempt. Deliveries aren't persisted, so these stay short
+const DELIVERY_DELAYS_SECS: [u64; 3] = [0, 60, 10 * 60];
+
+pub fn actor_url() -> String {
+    format!("{}/actor", config::settings().site_url)
+}
+
+fn key_id() -> String {
+    format!("{}#main-key", actor_url())
+}
+
+fn followers_url() -> String {
+    format!("{}/followers", actor_url())
+}
+
+/// The host part of a `acct:user@host` handle, including any port
+fn site_authority() -> anyhow::Result<String> {
+    let site = Url::parse(&config::settings().site_url)?;
+    Ok(site[url::Position::BeforeHost..url::Position::AfterPort].to_string())
+}
+
+pub struct ActorKey {
+    /// Where the public key can be found, which is how it's named in signatures
+    key_id: String,
+    private_key: RsaPrivateKey,
+    public_key_pem: String,
+}
+
+/// The actor's key pair, generated and stored in the db the first time it's needed.
+/// Called once at startup, so the slow generation doesn't happen during a request
+pub fn actor_key() -> anyhow::Result<&'static ActorKey> {
+    static KEY: OnceLock<ActorKey> = OnceLock::new();
+
+    if let Some(key) = KEY.get() {
+        return Ok(key);
+    }
+
+    let conn = db::DbConnection::new()?;
+
+    let key = match conn.actor_key()? {
+        Some((private_pem, public_pem)) => ActorKey {
+            key_id: key_id(),
+            private_key: RsaPrivateKey::from_pkcs8_pem(&private_pem)?,
+            public_key_pem: public_pem,
+        },
+        None => {
+            tracing::info!("Generating a key pair for the ActivityPub actor");
+
+            let private_key = RsaPrivateKey::new(&mut rand::thread_rng(), KEY_BITS)?;
+            let private_pem = private_key.to_pkcs8_pem(LineEnding::LF)?;
+            let public_key_pem = private_key
+                .to_public_key()
+                .to_public_key_pem(LineEnding::LF)?;
+
+            conn.set_actor_key(&private_pem, &public_key_pem)?;
+
+            ActorKey {
+                key_id: key_id(),
+                private_key,
+                public_key_pem,
+            }
+        }
+    };
+
+    Ok(KEY.get_or_init(|| key))
+}
+
+fn activity_json(value: Value) -> Response {
+    ([(header::CONTENT_TYPE, ACTIVITY_JSON)], value.to_string()).into_response()
+}
+
+fn http_date(dt: DateTime<Utc>) -> String {
+    dt.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+fn body_digest(body: &[u8]) -> String {
+    format!("SHA-256={}", BASE64.encode(Sha256::digest(body)))
+}
+
+/// The Signature header value for a request with the given (lowercase) headers
+fn sign(key: &ActorKey, method: &str, url: &Url, headers: &[(&str, &str)]) -> String {
+    let target = match url.query() {
+        Some(q) => format!("{} {}?{q}", method.to_lowercase(), url.path()),
+        None => format!("{} {}", method.to_lowercase(), url.path()),
+    };
+
+    let signing_string = std::iter::once(format!("(request-target): {target}"))
+        .chain(
+            headers
+                .iter()
+                .map(|(name, value)| format!("{name}: {value}")),
+        )
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let signature =
+        SigningKey::<Sha256>::new(key.private_key.clone()).sign(signing_string.as_bytes());
+
+    let header_names = std::iter::once("(request-target)")
+        .chain(headers.iter().map(|(name, _)| *name))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    format!(
+        r#"keyId="{}",algorithm="rsa-sha256",headers="{header_names}",signature="{}""#,
+        key.key_id,
+        BASE64.encode(signature.to_bytes())
+    )
+}
+
+/// The url a key id names the document of. Key ids are usually the actor's url
+/// with a fragment for the key
+fn without_fragment(url: &Url) -> Url {
+    let mut url = url.clone();
+    url.set_fragment(None);
+    url
+}
+
+/// Fetch an ActivityPub document, signed in case the server requires it.
+/// The document has to have `url` as its id, since anyone can serve a document
+/// claiming to be anything
+async fn fetch_object(
+    client: &reqwest::Client,
+    key: &ActorKey,
+    url: &Url,
+) -> anyhow::Result<Value> {
+    check_url(url)?;
+
+    let url = without_fragment(url);
+    let host = url[url::Position::BeforeHost..url::Position::AfterPort].to_string();
+    let date = http_date(Utc::now());
+    let signature = sign(key, "get", &url, &[("host", &host), ("date", &date)]);
+
+    let response = client
+        .get(url.clone())
+        .header("accept", ACTIVITY_JSON)
+        .header("date", &date)
+        .header("signature", signature)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    let object: Value =
+        serde_json::from_slice(&fetch::read_body(response, MAX_OBJECT_BYTES).await?)?;
+
+    match object["id"].as_str().map(Url::parse) {
+        Some(Ok(id)) if id == url => Ok(object),
+        _ => Err(format_err!("The document at {url} has a different id")),
+    }
+}
+
+/// POST an activity to an inbox
+async fn deliver(
+    client: &reqwest::Client,
+    key: &ActorKey,
+    activity: &Value,
+    inbox: &str,
+) -> anyhow::Result<reqwest::StatusCode> {
+    let url = Url::parse(inbox)?;
+    check_url(&url)?;
+
+    let body = serde_json::to_vec(activity)?;
+    let host = url[url::Position::BeforeHost..url::Position::AfterPort].to_string();
+    let date = http_date(Utc::now());
+    let digest = body_digest(&body);
+
+    let signature = sign(
+        key,
+        "post",
+        &url,
+        &[("host", &host), ("date", &date), ("digest", &digest)],
+    );
+
+    let response = client
+        .post(url)
+        .header("content-type", ACTIVITY_JSON)
+        .header("date", &date)
+        .header("digest", &digest)
+        .header("signature", signature)
+        .body(body)
+        .send()
+        .await?;
+
+    Ok(response.status())
+}
+
+/// Deliver an activity, retrying a few times if the inbox is down.
+/// Followers whose inbox says they're gone are removed
+async fn deliver_with_retries(activity: Value, inbox: String) {
+    for (attempt, delay) in DELIVERY_DELAYS_SECS.iter().enumerate() {
+        tokio::time::sleep(Duration::from_secs(*delay)).await;
+
+        let delivered = match actor_key() {
+            Ok(key) => deliver(http_client(), key, &activity, &inbox).await,
+            Err(e) => Err(e),
+        };
+
+        let error = match delivered {
+            Ok(s) if s.is_success() => return,
+            Ok(reqwest::StatusCode::GONE) => {
+                tracing::info!("{inbox} is gone, removing its followers");
+
+                let removed = db::DbConnection::new().and_then(|conn| {
+                    for f in conn.followers()?.iter().filter(|f| f.inbox == inbox) {
+                        conn.remove_follower(&f.actor)?;
+                    }
+                    Ok(())
+                });
+
+                if let Err(e) = removed {
+                    tracing::warn!("Could not remove followers for {inbox}: {e:?}");
+                }
+                return;
+            }
+            Ok(s) if s.is_client_error() => {
+                tracing::warn!("Delivery to {inbox} was refused with {s}");
+                return;
+            }
+            Ok(s) => format_err!("{s}"),
+            Err(e) => e,
+        };
+
+        tracing::warn!(
+            "Delivery attempt {} to {inbox} failed: {error:?}",
+            attempt + 1
+        );
+    }
+}
+
+/// Where to deliver to each follower, sending once per server where they share an inbox
+fn follower_inboxes(followers: &[Follower]) -> BTreeSet<String> {
+    followers
+        .iter()
+        .map(|f| f.shared_inbox.clone().unwrap_or_else(|| f.inbox.clone()))
+        .collect()
+}
+
+/// Relative links and images in rendered posts need to work from other servers
+fn absolute_links(html: &str) -> String {
+    let site = &config::settings().site_url;
+
+    html.replace(r#"src="/"#, &format!(r#"src="{site}/"#))
+        .replace(r#"href="/"#, &format!(r#"href="{site}/"#))
+}
+
+/// A post as an ActivityStreams object. Posts with a title heading are Articles,
+/// and those without (like notes from micropub) are Notes
+pub fn post_object(post: &Post) -> anyhow::Result<Value> {
+    let md = read_file_contents(post.md_path())?;
+    let html = render::post_builder().md_content(&md).timed_render()?;
+    let url = post_url(&post.slug);
+    let id = format!("{}/posts/{}", actor_url(), post.slug);
+
+    let published = DateTime::<Utc>::from_timestamp(post.timestamp as i64, 0)
+        .unwrap_or_default()
+        .to_rfc3339_opts(SecondsFormat::Secs, true);
+
+    let tags = post
+        .tags
+        .iter()
+        .map(|t| json!({ "type": "Hashtag", "name": format!("#{t}") }))
+        .collect::<Vec<_>>();
+
+    let mut object = json!({
+        "id": id,
+        "type": "Note",
+        "url": url,
+        "attributedTo": actor_url(),
+        "published": published,
+        "content": absolute_links(&html),
+        "to": [PUBLIC_COLLECTION],
+        "cc": [followers_url()],
+        "tag": tags,
+    });
+
+    if md.trim_start().starts_with("# ") {
+        object["type"] = json!("Article");
+        object["name"] = json!(post.title);
+    }
+
+    Ok(object)
+}
+
+fn create_activity(object: Value) -> Value {
+    json!({
+        "@context": ACTIVITYSTREAMS_CONTEXT,
+        "id": format!("{}#create", object["id"].as_str().unwrap_or_default()),
+        "type": "Create",
+        "actor": actor_url(),
+        "published": object["published"].clone(),
+        "to": object["to"].clone(),
+        "cc": object["cc"].clone(),
+        "object": object,
+    })
+}
+
+fn update_activity(object: Value) -> Value {
+    json!({
+        "@context": ACTIVITYSTREAMS_CONTEXT,
+        "id": format!(
+            "{}#update-{}",
+            object["id"].as_str().unwrap_or_default(),
+            common::now_timestamp()
+        ),
+        "type": "Update",
+        "actor": actor_url(),
+        "to": object["to"].clone(),
+        "cc": object["cc"].clone(),
+        "object": object,
+    })
+}
+
+/// Send a Create (or an Update, for edits) of a post to every follower in the background.
+/// Must be called from within the runtime
+pub fn publish(post: &Post, is_update: bool) -> anyhow::Result<()> {
+    let inboxes = follower_inboxes(&db::DbConnection::new()?.followers()?);
+
+    if inboxes.is_empty() {
+        return Ok(());
+    }
+
+    let object = post_object(post)?;
+    let activity = match is_update {
+        true => update_activity(object),
+        false => create_activity(object),
+    };
+
+    for inbox in inboxes {
+        tokio::spawn(deliver_with_retries(activity.clone(), inbox));
+    }
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct WebfingerQuery {
+    pub resource: String,
+}
+
+/// Lets `@blog@host` be looked up from other servers
+pub async fn webfinger(Query(query): Query<WebfingerQuery>) -> Result<Response, SiteError> {
+    let settings = config::settings();
+    let subject = format!("acct:{}@{}", settings.actor_username, site_authority()?);
+
+    if query.resource != subject && query.resource != actor_url() {
+        return Err(SiteError::from_status(StatusCode::NOT_FOUND));
+    }
+
+    let body = json!({
+        "subject": subject,
+        "aliases": [actor_url(), settings.site_url],
+        "links": [
+            { "rel": "self", "type": ACTIVITY_JSON, "href": actor_url() },
+            { "rel": "http://webfinger.net/rel/profile-page", "type": "text/html", "href": settings.site_url },
+        ],
+    });
+
+    Ok((
+        [(header::CONTENT_TYPE, "application/jrd+json")],
+        body.to_string(),
+    )
+        .into_response())
+}
+
+pub async fn actor() -> Result<Response, SiteError> {
+    let settings = config::settings();
+    let author = &settings.author;
+
+    let mut actor = json!({
+        "@context": [ACTIVITYSTREAMS_CONTEXT, SECURITY_CONTEXT],
+        "id": actor_url(),
+        "type": "Person",
+        "preferredUsername": settings.actor_username,
+        "name": author.name,
+        "url": settings.site_url,
+        "inbox": format!("{}/inbox", actor_url()),
+        "outbox": format!("{}/outbox", actor_url()),
+        "followers": followers_url(),
+        "publicKey": {
+            "id": key_id(),
+            "owner": actor_url(),
+            "publicKeyPem": actor_key()?.public_key_pem,
+        },
+    });
+
+    if let Some(note) = &author.note {
+        actor["summary"] = json!(note);
+    }
+
+    if let Some(photo) = &author.photo {
+        actor["icon"] = json!({ "type": "Image", "url": photo });
+    }
+
+    Ok(activity_json(actor))
+}
+
+/// Posts, newest first, as Create activities. The collection itself only links to its
+/// pages, so only the posts on the page asked for are rendered
+pub async fn outbox(Query(query): Query<PageQuery>) -> Result<Response, SiteError> {
+    let outbox = format!("{}/outbox", actor_url());
+    let page_url = |page: usize| format!("{outbox}?page={page}");
+
+    let posts = db::DbConnection::new()?.all_posts()?;
+
+    let Some(page) = query.page else {
+        let total_pages = posts.len().div_ceil(OUTBOX_PAGE_SIZE).max(1);
+
+        return Ok(activity_json(json!({
+            "@context": ACTIVITYSTREAMS_CONTEXT,
+            "id": outbox,
+            "type": "OrderedCollection",
+            "totalItems": posts.len(),
+            "first": page_url(1),
+            "last": page_url(total_pages),
+        })));
+    };
+
+    let (page_posts, total_pages) = render::paginate(&posts, page, OUTBOX_PAGE_SIZE)
+        .ok_or_else(|| SiteError::from_status(StatusCode::NOT_FOUND))?;
+
+    let items = page_posts
+        .iter()
+        .map(|p| post_object(p).map(create_activity))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut collection_page = json!({
+        "@context": ACTIVITYSTREAMS_CONTEXT,
+        "id": page_url(page),
+        "type": "OrderedCollectionPage",
+        "partOf": outbox,
+        "orderedItems": items,
+    });
+
+    if page > 1 {
+        collection_page["prev"] = json!(page_url(page - 1));
+    }
+    if page < total_pages {
+        collection_page["next"] = json!(page_url(page + 1));
+    }
+
+    Ok(activity_json(collection_page))
+}
+
+/// Only the number of followers is public, not who they are
+pub async fn followers() -> Result<Response, SiteError> {
+    let count = db::DbConnection::new()?.followers()?.len();
+
+    Ok(activity_json(json!({
+        "@context": ACTIVITYSTREAMS_CONTEXT,
+        "id": followers_url(),
+        "type": "OrderedCollection",
+        "totalItems": count,
+    })))
+}
+
+/// Pull the quoted key="value" parameters out of a Signature header
+fn parse_signature_header(value: &str) -> Option<(String, Vec<String>, Vec<u8>)> {
+    let mut key_id = None;
+    let mut headers = None;
+    let mut signature = None;
+
+    for param in value.split(',') {
+        let (name, value) = param.trim().split_once('=')?;
+        let value = value.trim_matches('"');
+
+        match name {
+            "keyId" => key_id = Some(value.to_string()),
+            "headers" => headers = Some(value.split(' ').map(String::from).collect()),
+            "signature" => signature = BASE64.decode(value).ok(),
+            _ => (),
+        }
+    }
+
+    // Without a headers parameter only the date is signed, which isn't enough
+    Some((key_id?, headers?, signature?))
+}
+
+fn public_key_from_pem(pem: &str) -> anyhow::Result<RsaPublicKey> {
+    RsaPublicKey::from_public_key_pem(pem)
+        .or_else(|_| RsaPublicKey::from_pkcs1_pem(pem))
+        .map_err(|e| format_err!("Invalid public key: {e}"))
+}
+
+fn same_origin(a: &str, b: &Url) -> bool {
+    Url::parse(a).is_ok_and(|a| a.origin() == b.origin())
+}
+
+/// Check a signed inbox request, giving the signer's actor document.
+/// Other documents are fetched with `client`, signed with `key`
+async fn verify_request(
+    client: &reqwest::Client,
+    key: &ActorKey,
+    method: &str,
+    uri: &Uri,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> anyhow::Result<Value> {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .ok_or_else(|| format_err!("Missing {name} header"))
+    };
+
+    let (key_id, signed_headers, signature) = parse_signature_header(header("signature")?)
+        .ok_or_else(|| format_err!("Invalid signature header"))?;
+
+    for required in ["(request-target)", "host", "date", "digest"] {
+        if !signed_headers.iter().any(|h| h == required) {
+            return Err(format_err!("{required} isn't signed"));
+        }
+    }
+
+    if header("digest")? != body_digest(body) {
+        return Err(format_err!("Digest doesn't match the body"));
+    }
+
+    let date = DateTime::parse_from_rfc2822(header("date")?)?;
+    if (Utc::now().timestamp() - date.timestamp()).abs() > MAX_SIGNATURE_AGE_SECS {
+        return Err(format_err!("Signature date is out of range"));
+    }
+
+    let target = match uri.query() {
+        Some(q) => format!("{} {}?{q}", method, uri.path()),
+        None => format!("{} {}", method, uri.path()),
+    };
+
+    let signing_string = signed_headers
+        .iter()
+        .map(|name| match name.as_str() {
+            "(request-target)" => Ok(format!("(request-target): {target}")),
+            name => header(name).map(|v| format!("{name}: {v}")),
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?
+        .join("\n");
+
+    let key_url = Url::parse(&key_id)?;
+    let document = fetch_object(client, key, &key_url).await?;
+
+    // The key id is usually the actor's url with a fragment, so this is the actor.
+    // Otherwise it's a key document, which names the actor as its owner
+    let (actor, public_key) = match document.get("inbox") {
+        Some(_) => (document.clone(), document["publicKey"].clone()),
+        None => {
+            let owner = document["owner"]
+                .as_str()
+                .ok_or_else(|| format_err!("Key {key_id} has no owner"))?;
+
+            if !same_origin(owner, &key_url) {
+                return Err(format_err!("Key {key_id} is owned by another server"));
+            }
+
+            (
+                fetch_object(client, key, &Url::parse(owner)?).await?,
+                document,
+            )
+        }
+    };
+
+    // Both ways round: the key is the actor's, and the actor says it's their key
+    let actor_id = actor["id"].as_str().unwrap_or_default();
+
+    if public_key["id"].as_str() != Some(&key_id)
+        || actor["publicKey"]["id"].as_str() != Some(&key_id)
+        || public_key["owner"].as_str() != Some(actor_id)
+    {
+        return Err(format_err!("Key {key_id} doesn't belong to {actor_id}"));
+    }
+
+    // Follows are delivered to the inbox, so it mustn't point at anyone else
+    let inboxes = [&actor["inbox"], &actor["endpoints"]["sharedInbox"]];
+
+    if !same_origin(actor_id, &key_url)
+        || inboxes
+            .iter()
+            .any(|i| !i.is_null() && !i.as_str().is_some_and(|i| same_origin(i, &key_url)))
+    {
+        return Err(format_err!("{actor_id} has an inbox on another server"));
+    }
+
+    let pem = public_key["publicKeyPem"]
+        .as_str()
+        .ok_or_else(|| format_err!("No public key found at {key_id}"))?;
+
+    VerifyingKey::<Sha256>::new(public_key_from_pem(pem)?).verify(
+        signing_string.as_bytes(),
+        &Signature::try_from(signature.as_slice())?,
+    )?;
+
+    Ok(actor)
+}
+
+/// The id of an activity's object, whether it's embedded or just referenced
+fn object_id(activity: &Value) -> Option<&str> {
+    match &activity["object"] {
+        Value::String(id) => Some(id),
+        object => object["id"].as_str(),
+    }
+}
+
+fn accept_activity(follow: &Value) -> Value {
+    json!({
+        "@context": ACTIVITYSTREAMS_CONTEXT,
+        "id": format!("{}#accepts/{}", actor_url(), common::content_hash(follow.to_string())),
+        "type": "Accept",
+        "actor": actor_url(),
+        "object": follow,
+    })
+}
+
+/// Inbox for the actor. Follows are accepted straight away, and undoing one unfollows.
+/// Anything else is acknowledged and ignored
+pub async fn inbox(uri: Uri, headers: HeaderMap, body: Bytes) -> Result<Response, SiteError> {
+    let activity: Value = serde_json::from_slice(&body)
+        .map_err(|e| SiteError::from(e).with_status(StatusCode::BAD_REQUEST))?;
+
+    let kind = activity["type"].as_str().unwrap_or_default();
+
+    // Account deletions get sent to every server, but the key they're signed with is already gone
+    if !matches!(kind, "Follow" | "Undo") {
+        return Ok(StatusCode::ACCEPTED.into_response());
+    }
+
+    let signer = verify_request(http_client(), actor_key()?, "post", &uri, &headers, &body)
+        .await
+        .map_err(|e| SiteError::from(e).with_status(StatusCode::UNAUTHORIZED))?;
+
+    let signer_id = signer["id"].as_str().unwrap_or_default();
+
+    if activity["actor"].as_str() != Some(signer_id) {
+        return Err(SiteError::from_status(StatusCode::FORBIDDEN));
+    }
+
+    let conn = db::DbConnection::new()?;
+
+    match kind {
+        "Follow" if object_id(&activity) == Some(actor_url().as_str()) => {
+            let inbox = signer["inbox"]
+                .as_str()
+                .ok_or_else(|| SiteError::from_status(StatusCode::BAD_REQUEST))?;
+
+            conn.add_follower(&Follower {
+                id: 0,
+                actor: signer_id.into(),
+                inbox: inbox.into(),
+                shared_inbox: signer["endpoints"]["sharedInbox"]
+                    .as_str()
+                    .map(String::from),
+                timestamp: common::now_timestamp(),
+            })?;
+
+            tracing::info!("{signer_id} followed");
+            tokio::spawn(deliver_with_retries(
+                accept_activity(&activity),
+                inbox.into(),
+            ));
+        }
+
+        "Undo" if activity["object"]["type"] == "Follow" || activity["object"].is_string() => {
+            conn.remove_follower(signer_id)?;
+            tracing::info!("{signer_id} unfollowed");
+        }
+
+        _ => (),
+    }
+
+    Ok(StatusCode::ACCEPTED.into_response())
+}
+
+/// A single post's object, which is where its id points
+pub async fn post_object_response(
+    extract::Path(slug): extract::Path<String>,
+) -> Result<Response, SiteError> {
+    let post = db::DbConnection::new()?
+        .find(&slug)?
+        .ok_or_else(|| SiteError::from_status(StatusCode::NOT_FOUND))?;
+
+    let mut object = post_object(&post)?;
+    object["@context"] = json!(ACTIVITYSTREAMS_CONTEXT);
+
+    Ok(activity_json(object))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, serve_with, test_client};
+    use axum::{
+        routing::{get, post, MethodRouter},
+        Router,
+    };
+    use tokio::sync::mpsc;
+
+    /// Keys are slow to make, so the tests share a couple of small ones
+    fn test_key(n: usize, key_id: &str) -> ActorKey {
+        static KEYS: OnceLock<Vec<RsaPrivateKey>> = OnceLock::new();
+
+        let private_key = KEYS.get_or_init(|| {
+            (0..2)
+                .map(|_| RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap())
+                .collect()
+        })[n]
+            .clone();
+
+        ActorKey {
+            key_id: key_id.into(),
+            public_key_pem: private_key
+                .to_public_key()
+                .to_public_key_pem(LineEnding::LF)
+                .unwrap(),
+            private_key,
+        }
+    }
+
+    fn document(doc: Value) -> MethodRouter {
+        get(move || {
+            let doc = doc.clone();
+            async move { activity_json(doc) }
+        })
+    }
+
+    fn actor_document(id: &Url, key_id: &str, key: &ActorKey) -> Value {
+        json!({
+            "id": id.as_str(),
+            "type": "Person",
+            "inbox": format!("{id}/inbox"),
+            "publicKey": {
+                "id": key_id,
+                "owner": id.as_str(),
+                "publicKeyPem": key.public_key_pem,
+            },
+        })
+    }
+
+    /// A request to our inbox, signed the way another server would
+    async fn check_signed(
+        key: &ActorKey,
+        signed_body: &[u8],
+        body: &[u8],
+    ) -> anyhow::Result<Value> {
+        let url = Url::parse("https://implicit.computer/actor/inbox").unwrap();
+        let date = http_date(Utc::now());
+        let digest = body_digest(signed_body);
+        let signature = sign(
+            key,
+            "post",
+            &url,
+            &[
+                ("host", "implicit.computer"),
+                ("date", &date),
+                ("digest", &digest),
+            ],
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.insert("host", "implicit.computer".parse().unwrap());
+        headers.insert("date", date.parse().unwrap());
+        headers.insert("digest", digest.parse().unwrap());
+        headers.insert("signature", signature.parse().unwrap());
+
+        let our_key = test_key(0, &key_id());
+        let uri = Uri::from_static("/actor/inbox");
+
+        verify_request(&test_client(), &our_key, "post", &uri, &headers, body).await
+    }
+
+    #[tokio::test]
+    async fn accepts_signed_requests() {
+        let base = serve_with(|base| {
+            let alice = base.join("/users/alice").unwrap();
+            let bob = base.join("/users/bob").unwrap();
+            let bob_key = base.join("/keys/bob").unwrap();
+            let key = test_key(0, "");
+
+            Router::new()
+                .route(
+                    "/users/alice",
+                    document(actor_document(&alice, &format!("{alice}#main-key"), &key)),
+                )
+                .route(
+                    "/users/bob",
+                    document(actor_document(&bob, bob_key.as_str(), &key)),
+                )
+                .route(
+                    "/keys/bob",
+                    document(json!({
+                        "id": bob_key.as_str(),
+                        "owner": bob.as_str(),
+                        "publicKeyPem": key.public_key_pem,
+                    })),
+                )
+        });
+
+        let body = br#"{"type":"Follow"}"#;
+
+        // A key that's a fragment of the actor
+        let alice = base.join("/users/alice").unwrap();
+        let key = test_key(0, &format!("{alice}#main-key"));
+        let signer = check_signed(&key, body, body).await.unwrap();
+        assert_eq!(signer["id"], alice.as_str());
+
+        // A key document naming its owner
+        let key = test_key(0, base.join("/keys/bob").unwrap().as_str());
+        let signer = check_signed(&key, body, body).await.unwrap();
+        assert_eq!(signer["id"], base.join("/users/bob").unwrap().as_str());
+    }
+
+    #[tokio::test]
+    async fn rejects_tampered_requests() {
+        let base = serve_with(|base| {
+            let alice = base.join("/users/alice").unwrap();
+            let key = test_key(0, "");
+
+            Router::new().route(
+                "/users/alice",
+                document(actor_document(&alice, &format!("{alice}#main-key"), &key)),
+            )
+        });
+
+        let key_id = format!("{}#main-key", base.join("/users/alice").unwrap());
+        let body = br#"{"type":"Follow"}"#;
+
+        let changed_body = check_signed(&test_key(0, &key_id), body, br#"{"type":"Undo"}"#).await;
+        assert!(changed_body.is_err());
+
+        let wrong_key = check_signed(&test_key(1, &key_id), body, body).await;
+        assert!(wrong_key.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_impersonation() {
+        let base = serve_with(|base| {
+            let alice = base.join("/users/alice").unwrap();
+            let mallory = base.join("/users/mallory").unwrap();
+            let alice_key = test_key(0, "");
+            let mallory_key = test_key(1, "");
+
+            let mut elsewhere =
+                actor_document(&mallory, &format!("{mallory}#main-key"), &mallory_key);
+            elsewhere["inbox"] = json!("http://inbox.example/mallory");
+
+            Router::new()
+                .route(
+                    "/users/alice",
+                    document(actor_document(
+                        &alice,
+                        &format!("{alice}#main-key"),
+                        &alice_key,
+                    )),
+                )
+                // Claims to be someone at another url
+                .route(
+                    "/users/fake",
+                    document(actor_document(
+                        &Url::parse("https://victim.example/users/v").unwrap(),
+                        base.join("/users/fake#main-key").unwrap().as_str(),
+                        &mallory_key,
+                    )),
+                )
+                // Claims to be alice's key, but she doesn't list it
+                .route(
+                    "/keys/grafted",
+                    document(json!({
+                        "id": base.join("/keys/grafted").unwrap().as_str(),
+                        "owner": alice.as_str(),
+                        "publicKeyPem": mallory_key.public_key_pem,
+                    })),
+                )
+                // Owned by an actor on another server
+                .route(
+                    "/keys/far",
+                    document(json!({
+                        "id": base.join("/keys/far").unwrap().as_str(),
+                        "owner": "https://victim.example/users/v",
+                        "publicKeyPem": mallory_key.public_key_pem,
+                    })),
+                )
+                // A real actor, but with someone else's inbox
+                .route("/users/mallory", document(elsewhere))
+        });
+
+        let body = br#"{"type":"Follow"}"#;
+
+        for key_id in [
+            "/users/fake#main-key",
+            "/keys/grafted",
+            "/keys/far",
+            "/users/mallory#main-key",
+        ] {
+            let key = test_key(1, base.join(key_id).unwrap().as_str());
+
+            assert!(
+                check_signed(&key, body, body).await.is_err(),
+                "{key_id} should be refused"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn delivers_signed_activities() {
+        let (received, mut inbox) = mpsc::unbounded_channel();
+
+        // A stand-in for a follower's server, checking deliveries like our inbox does
+        let base = serve_with(move |base| {
+            let actor = base.join("/actor").unwrap();
+            let key = test_key(0, "");
+
+            Router::new()
+                .route(
+                    "/actor",
+                    document(actor_document(&actor, &format!("{actor}#main-key"), &key)),
+                )
+                .route(
+                    "/inbox",
+                    post(move |uri: Uri, headers: HeaderMap, body: Bytes| {
+                        let received = received.clone();
+                        async move {
+                            let key = test_key(1, "");
+                            let signer =
+                                verify_request(&test_client(), &key, "post", &uri, &headers, &body)
+                                    .await;
+
+                            received.send((signer, body)).unwrap();
+                            StatusCode::ACCEPTED
+                        }
+                    }),
+                )
+        });
+
+        let actor = base.join("/actor").unwrap();
+        let key = test_key(0, &format!("{actor}#main-key"));
+        let activity = json!({ "type": "Create", "actor": actor.as_str() });
+
+        let status = deliver(
+            &test_client(),
+            &key,
+            &activity,
+            base.join("/inbox").unwrap().as_str(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, reqwest::StatusCode::ACCEPTED);
+
+        let (signer, body) = inbox.recv().await.unwrap();
+        assert_eq!(signer.unwrap()["id"], actor.as_str());
+        assert_eq!(serde_json::from_slice::<Value>(&body).unwrap(), activity);
+    }
+
+    #[tokio::test]
+    async fn pages_the_outbox() {
+        let _posts = testing::posts().await;
+
+        let posts = (0..OUTBOX_PAGE_SIZE + 5)
+            .map(|n| Post {
+                title: format!("Outbox {n}"),
+                timestamp: 1_700_000_000 + n,
+                slug: format!("outbox-{n}"),
+                tags: vec![],
+            })
+            .collect::<Vec<_>>();
+
+        let mut conn = db::DbConnection::new().unwrap();
+        for post in &posts {
+            std::fs::write(post.md_path(), format!("# {}\n\nHello", post.title)).unwrap();
+            conn.add_post_data(post).unwrap();
+        }
+        drop(conn);
+
+        let outbox_at = |page| async move {
+            outbox(Query(PageQuery { page }))
+                .await
+                .map_err(IntoResponse::into_response)
+        };
+
+        let collection = testing::json_body(outbox_at(None).await.unwrap()).await;
+        let url = format!("{}/outbox", actor_url());
+        assert_eq!(collection["type"], "OrderedCollection");
+        assert_eq!(collection["totalItems"], OUTBOX_PAGE_SIZE + 5);
+        assert_eq!(collection["first"], format!("{url}?page=1"));
+        assert_eq!(collection["last"], format!("{url}?page=2"));
+        assert!(collection.get("orderedItems").is_none());
+
+        let first = testing::json_body(outbox_at(Some(1)).await.unwrap()).await;
+        assert_eq!(first["type"], "OrderedCollectionPage");
+        assert_eq!(first["partOf"], url);
+        assert_eq!(first["next"], format!("{url}?page=2"));
+        assert!(first.get("prev").is_none());
+
+        let items = first["orderedItems"].as_array().unwrap();
+        assert_eq!(items.len(), OUTBOX_PAGE_SIZE);
+        let newest = format!("{}/posts/outbox-{}", actor_url(), OUTBOX_PAGE_SIZE + 4);
+        assert_eq!(items[0]["object"]["id"], newest);
+
+        let last = testing::json_body(outbox_at(Some(2)).await.unwrap()).await;
+        assert_eq!(last["orderedItems"].as_array().unwrap().len(), 5);
+        assert_eq!(last["prev"], format!("{url}?page=1"));
+        assert!(last.get("next").is_none());
+
+        let missing = outbox_at(Some(3)).await.unwrap_err();
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+
+        let conn = db::DbConnection::new().unwrap();
+        for post in &posts {
+            std::fs::remove_file(post.md_path()).unwrap();
+            conn.delete_post(&post.slug).unwrap();
+        }
+        conn.dump_json(common::POSTS_JSON_PATH).unwrap();
+    }
+}
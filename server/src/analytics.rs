@@ -0,0 +1,295 @@
+//! Page view counts, kept in the site's own database rather than sent to a third party.
+//!
+//! Nothing that identifies a reader is stored: no IP addresses, no full user agents and
+//! no cookies. Unique visitors are told apart by a hash of the IP and user agent salted
+//! with a random secret that changes every day and only lives in memory, so hashes from
+//! different days (or from before a restart) can't be linked or reversed. Bots, and
+//! readers who send `DNT: 1` or `Sec-GPC: 1`, aren't counted at all.
+
+use crate::{
+    blog::{
+        db::{self, PageView, ViewCount},
+        render,
+    },
+    common, config,
+    metrics::TimedRender,
+    ratelimit::client_ip,
+    redirects::normalize_path,
+    route::{require_author, SiteError},
+};
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Query},
+    http::{header, HeaderMap, Method, Request, StatusCode},
+    middleware::Next,
+    response::{Html, IntoResponse, Response},
+    Json,
+};
+use axum_auth::AuthBearer;
+use chrono::{Duration, Utc};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use url::Url;
+
+use tokio::sync::mpsc;
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::OnceLock,
+};
+
+/// User agents containing any of these are crawlers, link previewers or scripts
+const BOT_MARKERS: &[&str] = &[
+    "bot",
+    "crawl",
+    "spider",
+    "slurp",
+    "curl",
+    "wget",
+    "python",
+    "java/",
+    "go-http-client",
+    "headless",
+    "lighthouse",
+    "facebookexternalhit",
+    "embedly",
+    "preview",
+    "feed",
+];
+
+/// Paths that aren't pages readers visit
+const IGNORED_PREFIXES: &[&str] = &["/admin", "/api", "/metrics", "/micropub", "/actor"];
+
+/// Views waiting to be written, past which new ones are dropped
+const VIEW_QUEUE_LEN: usize = 1024;
+/// How long views wait after the first of a batch, so they're written together
+const VIEW_BATCH_DELAY: std::time::Duration = std::time::Duration::from_secs(5);
+
+pub const DEFAULT_DAYS: u32 = 30;
+pub const MAX_DAYS: u32 = 366;
+
+fn today() -> String {
+    Utc::now()
+        .with_timezone(&config::settings().timezone)
+        .format("%F")
+        .to_string()
+}
+
+/// The secret visitor hashes are salted with, replaced when the day changes
+fn daily_salt(day: &str) -> [u8; 32] {
+    static SALT: Mutex<Option<(String, [u8; 32])>> = parking_lot::const_mutex(None);
+
+    let mut salt = SALT.lock();
+
+    match &*salt {
+        Some((salt_day, secret)) if salt_day == day => *secret,
+        _ => {
+            let secret = rand::random();
+            *salt = Some((day.to_string(), secret));
+            secret
+        }
+    }
+}
+
+fn visitor_hash(day: &str, ip: IpAddr, user_agent: &str) -> String {
+    let digest = Sha256::new()
+        .chain_update(daily_salt(day))
+        .chain_update(ip.to_string())
+        .chain_update(user_agent)
+        .finalize();
+
+    hex::encode(&digest[..8])
+}
+
+fn is_bot(user_agent: &str) -> bool {
+    let user_agent = user_agent.to_ascii_lowercase();
+    user_agent.is_empty() || BOT_MARKERS.iter().any(|m| user_agent.contains(m))
+}
+
+/// A coarse kind of device, which is all that's kept of the user agent
+fn agent_class(user_agent: &str) -> &'static str {
+    let user_agent = user_agent.to_ascii_lowercase();
+
+    if user_agent.contains("ipad") || user_agent.contains("tablet") {
+        "tablet"
+    } else if user_agent.contains("mobi") || user_agent.contains("android") {
+        "mobile"
+    } else if ["windows", "macintosh", "x11", "linux", "cros"]
+        .iter()
+        .any(|os| user_agent.contains(os))
+    {
+        "desktop"
+    } else {
+        "other"
+    }
+}
+
+/// The referring site's host, unless it's this site
+fn referrer_host(headers: &HeaderMap) -> Option<String> {
+    let referrer = Url::parse(headers.get(header::REFERER)?.to_str().ok()?).ok()?;
+    let host = referrer.host_str()?.to_ascii_lowercase();
+
+    let site_host = Url::parse(&config::settings().site_url)
+        .ok()
+        .and_then(|u| u.host_str().map(str::to_ascii_lowercase));
+
+    (site_host.as_deref() != Some(host.as_str())).then_some(host)
+}
+
+fn opted_out(headers: &HeaderMap) -> bool {
+    ["dnt", "sec-gpc"]
+        .iter()
+        .any(|h| headers.get(*h).is_some_and(|v| v == "1"))
+}
+
+fn header_str(headers: &HeaderMap, name: header::HeaderName) -> &str {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+}
+
+/// The view a request would record, if it's one worth counting
+fn page_view(request: &Request<Body>) -> Option<PageView> {
+    let path = normalize_path(request.uri().path());
+    let headers = request.headers();
+    let user_agent = header_str(headers, header::USER_AGENT);
+
+    if request.method() != Method::GET
+        || IGNORED_PREFIXES.iter().any(|p| path.starts_with(p))
+        || path.starts_with(common::MEDIA_URL_PREFIX)
+        || opted_out(headers)
+        || is_bot(user_agent)
+    {
+        return None;
+    }
+
+    let ConnectInfo(addr) = request.extensions().get::<ConnectInfo<SocketAddr>>()?;
+    let day = today();
+
+    Some(PageView {
+        path: path.to_string(),
+        referrer_host: referrer_host(headers),
+        agent: agent_class(user_agent).into(),
+        visitor: visitor_hash(&day, client_ip(headers, *addr), user_agent),
+        day,
+        timestamp: common::now_timestamp(),
+    })
+}
+
+/// Write queued views in batches, each in one transaction
+async fn write_views(mut queue: mpsc::Receiver<PageView>) {
+    while let Some(first) = queue.recv().await {
+        tokio::time::sleep(VIEW_BATCH_DELAY).await;
+
+        let mut views = vec![first];
+        while let Ok(view) = queue.try_recv() {
+            views.push(view);
+        }
+
+        let written = tokio::task::spawn_blocking(move || {
+            db::DbConnection::without_posts_json()?.add_page_views(&views)?;
+            anyhow::Ok(views.len())
+        })
+        .await;
+
+        match written {
+            Ok(Ok(count)) => tracing::debug!(count, "Recorded page views"),
+            Ok(Err(e)) => tracing::warn!("Could not record page views: {e:?}"),
+            Err(e) => tracing::warn!("Recording page views panicked: {e:?}"),
+        }
+    }
+}
+
+fn view_queue() -> &'static mpsc::Sender<PageView> {
+    static QUEUE: OnceLock<mpsc::Sender<PageView>> = OnceLock::new();
+
+    QUEUE.get_or_init(|| {
+        let (sender, receiver) = mpsc::channel(VIEW_QUEUE_LEN);
+        tokio::spawn(write_views(receiver));
+        sender
+    })
+}
+
+/// Middleware recording views of html pages that were served successfully,
+/// for use with `middleware::from_fn`
+pub async fn record_views(request: Request<Body>, next: Next<Body>) -> Response {
+    if !config::settings().analytics {
+        return next.run(request).await;
+    }
+
+    let view = page_view(&request);
+    let response = next.run(request).await;
+
+    let is_page = header_str(response.headers(), header::CONTENT_TYPE).starts_with("text/html");
+
+    if let Some(view) = view.filter(|_| response.status() == StatusCode::OK && is_page) {
+        // Written later in a batch, so readers don't wait on the database
+        if let Err(e) = view_queue().try_send(view) {
+            tracing::debug!("Dropping a page view: {e}");
+        }
+    }
+
+    response
+}
+
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum AnalyticsFormat {
+    #[default]
+    Json,
+    Html,
+}
+
+#[derive(Deserialize)]
+pub struct AnalyticsQuery {
+    /// How many days back to count, including today
+    pub days: Option<u32>,
+    #[serde(default)]
+    pub format: AnalyticsFormat,
+}
+
+#[derive(Serialize)]
+pub struct AnalyticsReport {
+    pub since: String,
+    pub days: Vec<ViewCount>,
+    pub posts: Vec<ViewCount>,
+    pub referrers: Vec<ViewCount>,
+    pub agents: Vec<ViewCount>,
+}
+
+/// Views per day, post, referring site and kind of device, as JSON
+/// or as an html page with a table for each
+pub async fn get_analytics(
+    AuthBearer(token): AuthBearer,
+    Query(query): Query<AnalyticsQuery>,
+) -> Result<Response, SiteError> {
+    require_author(token)?;
+
+    let days = query.days.unwrap_or(DEFAULT_DAYS).clamp(1, MAX_DAYS);
+    let since = (Utc::now().with_timezone(&config::settings().timezone)
+        - Duration::days(days as i64 - 1))
+    .format("%F")
+    .to_string();
+
+    let conn = db::DbConnection::new()?;
+    let report = AnalyticsReport {
+        days: conn.views_by_day(&since)?,
+        posts: conn.views_by_post(&since)?,
+        referrers: conn.views_by_referrer(&since)?,
+        agents: conn.views_by_agent(&since)?,
+        since,
+    };
+
+    match query.format {
+        AnalyticsFormat::Json => Ok(Json(report).into_response()),
+        AnalyticsFormat::Html => {
+            let content = render::page_builder("Analytics")
+                .html_content(&render::analytics_display(&report)?)
+                .timed_render()?;
+
+            Ok(Html::from(content).into_response())
+        }
+    }
+}
@@ -0,0 +1,221 @@
+//! Versioned, read-only JSON API for posts, with an OpenAPI document generated from these types.
+//! Posts have the same shape as in posts.json
+
+use crate::{
+    blog::{db, render},
+    common::Post,
+    config,
+    metrics::TimedRender,
+    route::SiteError,
+    security::{self, SecurityHeaders},
+    view::localized_datetime,
+    webmention::post_url,
+};
+use anyhow::format_err;
+use axum::{
+    extract::{self, Json, Query},
+    http::{header, HeaderValue, Method, StatusCode},
+    middleware,
+    routing::get,
+    Router,
+};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+use std::sync::Arc;
+
+const MAX_PER_PAGE: usize = 100;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Blog API", description = "Read-only access to posts"),
+    paths(list_posts, get_post, openapi),
+    components(schemas(Post, PostList, PostDetail))
+)]
+struct ApiDoc;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PostsQuery {
+    /// 1-indexed page of results
+    pub page: Option<usize>,
+    /// Posts per page, at most 100. Defaults to the site's page size
+    pub per_page: Option<usize>,
+    /// Only posts with this tag
+    pub tag: Option<String>,
+    /// Only posts published on or after this date, as YYYY-MM-DD
+    pub since: Option<String>,
+    /// Only posts published on or before this date, as YYYY-MM-DD
+    pub until: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct PostList {
+    /// Newest first
+    pub posts: Vec<Post>,
+    pub page: usize,
+    pub per_page: usize,
+    /// Posts matching the filters, across all pages
+    pub total: usize,
+    pub total_pages: usize,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PostDetailQuery {
+    /// Include the post's markdown source
+    #[serde(default)]
+    pub markdown: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct PostDetail {
+    #[serde(flatten)]
+    pub post: Post,
+    /// Absolute url of the post's page
+    pub url: String,
+    /// The post's content rendered as html
+    pub html: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub markdown: Option<String>,
+}
+
+fn parse_date(date: &Option<String>, name: &str) -> Result<Option<NaiveDate>, SiteError> {
+    date.as_deref()
+        .map(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d"))
+        .transpose()
+        .map_err(|_| {
+            SiteError::from(format_err!("{name} must be a date like 2023-06-21"))
+                .with_status(StatusCode::BAD_REQUEST)
+        })
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/posts",
+    params(PostsQuery),
+    responses(
+        (status = 200, description = "A page of posts, newest first", body = PostList),
+        (status = 400, description = "A filter couldn't be parsed"),
+    ),
+    tag = "posts"
+)]
+pub async fn list_posts(Query(query): Query<PostsQuery>) -> Result<Json<PostList>, SiteError> {
+    let since = parse_date(&query.since, "since")?;
+    let until = parse_date(&query.until, "until")?;
+
+    let posts = db::DbConnection::new()?
+        .all_posts()?
+        .into_iter()
+        .filter(|p| query.tag.as_ref().is_none_or(|t| p.tags.contains(t)))
+        .filter(|p| {
+            let date = localized_datetime(p.timestamp).ok().map(|d| d.date_naive());
+            since.is_none_or(|s| date.is_some_and(|d| d >= s))
+                && until.is_none_or(|u| date.is_some_and(|d| d <= u))
+        })
+        .collect::<Vec<_>>();
+
+    let per_page = query
+        .per_page
+        .unwrap_or(config::settings().posts_page_size)
+        .clamp(1, MAX_PER_PAGE);
+    let page = query.page.unwrap_or(1).max(1);
+
+    // Pages past the end are empty rather than missing, so clients can stop when they see one
+    let (page_posts, total_pages) = match render::paginate(&posts, page, per_page) {
+        Some((p, total_pages)) => (p.to_vec(), total_pages),
+        None => (Vec::new(), posts.len().div_ceil(per_page).max(1)),
+    };
+
+    Ok(Json(PostList {
+        posts: page_posts,
+        page,
+        per_page,
+        total: posts.len(),
+        total_pages,
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/posts/{slug}",
+    params(("slug" = String, Path, description = "The post's slug"), PostDetailQuery),
+    responses(
+        (status = 200, description = "The post", body = PostDetail),
+        (status = 404, description = "No post has that slug"),
+    ),
+    tag = "posts"
+)]
+pub async fn get_post(
+    extract::Path(slug): extract::Path<String>,
+    Query(query): Query<PostDetailQuery>,
+) -> Result<Json<PostDetail>, SiteError> {
+    let conn = db::DbConnection::new()?;
+
+    let post = conn
+        .find(&slug)?
+        .ok_or_else(|| SiteError::from_status(StatusCode::NOT_FOUND))?;
+
+    let images = conn
+        .post_media(&post.slug)?
+        .iter()
+        .map(|m| m.as_image_asset())
+        .collect::<Vec<_>>();
+
+    let md_content = render::read_file_contents(post.md_path())?;
+    let html = render::post_builder()
+        .md_content(&md_content)
+        .images(&images)
+        .timed_render()?;
+
+    Ok(Json(PostDetail {
+        url: post_url(&post.slug),
+        post,
+        html,
+        markdown: query.markdown.then_some(md_content),
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/openapi.json",
+    responses((status = 200, description = "This document")),
+    tag = "meta"
+)]
+pub async fn openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+fn cors_layer() -> CorsLayer {
+    let origins = &config::settings().api_cors_origins;
+
+    let allow_origin = match origins.iter().any(|o| o == "*") {
+        true => AllowOrigin::any(),
+        false => AllowOrigin::list(
+            origins
+                .iter()
+                .filter_map(|o| HeaderValue::from_str(o).ok())
+                .collect::<Vec<_>>(),
+        ),
+    };
+
+    CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods([Method::GET])
+        .allow_headers([header::ACCEPT])
+}
+
+/// Routes for the API, to be nested under `/api/v1`
+pub fn router() -> Router {
+    Router::new()
+        .route("/posts", get(list_posts))
+        .route("/posts/:slug", get(get_post))
+        .route("/openapi.json", get(openapi))
+        .layer(cors_layer())
+        .layer(middleware::from_fn_with_state(
+            Arc::new(SecurityHeaders::api()),
+            security::set_headers,
+        ))
+}
@@ -1,22 +1,33 @@
 pub mod db {
 
     use crate::common;
+    use crate::metrics;
     use anyhow::format_err;
     use common::Post;
+    use md_render::{ImageAsset, ImageVariant};
+    use serde::{Deserialize, Serialize};
     use std::fs::File;
     use std::io::BufReader;
 
     use anyhow;
+    use rusqlite::OptionalExtension;
     use std::fs;
     use std::path::Path;
     use std::path::PathBuf;
+    use std::sync::atomic::{AtomicBool, Ordering};
 
     pub struct DbConnection {
         pub conn: rusqlite::Connection,
+        /// Whether posts.json was loaded, and so has to be written back out on drop
+        syncs_posts_json: bool,
     }
 
     impl Drop for DbConnection {
         fn drop(&mut self) {
+            if !self.syncs_posts_json {
+                return;
+            }
+
             match self.dump_json(common::POSTS_JSON_PATH) {
                 Ok(_) => (),
                 Err(e) => panic!("Cannot dump db content to json: {:?}", e),
@@ -25,9 +36,36 @@ pub mod db {
     }
 
     impl DbConnection {
+        /// Opens the database, making any tables that are missing
+        #[tracing::instrument(name = "db_connect", level = "debug", err)]
         pub fn new() -> anyhow::Result<Self> {
+            let conn = open_connection()?;
+            create_tables(&conn)?;
+            load_posts_json(&conn, common::POSTS_JSON_PATH)?;
+
             Ok(DbConnection {
-                conn: init_table_connection()?,
+                conn,
+                syncs_posts_json: true,
+            })
+        }
+
+        /// Opens the database without loading posts.json or writing it back out, for
+        /// frequent work that doesn't change posts, like recording views.
+        /// Missing tables are only made the first time
+        #[tracing::instrument(name = "db_connect_light", level = "debug", err)]
+        pub fn without_posts_json() -> anyhow::Result<Self> {
+            static TABLES_MADE: AtomicBool = AtomicBool::new(false);
+
+            let conn = open_connection()?;
+
+            if !TABLES_MADE.load(Ordering::Relaxed) {
+                create_tables(&conn)?;
+                TABLES_MADE.store(true, Ordering::Relaxed);
+            }
+
+            Ok(DbConnection {
+                conn,
+                syncs_posts_json: false,
             })
         }
 
@@ -39,23 +77,943 @@ pub mod db {
             get_all_post_metadata(&self.conn)
         }
 
+        pub fn post_count(&self) -> anyhow::Result<usize> {
+            Ok(self
+                .conn
+                .query_row("SELECT COUNT(*) FROM post", [], |row| row.get(0))?)
+        }
+
         pub fn dump_json(&self, json_path: impl AsRef<Path>) -> anyhow::Result<()> {
             dump_posts_json(&self.conn, json_path)
         }
 
         pub fn get(&self, slug: &str) -> anyhow::Result<Post> {
-            let stmt = format!("SELECT title, timestamp, slug FROM post WHERE slug='{slug}'");
+            self.find(slug)?
+                .ok_or_else(|| format_err!("No post with slug {slug:?}"))
+        }
+
+        pub fn find(&self, slug: &str) -> anyhow::Result<Option<Post>> {
+            self.conn
+                .query_row(
+                    "SELECT p.title, p.timestamp, p.slug, GROUP_CONCAT(t.tag) FROM post p
+                    LEFT JOIN post_tag t ON t.post_slug = p.slug WHERE p.slug = ?1 GROUP BY p.id",
+                    [slug],
+                    post_from_row,
+                )
+                .optional()
+                .map_err(anyhow::Error::from)
+        }
+
+        /// The ActivityPub actor's key pair, as private and public PEM
+        pub fn actor_key(&self) -> anyhow::Result<Option<(String, String)>> {
+            Ok(self
+                .conn
+                .query_row(
+                    "SELECT private_key_pem, public_key_pem FROM actor_key WHERE id = 1",
+                    [],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .optional()?)
+        }
+
+        pub fn set_actor_key(&self, private_key_pem: &str, public_key_pem: &str) -> anyhow::Result<()> {
+            self.conn.execute(
+                "INSERT OR REPLACE INTO actor_key (id, private_key_pem, public_key_pem) VALUES (1, ?1, ?2)",
+                (private_key_pem, public_key_pem),
+            )?;
+
+            Ok(())
+        }
+
+        /// Add a follower, updating the inboxes of one that already follows
+        pub fn add_follower(&self, follower: &Follower) -> anyhow::Result<()> {
+            self.conn.execute(
+                r#"INSERT INTO follower (actor, inbox, shared_inbox, timestamp) VALUES (?1, ?2, ?3, ?4)
+                ON CONFLICT(actor) DO UPDATE SET inbox = excluded.inbox, shared_inbox = excluded.shared_inbox"#,
+                (
+                    &follower.actor,
+                    &follower.inbox,
+                    &follower.shared_inbox,
+                    &follower.timestamp,
+                ),
+            )?;
+
+            Ok(())
+        }
+
+        pub fn remove_follower(&self, actor: &str) -> anyhow::Result<bool> {
+            let deleted = self
+                .conn
+                .execute("DELETE FROM follower WHERE actor = ?1", [actor])?;
+
+            Ok(deleted > 0)
+        }
+
+        pub fn followers(&self) -> anyhow::Result<Vec<Follower>> {
+            let mut stmt = self.conn.prepare(
+                "SELECT id, actor, inbox, shared_inbox, timestamp FROM follower ORDER BY timestamp ASC",
+            )?;
+
+            let followers = stmt.query_map([], |row| {
+                Ok(Follower {
+                    id: row.get(0)?,
+                    actor: row.get(1)?,
+                    inbox: row.get(2)?,
+                    shared_inbox: row.get(3)?,
+                    timestamp: row.get(4)?,
+                })
+            })?;
+
+            Ok(followers.filter_map(|f| f.ok()).collect())
+        }
+
+        /// Remove a post's metadata and tags. Its revisions, comments and media are kept
+        pub fn delete_post(&self, slug: &str) -> anyhow::Result<bool> {
+            self.conn
+                .execute("DELETE FROM post_tag WHERE post_slug = ?1", [slug])?;
+            let deleted = self.conn.execute("DELETE FROM post WHERE slug = ?1", [slug])?;
+
+            Ok(deleted > 0)
+        }
+
+        pub fn add_media(&self, media: &MediaRecord) -> anyhow::Result<()> {
+            let variant_widths = media
+                .variant_widths
+                .iter()
+                .map(|w| w.to_string())
+                .collect::<Vec<_>>()
+                .join(",");
+
+            self.conn.execute(
+                r#"INSERT OR REPLACE INTO media (post_slug, file_stem, extension, width, height, variant_widths)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)"#,
+                (
+                    &media.post_slug,
+                    &media.file_stem,
+                    &media.extension,
+                    &media.width,
+                    &media.height,
+                    &variant_widths,
+                ),
+            )?;
+
+            Ok(())
+        }
+
+        pub fn add_revision(
+            &self,
+            slug: &str,
+            content: &str,
+            timestamp: usize,
+            author: &str,
+        ) -> anyhow::Result<Revision> {
+            let content_hash = common::content_hash(content);
+
+            self.conn.execute(
+                r#"INSERT INTO revision (post_slug, content_hash, timestamp, author, content)
+                VALUES (?1, ?2, ?3, ?4, ?5)"#,
+                (slug, &content_hash, &timestamp, author, content),
+            )?;
+
+            Ok(Revision {
+                id: self.conn.last_insert_rowid(),
+                post_slug: slug.into(),
+                content_hash,
+                timestamp,
+                author: author.into(),
+                content: content.into(),
+            })
+        }
+
+        /// All revisions of a post, newest first
+        pub fn revisions(&self, slug: &str) -> anyhow::Result<Vec<Revision>> {
+            let mut stmt = self.conn.prepare(
+                "SELECT id, post_slug, content_hash, timestamp, author, content FROM revision
+                WHERE post_slug = ?1 ORDER BY id DESC",
+            )?;
+
+            let revisions = stmt.query_map([slug], Revision::from_row)?;
+
+            Ok(revisions.filter_map(|r| r.ok()).collect())
+        }
+
+        pub fn revision(&self, slug: &str, id: i64) -> anyhow::Result<Option<Revision>> {
+            self.conn
+                .query_row(
+                    "SELECT id, post_slug, content_hash, timestamp, author, content FROM revision
+                    WHERE post_slug = ?1 AND id = ?2",
+                    (slug, id),
+                    Revision::from_row,
+                )
+                .optional()
+                .map_err(anyhow::Error::from)
+        }
+
+        pub fn redirect_for(&self, path: &str) -> anyhow::Result<Option<Redirect>> {
+            self.conn
+                .query_row(
+                    "SELECT id, from_path, to_path, status_code FROM redirect WHERE from_path = ?1",
+                    [path],
+                    |row| {
+                        Ok(Redirect {
+                            id: row.get(0)?,
+                            from_path: row.get(1)?,
+                            to_path: row.get(2)?,
+                            status_code: row.get(3)?,
+                        })
+                    },
+                )
+                .optional()
+                .map_err(anyhow::Error::from)
+        }
+
+        pub fn redirects(&self) -> anyhow::Result<Vec<Redirect>> {
+            let mut stmt = self
+                .conn
+                .prepare("SELECT id, from_path, to_path, status_code FROM redirect ORDER BY from_path")?;
+
+            let redirects = stmt.query_map([], |row| {
+                Ok(Redirect {
+                    id: row.get(0)?,
+                    from_path: row.get(1)?,
+                    to_path: row.get(2)?,
+                    status_code: row.get(3)?,
+                })
+            })?;
+
+            Ok(redirects.filter_map(|r| r.ok()).collect())
+        }
+
+        pub fn set_redirect(&self, redirect: &Redirect) -> anyhow::Result<()> {
+            set_redirect(&self.conn, redirect)
+        }
+
+        pub fn delete_redirect(&self, id: i64) -> anyhow::Result<bool> {
+            let deleted = self
+                .conn
+                .execute("DELETE FROM redirect WHERE id = ?1", [id])?;
+
+            Ok(deleted > 0)
+        }
+
+        /// Record `views` in one transaction
+        pub fn add_page_views(&mut self, views: &[PageView]) -> anyhow::Result<()> {
+            let tx = self.conn.transaction()?;
+
+            {
+                let mut stmt = tx.prepare(
+                    r#"INSERT INTO page_view (path, referrer_host, agent, visitor, day, timestamp)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6)"#,
+                )?;
+
+                for view in views {
+                    stmt.execute((
+                        &view.path,
+                        &view.referrer_host,
+                        &view.agent,
+                        &view.visitor,
+                        &view.day,
+                        &view.timestamp,
+                    ))?;
+                }
+            }
+
+            tx.commit()?;
+
+            Ok(())
+        }
+
+        /// Views per day from `since_day` on, oldest first
+        pub fn views_by_day(&self, since_day: &str) -> anyhow::Result<Vec<ViewCount>> {
+            self.query_view_counts(
+                "SELECT day, COUNT(*), COUNT(DISTINCT visitor) FROM page_view
+                WHERE day >= ?1 GROUP BY day ORDER BY day",
+                since_day,
+            )
+        }
+
+        /// Views of each post from `since_day` on, keyed by slug, most viewed first
+        pub fn views_by_post(&self, since_day: &str) -> anyhow::Result<Vec<ViewCount>> {
+            self.query_view_counts(
+                "SELECT p.slug, COUNT(*), COUNT(DISTINCT v.day || v.visitor) FROM page_view v
+                JOIN post p ON v.path = '/blog/' || p.slug
+                WHERE v.day >= ?1 GROUP BY p.slug ORDER BY 2 DESC",
+                since_day,
+            )
+        }
+
+        /// Views from each referring site from `since_day` on, most first
+        pub fn views_by_referrer(&self, since_day: &str) -> anyhow::Result<Vec<ViewCount>> {
+            self.query_view_counts(
+                "SELECT referrer_host, COUNT(*), COUNT(DISTINCT day || visitor) FROM page_view
+                WHERE day >= ?1 AND referrer_host IS NOT NULL GROUP BY referrer_host ORDER BY 2 DESC",
+                since_day,
+            )
+        }
+
+        /// Views from each kind of device from `since_day` on, most first
+        pub fn views_by_agent(&self, since_day: &str) -> anyhow::Result<Vec<ViewCount>> {
+            self.query_view_counts(
+                "SELECT agent, COUNT(*), COUNT(DISTINCT day || visitor) FROM page_view
+                WHERE day >= ?1 GROUP BY agent ORDER BY 2 DESC",
+                since_day,
+            )
+        }
+
+        fn query_view_counts(&self, sql: &str, since_day: &str) -> anyhow::Result<Vec<ViewCount>> {
+            let mut stmt = self.conn.prepare(sql)?;
+
+            let counts = stmt.query_map([since_day], |row| {
+                Ok(ViewCount {
+                    key: row.get(0)?,
+                    views: row.get(1)?,
+                    visitors: row.get(2)?,
+                })
+            })?;
+
+            Ok(counts.filter_map(|c| c.ok()).collect())
+        }
+
+        pub fn quotes(&self) -> anyhow::Result<Vec<Quote>> {
+            self.query_quotes("SELECT id, text, weight, starts, ends FROM quote ORDER BY id", [])
+        }
+
+        fn query_quotes(
+            &self,
+            sql: &str,
+            params: impl rusqlite::Params,
+        ) -> anyhow::Result<Vec<Quote>> {
+            let mut stmt = self.conn.prepare(sql)?;
+
+            let quotes = stmt.query_map(params, |row| {
+                Ok(Quote {
+                    id: row.get(0)?,
+                    text: row.get(1)?,
+                    weight: row.get(2)?,
+                    starts: row.get(3)?,
+                    ends: row.get(4)?,
+                })
+            })?;
+
+            Ok(quotes.filter_map(|q| q.ok()).collect())
+        }
+
+        /// Add a quote, returning its id
+        pub fn add_quote(&self, quote: &Quote) -> anyhow::Result<i64> {
+            self.conn.execute(
+                "INSERT INTO quote (text, weight, starts, ends) VALUES (?1, ?2, ?3, ?4)",
+                (&quote.text, &quote.weight, &quote.starts, &quote.ends),
+            )?;
+
+            Ok(self.conn.last_insert_rowid())
+        }
+
+        pub fn delete_quote(&self, id: i64) -> anyhow::Result<bool> {
+            let deleted = self.conn.execute("DELETE FROM quote WHERE id = ?1", [id])?;
+
+            Ok(deleted > 0)
+        }
+
+        /// Move everything keyed by `old_slug` over to `new_slug`, and leave a permanent redirect behind.
+        /// Existing redirects pointing at the old url are updated so they don't chain.
+        pub fn rename_post(&mut self, old_slug: &str, new_slug: &str) -> anyhow::Result<()> {
+            let old_url = format!("/blog/{old_slug}");
+            let new_url = format!("/blog/{new_slug}");
+
+            let tx = self.conn.transaction()?;
+
+            tx.execute(
+                "UPDATE post SET slug = ?2 WHERE slug = ?1",
+                (old_slug, new_slug),
+            )?;
+            tx.execute(
+                "UPDATE post_tag SET post_slug = ?2 WHERE post_slug = ?1",
+                (old_slug, new_slug),
+            )?;
+            tx.execute(
+                "UPDATE media SET post_slug = ?2 WHERE post_slug = ?1",
+                (old_slug, new_slug),
+            )?;
+            tx.execute(
+                "UPDATE revision SET post_slug = ?2 WHERE post_slug = ?1",
+                (old_slug, new_slug),
+            )?;
+            tx.execute(
+                "UPDATE comment SET post_slug = ?2 WHERE post_slug = ?1",
+                (old_slug, new_slug),
+            )?;
+            tx.execute(
+                "UPDATE webmention SET post_slug = ?2 WHERE post_slug = ?1",
+                (old_slug, new_slug),
+            )?;
+            tx.execute(
+                "UPDATE outgoing_webmention SET post_slug = ?2 WHERE post_slug = ?1",
+                (old_slug, new_slug),
+            )?;
+
+            tx.execute("DELETE FROM redirect WHERE from_path = ?1", [&new_url])?;
+            tx.execute(
+                "UPDATE redirect SET to_path = ?2 WHERE to_path = ?1",
+                (&old_url, &new_url),
+            )?;
+            set_redirect(
+                &tx,
+                &Redirect {
+                    id: 0,
+                    from_path: old_url,
+                    to_path: new_url,
+                    status_code: 301,
+                },
+            )?;
+
+            tx.commit()?;
+
+            Ok(())
+        }
+
+        pub fn add_comment(&self, comment: &Comment) -> anyhow::Result<i64> {
+            self.conn.execute(
+                r#"INSERT INTO comment (post_slug, author_name, body_md, body_html, timestamp, status)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)"#,
+                (
+                    &comment.post_slug,
+                    &comment.author_name,
+                    &comment.body_md,
+                    &comment.body_html,
+                    &comment.timestamp,
+                    comment.status.as_str(),
+                ),
+            )?;
+
+            Ok(self.conn.last_insert_rowid())
+        }
+
+        /// Comments on a post with the given status, oldest first
+        pub fn post_comments(
+            &self,
+            slug: &str,
+            status: CommentStatus,
+        ) -> anyhow::Result<Vec<Comment>> {
+            let mut stmt = self.conn.prepare(
+                "SELECT id, post_slug, author_name, body_md, body_html, timestamp, status FROM comment
+                WHERE post_slug = ?1 AND status = ?2 ORDER BY timestamp ASC",
+            )?;
+
+            let comments = stmt.query_map((slug, status.as_str()), Comment::from_row)?;
+
+            Ok(comments.filter_map(|c| c.ok()).collect())
+        }
+
+        /// Comments across all posts with the given status, oldest first
+        pub fn comments_with_status(&self, status: CommentStatus) -> anyhow::Result<Vec<Comment>> {
+            let mut stmt = self.conn.prepare(
+                "SELECT id, post_slug, author_name, body_md, body_html, timestamp, status FROM comment
+                WHERE status = ?1 ORDER BY timestamp ASC",
+            )?;
+
+            let comments = stmt.query_map([status.as_str()], Comment::from_row)?;
+
+            Ok(comments.filter_map(|c| c.ok()).collect())
+        }
+
+        pub fn set_comment_status(&self, id: i64, status: CommentStatus) -> anyhow::Result<bool> {
+            let updated = self.conn.execute(
+                "UPDATE comment SET status = ?2 WHERE id = ?1",
+                (id, status.as_str()),
+            )?;
+
+            Ok(updated > 0)
+        }
+
+        pub fn delete_comment(&self, id: i64) -> anyhow::Result<bool> {
+            let deleted = self.conn.execute("DELETE FROM comment WHERE id = ?1", [id])?;
+
+            Ok(deleted > 0)
+        }
+
+        /// Add a mention, replacing any earlier one from the same source to the same target
+        pub fn set_webmention(&self, mention: &Webmention) -> anyhow::Result<()> {
+            self.conn.execute(
+                r#"INSERT INTO webmention
+                (source, target, post_slug, kind, author_name, author_url, author_photo, content, url, timestamp)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+                ON CONFLICT(source, target) DO UPDATE SET
+                post_slug = excluded.post_slug, kind = excluded.kind, author_name = excluded.author_name,
+                author_url = excluded.author_url, author_photo = excluded.author_photo,
+                content = excluded.content, url = excluded.url, timestamp = excluded.timestamp"#,
+                rusqlite::params![
+                    &mention.source,
+                    &mention.target,
+                    &mention.post_slug,
+                    &mention.kind,
+                    &mention.author_name,
+                    &mention.author_url,
+                    &mention.author_photo,
+                    &mention.content,
+                    &mention.url,
+                    &mention.timestamp,
+                ],
+            )?;
+
+            Ok(())
+        }
+
+        pub fn delete_webmention(&self, source: &str, target: &str) -> anyhow::Result<()> {
+            self.conn.execute(
+                "DELETE FROM webmention WHERE source = ?1 AND target = ?2",
+                (source, target),
+            )?;
+
+            Ok(())
+        }
+
+        /// Mentions of a post, oldest first
+        pub fn post_webmentions(&self, slug: &str) -> anyhow::Result<Vec<Webmention>> {
+            let mut stmt = self.conn.prepare(
+                "SELECT id, source, target, post_slug, kind, author_name, author_url, author_photo, content, url, timestamp
+                FROM webmention WHERE post_slug = ?1 ORDER BY timestamp ASC",
+            )?;
+
+            let mentions = stmt.query_map([slug], |row| {
+                Ok(Webmention {
+                    id: row.get(0)?,
+                    source: row.get(1)?,
+                    target: row.get(2)?,
+                    post_slug: row.get(3)?,
+                    kind: row.get(4)?,
+                    author_name: row.get(5)?,
+                    author_url: row.get(6)?,
+                    author_photo: row.get(7)?,
+                    content: row.get(8)?,
+                    url: row.get(9)?,
+                    timestamp: row.get(10)?,
+                })
+            })?;
+
+            Ok(mentions.filter_map(|m| m.ok()).collect())
+        }
+
+        /// Queue a mention of `target` to be sent, or sent again if it was before
+        pub fn queue_outgoing_webmention(
+            &self,
+            slug: &str,
+            source: &str,
+            target: &str,
+            now: usize,
+        ) -> anyhow::Result<()> {
+            self.conn.execute(
+                r#"INSERT INTO outgoing_webmention (post_slug, source, target, status, attempts, next_attempt)
+                VALUES (?1, ?2, ?3, ?4, 0, ?5)
+                ON CONFLICT(source, target) DO UPDATE SET
+                post_slug = excluded.post_slug, status = excluded.status, attempts = 0,
+                next_attempt = excluded.next_attempt, response_code = NULL, error = NULL"#,
+                (slug, source, target, DeliveryStatus::Pending.as_str(), now),
+            )?;
+
+            Ok(())
+        }
+
+        /// Pending deliveries whose next attempt is due by `now`
+        pub fn due_outgoing_webmentions(
+            &self,
+            now: usize,
+        ) -> anyhow::Result<Vec<OutgoingWebmention>> {
+            let mut stmt = self.conn.prepare(
+                "SELECT id, post_slug, source, target, endpoint, status, attempts, last_attempt, next_attempt, response_code, error
+                FROM outgoing_webmention WHERE status = ?1 AND next_attempt <= ?2 ORDER BY next_attempt ASC",
+            )?;
+
+            let deliveries = stmt.query_map(
+                (DeliveryStatus::Pending.as_str(), now),
+                OutgoingWebmention::from_row,
+            )?;
+
+            Ok(deliveries.filter_map(|d| d.ok()).collect())
+        }
+
+        /// When the soonest pending delivery is due, if there are any
+        pub fn next_outgoing_attempt(&self) -> anyhow::Result<Option<usize>> {
+            Ok(self.conn.query_row(
+                "SELECT MIN(next_attempt) FROM outgoing_webmention WHERE status = ?1",
+                [DeliveryStatus::Pending.as_str()],
+                |row| row.get(0),
+            )?)
+        }
+
+        /// Outgoing mentions, optionally only those from one post or with one status. Newest first
+        pub fn outgoing_webmentions(
+            &self,
+            slug: Option<&str>,
+            status: Option<DeliveryStatus>,
+        ) -> anyhow::Result<Vec<OutgoingWebmention>> {
+            let mut stmt = self.conn.prepare(
+                "SELECT id, post_slug, source, target, endpoint, status, attempts, last_attempt, next_attempt, response_code, error
+                FROM outgoing_webmention
+                WHERE (?1 IS NULL OR post_slug = ?1) AND (?2 IS NULL OR status = ?2)
+                ORDER BY id DESC",
+            )?;
+
+            let deliveries = stmt.query_map(
+                (slug, status.map(|s| s.as_str())),
+                OutgoingWebmention::from_row,
+            )?;
+
+            Ok(deliveries.filter_map(|d| d.ok()).collect())
+        }
+
+        pub fn update_outgoing_webmention(
+            &self,
+            delivery: &OutgoingWebmention,
+        ) -> anyhow::Result<()> {
+            self.conn.execute(
+                r#"UPDATE outgoing_webmention SET endpoint = ?2, status = ?3, attempts = ?4,
+                last_attempt = ?5, next_attempt = ?6, response_code = ?7, error = ?8
+                WHERE id = ?1"#,
+                rusqlite::params![
+                    &delivery.id,
+                    &delivery.endpoint,
+                    delivery.status.as_str(),
+                    &delivery.attempts,
+                    &delivery.last_attempt,
+                    &delivery.next_attempt,
+                    &delivery.response_code,
+                    &delivery.error,
+                ],
+            )?;
+
+            Ok(())
+        }
+
+        /// Put a delivery back in the queue with a fresh set of attempts
+        pub fn retry_outgoing_webmention(&self, id: i64, now: usize) -> anyhow::Result<bool> {
+            let updated = self.conn.execute(
+                r#"UPDATE outgoing_webmention SET status = ?2, attempts = 0, next_attempt = ?3,
+                response_code = NULL, error = NULL WHERE id = ?1"#,
+                (id, DeliveryStatus::Pending.as_str(), now),
+            )?;
+
+            Ok(updated > 0)
+        }
+
+        pub fn post_media(&self, slug: &str) -> anyhow::Result<Vec<MediaRecord>> {
+            let mut stmt = self.conn.prepare(
+                "SELECT post_slug, file_stem, extension, width, height, variant_widths FROM media WHERE post_slug = ?1",
+            )?;
+
+            let media_iter = stmt.query_map([slug], |row| {
+                let variant_widths: String = row.get(5)?;
+
+                Ok(MediaRecord {
+                    post_slug: row.get(0)?,
+                    file_stem: row.get(1)?,
+                    extension: row.get(2)?,
+                    width: row.get(3)?,
+                    height: row.get(4)?,
+                    variant_widths: variant_widths
+                        .split(',')
+                        .filter_map(|w| w.parse().ok())
+                        .collect(),
+                })
+            })?;
+
+            Ok(media_iter.filter_map(|m| m.ok()).collect())
+        }
+    }
+
+    /// Where requests for `from_path` should be sent instead
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct Redirect {
+        #[serde(default)]
+        pub id: i64,
+        pub from_path: String,
+        pub to_path: String,
+        pub status_code: u16,
+    }
+
+    /// One view of a page, recorded without anything that identifies the reader.
+    /// `visitor` is a hash salted with a secret that changes daily and is never stored,
+    /// so it only tells views on the same day apart
+    #[derive(Debug, Clone)]
+    pub struct PageView {
+        pub path: String,
+        /// Host of the referring page, if it was another site
+        pub referrer_host: Option<String>,
+        pub agent: String,
+        pub visitor: String,
+        /// The date in the site's timezone, as YYYY-MM-DD
+        pub day: String,
+        pub timestamp: usize,
+    }
+
+    /// Views of something over a period. Visitors are counted per day, since the
+    /// visitor hash can't link the same reader across days
+    #[derive(Debug, Clone, Serialize)]
+    pub struct ViewCount {
+        pub key: String,
+        pub views: u64,
+        pub visitors: u64,
+    }
+
+    fn default_quote_weight() -> u32 {
+        1
+    }
+
+    /// A line for the page header. Picked with a chance proportional to its weight,
+    /// and only between `starts` and `ends` when they're set
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct Quote {
+        #[serde(default)]
+        pub id: i64,
+        pub text: String,
+        #[serde(default = "default_quote_weight")]
+        pub weight: u32,
+        /// Timestamp the quote can be picked from
+        pub starts: Option<usize>,
+        /// Timestamp the quote stops being picked at
+        pub ends: Option<usize>,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    pub enum CommentStatus {
+        Pending,
+        Approved,
+        Rejected,
+    }
+
+    impl CommentStatus {
+        pub fn as_str(&self) -> &'static str {
+            match self {
+                CommentStatus::Pending => "pending",
+                CommentStatus::Approved => "approved",
+                CommentStatus::Rejected => "rejected",
+            }
+        }
+
+        fn from_db(s: &str) -> Self {
+            match s {
+                "approved" => CommentStatus::Approved,
+                "rejected" => CommentStatus::Rejected,
+                _ => CommentStatus::Pending,
+            }
+        }
+    }
+
+    /// A reader comment on a post. The html is rendered and sanitized once, at submission
+    #[derive(Debug, Clone, Serialize)]
+    pub struct Comment {
+        pub id: i64,
+        pub post_slug: String,
+        pub author_name: String,
+        pub body_md: String,
+        pub body_html: String,
+        pub timestamp: usize,
+        pub status: CommentStatus,
+    }
+
+    impl Comment {
+        fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+            let status: String = row.get(6)?;
+
+            Ok(Comment {
+                id: row.get(0)?,
+                post_slug: row.get(1)?,
+                author_name: row.get(2)?,
+                body_md: row.get(3)?,
+                body_html: row.get(4)?,
+                timestamp: row.get(5)?,
+                status: CommentStatus::from_db(&status),
+            })
+        }
+    }
+
+    /// A verified Webmention of one of our posts
+    #[derive(Debug, Clone, Serialize)]
+    pub struct Webmention {
+        pub id: i64,
+        pub source: String,
+        pub target: String,
+        pub post_slug: String,
+        /// One of like, repost, reply or mention
+        pub kind: String,
+        pub author_name: String,
+        pub author_url: Option<String>,
+        pub author_photo: Option<String>,
+        pub content: Option<String>,
+        pub url: String,
+        pub timestamp: usize,
+    }
+
+    /// A fediverse account following the blog's ActivityPub actor
+    #[derive(Debug, Clone, Serialize)]
+    pub struct Follower {
+        pub id: i64,
+        /// The follower's actor id
+        pub actor: String,
+        pub inbox: String,
+        /// Servers with many followers can take one delivery for all of them here
+        pub shared_inbox: Option<String>,
+        pub timestamp: usize,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum DeliveryStatus {
+        Pending,
+        Sent,
+        Failed,
+        /// The target doesn't accept webmentions, so there was nothing to send
+        NoEndpoint,
+    }
+
+    impl DeliveryStatus {
+        pub fn as_str(&self) -> &'static str {
+            match self {
+                DeliveryStatus::Pending => "pending",
+                DeliveryStatus::Sent => "sent",
+                DeliveryStatus::Failed => "failed",
+                DeliveryStatus::NoEndpoint => "no_endpoint",
+            }
+        }
+
+        fn from_db(s: &str) -> Self {
+            match s {
+                "sent" => DeliveryStatus::Sent,
+                "failed" => DeliveryStatus::Failed,
+                "no_endpoint" => DeliveryStatus::NoEndpoint,
+                _ => DeliveryStatus::Pending,
+            }
+        }
+    }
+
+    /// A Webmention we send to another site for a link in one of our posts
+    #[derive(Debug, Clone, Serialize)]
+    pub struct OutgoingWebmention {
+        pub id: i64,
+        pub post_slug: String,
+        pub source: String,
+        pub target: String,
+        /// The target's endpoint, once discovered
+        pub endpoint: Option<String>,
+        pub status: DeliveryStatus,
+        pub attempts: u32,
+        pub last_attempt: Option<usize>,
+        pub next_attempt: Option<usize>,
+        pub response_code: Option<u16>,
+        pub error: Option<String>,
+    }
+
+    impl OutgoingWebmention {
+        fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+            let status: String = row.get(5)?;
+
+            Ok(OutgoingWebmention {
+                id: row.get(0)?,
+                post_slug: row.get(1)?,
+                source: row.get(2)?,
+                target: row.get(3)?,
+                endpoint: row.get(4)?,
+                status: DeliveryStatus::from_db(&status),
+                attempts: row.get(6)?,
+                last_attempt: row.get(7)?,
+                next_attempt: row.get(8)?,
+                response_code: row.get(9)?,
+                error: row.get(10)?,
+            })
+        }
+    }
+
+    /// A saved version of a post's markdown
+    #[derive(Debug, Clone, Serialize)]
+    pub struct Revision {
+        pub id: i64,
+        pub post_slug: String,
+        pub content_hash: String,
+        pub timestamp: usize,
+        pub author: String,
+        #[serde(skip)]
+        pub content: String,
+    }
+
+    impl Revision {
+        fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+            Ok(Revision {
+                id: row.get(0)?,
+                post_slug: row.get(1)?,
+                content_hash: row.get(2)?,
+                timestamp: row.get(3)?,
+                author: row.get(4)?,
+                content: row.get(5)?,
+            })
+        }
+
+        pub fn short_hash(&self) -> &str {
+            &self.content_hash[..self.content_hash.len().min(8)]
+        }
+    }
+
+    /// An uploaded image as stored on disk under `MEDIA_PATH/<post_slug>/`.
+    /// Resized variants are saved alongside as `<file_stem>-<width>w.<extension>`
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct MediaRecord {
+        pub post_slug: String,
+        pub file_stem: String,
+        pub extension: String,
+        pub width: u32,
+        pub height: u32,
+        pub variant_widths: Vec<u32>,
+    }
+
+    impl MediaRecord {
+        pub fn file_name(&self) -> String {
+            format!("{}.{}", self.file_stem, self.extension)
+        }
+
+        pub fn variant_file_name(&self, width: u32) -> String {
+            format!("{}-{width}w.{}", self.file_stem, self.extension)
+        }
+
+        pub fn url(&self) -> String {
+            format!(
+                "{}/{}/{}",
+                common::MEDIA_URL_PREFIX,
+                self.post_slug,
+                self.file_name()
+            )
+        }
+
+        pub fn as_image_asset(&self) -> ImageAsset {
+            let variants = self
+                .variant_widths
+                .iter()
+                .map(|&width| ImageVariant {
+                    src: format!(
+                        "{}/{}/{}",
+                        common::MEDIA_URL_PREFIX,
+                        self.post_slug,
+                        self.variant_file_name(width)
+                    ),
+                    width,
+                })
+                .collect();
+
+            ImageAsset {
+                src: self.url(),
+                width: self.width,
+                height: self.height,
+                variants,
+            }
+        }
+    }
+
+    fn set_redirect(conn: &rusqlite::Connection, redirect: &Redirect) -> anyhow::Result<()> {
+        conn.execute(
+            r#"INSERT INTO redirect (from_path, to_path, status_code) VALUES (?1, ?2, ?3)
+            ON CONFLICT(from_path) DO UPDATE SET to_path = excluded.to_path, status_code = excluded.status_code"#,
+            (&redirect.from_path, &redirect.to_path, &redirect.status_code),
+        )?;
 
-            self.conn
-                .query_row(&stmt, [], |row| {
-                    Ok(Post {
-                        title: row.get(0)?,
-                        timestamp: row.get(1)?,
-                        slug: row.get(2)?,
-                    })
-                })
-                .map_err(anyhow::Error::from)
-        }
+        Ok(())
     }
 
     pub fn add_post_metadata_to_db(conn: &rusqlite::Connection, post: &Post) -> anyhow::Result<()> {
@@ -72,16 +1030,22 @@ pub mod db {
         }
 
         conn.execute(
-            r#"INSERT INTO post (title, timestamp, slug) VALUES (?1, ?2, ?3)"#,
+            r#"INSERT INTO post (title, timestamp, slug) VALUES (?1, ?2, ?3)
+            ON CONFLICT(slug) DO UPDATE SET title = excluded.title, timestamp = excluded.timestamp"#,
             (&post.title, &post.timestamp, &post.slug),
         )?;
 
-        Ok(())
+        set_post_tags(conn, post)
     }
 
-    fn init_table_connection() -> anyhow::Result<rusqlite::Connection> {
-        let conn = rusqlite::Connection::open(common::POSTS_DB_PATH)?;
+    fn open_connection() -> anyhow::Result<rusqlite::Connection> {
+        let mut conn = rusqlite::Connection::open(common::POSTS_DB_PATH)?;
+        conn.profile(Some(metrics::observe_query));
+
+        Ok(conn)
+    }
 
+    fn create_tables(conn: &rusqlite::Connection) -> anyhow::Result<()> {
         conn.execute(
             r#"
         CREATE TABLE IF NOT EXISTS post(
@@ -94,22 +1058,226 @@ pub mod db {
             (),
         )?;
 
-        load_posts_json(&conn, common::POSTS_JSON_PATH)?;
+        conn.execute(
+            r#"
+        CREATE TABLE IF NOT EXISTS post_tag(
+          id INTEGER PRIMARY KEY,
+          post_slug VARCHAR(255) NOT NULL,
+          tag VARCHAR(255) NOT NULL,
+          UNIQUE(post_slug, tag)
+        );
+        "#,
+            (),
+        )?;
+
+        conn.execute(
+            r#"
+        CREATE TABLE IF NOT EXISTS media(
+          id INTEGER PRIMARY KEY,
+          post_slug VARCHAR(255) NOT NULL,
+          file_stem VARCHAR(255) NOT NULL,
+          extension VARCHAR(16) NOT NULL,
+          width INTEGER NOT NULL,
+          height INTEGER NOT NULL,
+          variant_widths TEXT NOT NULL,
+          UNIQUE(post_slug, file_stem)
+        );
+        "#,
+            (),
+        )?;
 
-        Ok(conn)
+        conn.execute(
+            r#"
+        CREATE TABLE IF NOT EXISTS revision(
+          id INTEGER PRIMARY KEY,
+          post_slug VARCHAR(255) NOT NULL,
+          content_hash CHAR(64) NOT NULL,
+          timestamp INTEGER NOT NULL,
+          author VARCHAR(255) NOT NULL,
+          content TEXT NOT NULL
+        );
+        "#,
+            (),
+        )?;
+
+        conn.execute(
+            r#"
+        CREATE TABLE IF NOT EXISTS redirect(
+          id INTEGER PRIMARY KEY,
+          from_path VARCHAR(1024) UNIQUE NOT NULL,
+          to_path VARCHAR(1024) NOT NULL,
+          status_code INTEGER NOT NULL
+        );
+        "#,
+            (),
+        )?;
+
+        conn.execute(
+            r#"
+        CREATE TABLE IF NOT EXISTS comment(
+          id INTEGER PRIMARY KEY,
+          post_slug VARCHAR(255) NOT NULL,
+          author_name VARCHAR(255) NOT NULL,
+          body_md TEXT NOT NULL,
+          body_html TEXT NOT NULL,
+          timestamp INTEGER NOT NULL,
+          status VARCHAR(16) NOT NULL
+        );
+        "#,
+            (),
+        )?;
+
+        conn.execute(
+            r#"
+        CREATE TABLE IF NOT EXISTS webmention(
+          id INTEGER PRIMARY KEY,
+          source VARCHAR(2048) NOT NULL,
+          target VARCHAR(2048) NOT NULL,
+          post_slug VARCHAR(255) NOT NULL,
+          kind VARCHAR(16) NOT NULL,
+          author_name VARCHAR(255) NOT NULL,
+          author_url VARCHAR(2048),
+          author_photo VARCHAR(2048),
+          content TEXT,
+          url VARCHAR(2048) NOT NULL,
+          timestamp INTEGER NOT NULL,
+          UNIQUE(source, target)
+        );
+        "#,
+            (),
+        )?;
+
+        conn.execute(
+            r#"
+        CREATE TABLE IF NOT EXISTS outgoing_webmention(
+          id INTEGER PRIMARY KEY,
+          post_slug VARCHAR(255) NOT NULL,
+          source VARCHAR(2048) NOT NULL,
+          target VARCHAR(2048) NOT NULL,
+          endpoint VARCHAR(2048),
+          status VARCHAR(16) NOT NULL,
+          attempts INTEGER NOT NULL,
+          last_attempt INTEGER,
+          next_attempt INTEGER,
+          response_code INTEGER,
+          error TEXT,
+          UNIQUE(source, target)
+        );
+        "#,
+            (),
+        )?;
+
+        conn.execute(
+            r#"
+        CREATE TABLE IF NOT EXISTS actor_key(
+          id INTEGER PRIMARY KEY CHECK (id = 1),
+          private_key_pem TEXT NOT NULL,
+          public_key_pem TEXT NOT NULL
+        );
+        "#,
+            (),
+        )?;
+
+        conn.execute(
+            r#"
+        CREATE TABLE IF NOT EXISTS follower(
+          id INTEGER PRIMARY KEY,
+          actor VARCHAR(2048) UNIQUE NOT NULL,
+          inbox VARCHAR(2048) NOT NULL,
+          shared_inbox VARCHAR(2048),
+          timestamp INTEGER NOT NULL
+        );
+        "#,
+            (),
+        )?;
+
+        conn.execute(
+            r#"
+        CREATE TABLE IF NOT EXISTS page_view(
+          id INTEGER PRIMARY KEY,
+          path VARCHAR(1024) NOT NULL,
+          referrer_host VARCHAR(255),
+          agent VARCHAR(16) NOT NULL,
+          visitor CHAR(16) NOT NULL,
+          day CHAR(10) NOT NULL,
+          timestamp INTEGER NOT NULL
+        );
+        "#,
+            (),
+        )?;
+
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS page_view_day ON page_view (day);",
+            (),
+        )?;
+
+        // Seeded from quotes.json only when the table is first made,
+        // so quotes deleted later don't come back
+        let has_quotes: bool = conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'quote')",
+            [],
+            |row| row.get(0),
+        )?;
+
+        conn.execute(
+            r#"
+        CREATE TABLE IF NOT EXISTS quote(
+          id INTEGER PRIMARY KEY,
+          text TEXT NOT NULL,
+          weight INTEGER NOT NULL DEFAULT 1,
+          starts INTEGER,
+          ends INTEGER
+        );
+        "#,
+            (),
+        )?;
+
+        if !has_quotes {
+            load_quotes_json(conn, common::QUOTES_JSON_PATH)?;
+        }
+
+        Ok(())
+    }
+
+    /// Read a post from a row of title, timestamp, slug and comma separated tags
+    fn post_from_row(row: &rusqlite::Row) -> rusqlite::Result<Post> {
+        let tags: Option<String> = row.get(3)?;
+        let mut tags = tags
+            .unwrap_or_default()
+            .split(',')
+            .filter(|t| !t.is_empty())
+            .map(String::from)
+            .collect::<Vec<_>>();
+        tags.sort();
+
+        Ok(Post {
+            title: row.get(0)?,
+            timestamp: row.get(1)?,
+            slug: row.get(2)?,
+            tags,
+        })
+    }
+
+    fn set_post_tags(conn: &rusqlite::Connection, post: &Post) -> anyhow::Result<()> {
+        conn.execute("DELETE FROM post_tag WHERE post_slug = ?1", [&post.slug])?;
+
+        for tag in common::normalize_tags(&post.tags) {
+            conn.execute(
+                "INSERT INTO post_tag (post_slug, tag) VALUES (?1, ?2)",
+                (&post.slug, &tag),
+            )?;
+        }
+
+        Ok(())
     }
 
     fn get_all_post_metadata(conn: &rusqlite::Connection) -> anyhow::Result<Vec<Post>> {
-        let mut stmt =
-            conn.prepare("SELECT title, timestamp, slug FROM post ORDER BY timestamp DESC;")?;
-
-        let posts_iter = stmt.query_map([], |row| {
-            Ok(Post {
-                title: row.get(0)?,
-                timestamp: row.get(1)?,
-                slug: row.get(2)?,
-            })
-        })?;
+        let mut stmt = conn.prepare(
+            "SELECT p.title, p.timestamp, p.slug, GROUP_CONCAT(t.tag) FROM post p
+            LEFT JOIN post_tag t ON t.post_slug = p.slug GROUP BY p.id ORDER BY p.timestamp DESC;",
+        )?;
+
+        let posts_iter = stmt.query_map([], post_from_row)?;
 
         Ok(posts_iter.filter_map(|p| p.ok()).collect::<Vec<Post>>())
     }
@@ -124,7 +1292,7 @@ pub mod db {
             None => Err(format_err!("Invalid file type: must be json")),
             Some(o) => match o.to_str() {
                 None => Err(format_err!("Invalid file name")),
-                Some(s) if s == "json" => Ok(()),
+                Some("json") => Ok(()),
                 Some(x) => Err(format_err!("Invalid filetype {x:?}; must be json")),
             },
         }
@@ -143,6 +1311,27 @@ pub mod db {
         Ok(())
     }
 
+    #[derive(Deserialize)]
+    struct QuotesJson {
+        quotes: Vec<String>,
+    }
+
+    pub fn load_quotes_json(
+        conn: &rusqlite::Connection,
+        load_path: impl AsRef<Path>,
+    ) -> anyhow::Result<()> {
+        assure_is_json_path(&load_path)?;
+
+        let reader = BufReader::new(File::open(load_path)?);
+        let QuotesJson { quotes } = serde_json::from_reader(reader)?;
+
+        for text in quotes {
+            conn.execute("INSERT INTO quote (text) VALUES (?1)", [&text])?;
+        }
+
+        Ok(())
+    }
+
     pub fn load_posts_json(
         conn: &rusqlite::Connection,
         load_path: impl AsRef<Path>,
@@ -159,6 +1348,8 @@ pub mod db {
                 r#"INSERT OR REPLACE INTO post (title, timestamp, slug) VALUES (?1, ?2, ?3);"#,
                 (&post.title, &post.timestamp, &post.slug),
             )?;
+
+            set_post_tags(conn, post)?;
         }
 
         Ok(())
@@ -166,11 +1357,17 @@ pub mod db {
 }
 
 pub mod render {
+    use crate::analytics::AnalyticsReport;
+    use crate::blog::db::ViewCount;
     use crate::common;
-    use crate::common::Post;
+    use crate::config;
+    use crate::quotes;
+    use crate::security;
+    use crate::view::{format_timestamp, CommentView, PostView, RevisionView, WebmentionsView};
     use anyhow;
     use anyhow::format_err;
     use handlebars::Handlebars;
+    use serde::Serialize;
 
     use std::fs::File;
     use std::io::Read;
@@ -178,6 +1375,59 @@ pub mod render {
 
     pub use md_render::*;
 
+    /// The sanitizer's policy for this site, so links back to it aren't marked external
+    pub fn sanitize_policy() -> SanitizePolicy {
+        let mut policy = SanitizePolicy::new();
+
+        let site_url = &config::settings().site_url;
+        let host = site_url
+            .split("://")
+            .nth(1)
+            .and_then(|rest| rest.split(['/', ':']).next());
+
+        if let Some(host) = host {
+            policy.site_host(host);
+        }
+
+        policy
+    }
+
+    /// A builder for a full page, carrying the request's Content-Security-Policy nonce
+    /// and a quote for the header. The content is trusted, since it's the site's own
+    /// templates, with posts and anything from readers already rendered into them
+    pub fn page_builder(title: &str) -> RenderBuilder {
+        let mut builder = RenderBuilder::new();
+        builder.into_base_template(title).trust(TrustLevel::Trusted);
+
+        if let Some(nonce) = security::nonce() {
+            builder.nonce(&nonce);
+        }
+
+        // A page is still worth showing without its quote
+        match quotes::quote_for_page(title) {
+            Ok(Some(quote)) => {
+                builder.quote(&quote);
+            }
+            Ok(None) => (),
+            Err(e) => tracing::warn!("Could not pick a quote: {e:?}"),
+        }
+
+        builder
+    }
+
+    /// A builder for a post's markdown, trusted only if the site is configured to
+    pub fn post_builder() -> RenderBuilder {
+        let trust = if config::settings().trust_posts {
+            TrustLevel::Trusted
+        } else {
+            TrustLevel::Untrusted
+        };
+
+        let mut builder = RenderBuilder::new();
+        builder.sanitize_policy(sanitize_policy()).trust(trust);
+        builder
+    }
+
     pub fn read_file_contents(file_path: impl AsRef<Path>) -> anyhow::Result<String> {
         let file_path = PathBuf::from(file_path.as_ref());
 
@@ -211,18 +1461,342 @@ pub mod render {
         }
     }
 
-    pub fn post_index_display(posts: &Vec<Post>) -> anyhow::Result<String> {
+    #[tracing::instrument(level = "debug", err)]
+    fn load_templates(template_names: &[&str]) -> anyhow::Result<Handlebars<'static>> {
         let mut hb = Handlebars::new();
-        let template_path = get_template_path("posts_list")?;
 
-        hb.register_template_file("posts_list", template_path)?;
+        for name in template_names {
+            hb.register_template_file(name, get_template_path(name)?)?;
+        }
+
+        Ok(hb)
+    }
+
+    /// Links between the pages of a paginated listing.
+    /// The first page is served without a `page` query, so it keeps the bare url.
+    #[derive(Debug, Serialize)]
+    pub struct Pagination {
+        pub page: usize,
+        pub total_pages: usize,
+        pub prev_url: Option<String>,
+        pub next_url: Option<String>,
+    }
+
+    impl Pagination {
+        pub fn new(page: usize, total_pages: usize, base_url: &str) -> Self {
+            let page_url = |p: usize| match p {
+                1 => base_url.to_string(),
+                _ => format!("{base_url}?page={p}"),
+            };
+
+            Pagination {
+                page,
+                total_pages,
+                prev_url: (page > 1).then(|| page_url(page - 1)),
+                next_url: (page < total_pages).then(|| page_url(page + 1)),
+            }
+        }
+    }
+
+    /// Get the 1-indexed `page` of `items`, or None if it's past the end.
+    /// Page 1 always exists, even when empty
+    pub fn paginate<T>(items: &[T], page: usize, page_size: usize) -> Option<(&[T], usize)> {
+        let total_pages = items.len().div_ceil(page_size).max(1);
+
+        if page == 0 || page > total_pages {
+            return None;
+        }
+
+        let start = (page - 1) * page_size;
+        let end = (start + page_size).min(items.len());
+
+        Some((&items[start..end], total_pages))
+    }
+
+    pub fn post_index_display(posts: &[PostView], pagination: &Pagination) -> anyhow::Result<String> {
+        let hb = load_templates(&["posts_list", "pagination", "author_card"])?;
 
         let mut template_values = serde_json::Map::new();
         let list_items_json = handlebars::to_json(posts);
         template_values.insert(String::from("posts"), list_items_json);
+        template_values.insert(
+            String::from("author"),
+            handlebars::to_json(&config::settings().author),
+        );
+
+        if pagination.total_pages > 1 {
+            template_values.insert(
+                String::from("pagination"),
+                handlebars::to_json(pagination),
+            );
+        }
 
         let rendered_content = hb.render("posts_list", &template_values)?;
+        tracing::trace!(posts = posts.len(), "Rendered the posts list");
+
+        Ok(rendered_content)
+    }
+
+    #[derive(Debug, Serialize)]
+    pub struct ArchiveGroup {
+        pub label: String,
+        pub posts: Vec<PostView>,
+    }
+
+    /// Split posts (assumed already sorted by timestamp) into consecutive runs by month
+    pub fn group_by_month(posts: &[PostView]) -> Vec<ArchiveGroup> {
+        let mut groups: Vec<ArchiveGroup> = Vec::new();
+
+        for post in posts {
+            let label = format_timestamp(post.timestamp, "%B %Y");
+
+            match groups.last_mut() {
+                Some(g) if g.label == label => g.posts.push(post.clone()),
+                _ => groups.push(ArchiveGroup {
+                    label,
+                    posts: vec![post.clone()],
+                }),
+            }
+        }
+
+        groups
+    }
+
+    pub fn archive_display(heading: &str, groups: &[ArchiveGroup]) -> anyhow::Result<String> {
+        let hb = load_templates(&["archive", "author_card"])?;
+
+        let mut template_values = serde_json::Map::new();
+        template_values.insert(String::from("heading"), handlebars::to_json(heading));
+        template_values.insert(String::from("groups"), handlebars::to_json(groups));
+        template_values.insert(
+            String::from("author"),
+            handlebars::to_json(&config::settings().author),
+        );
+
+        Ok(hb.render("archive", &template_values)?)
+    }
+
+    pub fn changelog_display(revisions: &[RevisionView]) -> anyhow::Result<String> {
+        let hb = load_templates(&["changelog"])?;
 
-        Ok(dbg!(rendered_content))
+        let mut template_values = serde_json::Map::new();
+        template_values.insert(String::from("revisions"), handlebars::to_json(revisions));
+
+        Ok(hb.render("changelog", &template_values)?)
+    }
+
+    pub fn comments_display(
+        slug: &str,
+        comments: &[CommentView],
+        submitted: bool,
+    ) -> anyhow::Result<String> {
+        let hb = load_templates(&["comments"])?;
+
+        let mut template_values = serde_json::Map::new();
+        template_values.insert(String::from("slug"), handlebars::to_json(slug));
+        template_values.insert(String::from("comments"), handlebars::to_json(comments));
+        template_values.insert(String::from("submitted"), handlebars::to_json(submitted));
+
+        Ok(hb.render("comments", &template_values)?)
+    }
+
+    pub fn webmentions_display(mentions: &WebmentionsView) -> anyhow::Result<String> {
+        let hb = load_templates(&["webmentions"])?;
+
+        Ok(hb.render("webmentions", mentions)?)
+    }
+
+    #[derive(Debug, Serialize)]
+    pub struct DiffLine {
+        pub kind: &'static str,
+        pub text: String,
+    }
+
+    pub fn analytics_display(report: &AnalyticsReport) -> anyhow::Result<String> {
+        let hb = load_templates(&["analytics"])?;
+
+        let section = |heading: &str, label: &str, counts: &[ViewCount]| {
+            serde_json::json!({ "heading": heading, "label": label, "counts": counts })
+        };
+
+        let sections = [
+            section("By day", "Day", &report.days),
+            section("Posts", "Post", &report.posts),
+            section("Referrers", "Site", &report.referrers),
+            section("Devices", "Device", &report.agents),
+        ];
+
+        let mut template_values = serde_json::Map::new();
+        template_values.insert(String::from("since"), handlebars::to_json(&report.since));
+        template_values.insert(String::from("sections"), handlebars::to_json(sections));
+
+        Ok(hb.render("analytics", &template_values)?)
+    }
+
+    pub fn diff_display(heading: &str, lines: &[DiffLine]) -> anyhow::Result<String> {
+        let hb = load_templates(&["diff"])?;
+
+        let mut template_values = serde_json::Map::new();
+        template_values.insert(String::from("heading"), handlebars::to_json(heading));
+        template_values.insert(String::from("lines"), handlebars::to_json(lines));
+
+        Ok(hb.render("diff", &template_values)?)
+    }
+
+    /// A post's page content as an h-entry: the byline, the rendered markdown and the changelog
+    pub fn post_display(post: &PostView, content: &str, changelog: &str) -> anyhow::Result<String> {
+        let hb = load_templates(&["post", "post_header", "author_card"])?;
+
+        let mut template_values = serde_json::Map::new();
+        template_values.insert(String::from("post"), handlebars::to_json(post));
+        template_values.insert(
+            String::from("author"),
+            handlebars::to_json(&config::settings().author),
+        );
+        template_values.insert(String::from("content"), handlebars::to_json(content));
+        template_values.insert(String::from("changelog"), handlebars::to_json(changelog));
+
+        Ok(hb.render("post", &template_values)?)
+    }
+
+    /// A post as wrapped plain text, titled even if its markdown has no heading
+    pub fn post_plain_text(post: &common::Post, md_content: &str) -> anyhow::Result<String> {
+        let text = md_to_text(md_content, TEXT_WIDTH, Some(&config::settings().site_url))?;
+
+        if md_content.trim_start().starts_with("# ") {
+            return Ok(text);
+        }
+
+        let underline = "=".repeat(post.title.chars().count().min(TEXT_WIDTH));
+        Ok(format!("{}\n{underline}\n\n{text}", post.title))
+    }
+
+    /// Lint a post's markdown before it's saved, logging warnings.
+    /// Returns a description of the errors, if there are any that should stop it being saved
+    pub fn lint_errors(md_content: &str) -> anyhow::Result<Option<String>> {
+        let options = lint::LintOptions {
+            max_line_length: config::settings().lint_max_line_length,
+        };
+
+        let diagnostics = lint::lint(md_content, &options)?;
+
+        for warning in diagnostics
+            .iter()
+            .filter(|d| d.severity == lint::Severity::Warning)
+        {
+            tracing::info!("Lint warning in uploaded post, {warning}");
+        }
+
+        if !lint::has_errors(&diagnostics) {
+            return Ok(None);
+        }
+
+        let errors = diagnostics
+            .iter()
+            .filter(|d| d.severity == lint::Severity::Error)
+            .map(|d| d.to_string())
+            .collect::<Vec<_>>();
+
+        Ok(Some(format!(
+            "The post has problems to fix first: {}",
+            errors.join("; ")
+        )))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::mf2;
+        use url::Url;
+
+        fn sample_post() -> PostView {
+            PostView {
+                title: "Shave your Head!".into(),
+                slug: "bald".into(),
+                url: "/blog/bald".into(),
+                timestamp: 1687330800,
+                date: "2023-06-21".into(),
+                datetime: "2023-06-21T07:00:00Z".into(),
+                relative_date: "3 years ago".into(),
+                word_count: 1200,
+                reading_time_minutes: 6,
+                tags: vec!["grooming".into(), "life".into()],
+            }
+        }
+
+        fn site_url() -> Url {
+            Url::parse(&config::settings().site_url).unwrap()
+        }
+
+        fn assert_author(item: &mf2::Item) {
+            let author = config::settings().author.clone();
+            let card = item.first("author").and_then(|a| a.as_item()).unwrap();
+
+            assert!(card.has_type("h-card"));
+            assert_eq!(card.first_text("name"), Some(author.name));
+            assert_eq!(
+                card.first_text("url").map(|u| u.trim_end_matches('/').to_string()),
+                Some(author.url)
+            );
+        }
+
+        #[test]
+        fn post_page_is_an_h_entry() {
+            let html = post_display(&sample_post(), "<p>Just do it.</p>", "").unwrap();
+            let parsed = mf2::parse(&html, Some(&site_url()));
+
+            assert_eq!(parsed.items.len(), 1);
+            let entry = &parsed.items[0];
+
+            assert!(entry.has_type("h-entry"));
+            assert_eq!(entry.first_text("name").as_deref(), Some("Shave your Head!"));
+            assert_eq!(
+                entry.first_text("url"),
+                Some(site_url().join("/blog/bald").unwrap().to_string())
+            );
+            assert_eq!(
+                entry.first_text("published").as_deref(),
+                Some("2023-06-21T07:00:00Z")
+            );
+            assert_eq!(
+                entry.properties["category"],
+                vec![
+                    mf2::PropertyValue::Text("grooming".into()),
+                    mf2::PropertyValue::Text("life".into())
+                ]
+            );
+            assert!(matches!(
+                entry.first("content"),
+                Some(mf2::PropertyValue::Html { html, value })
+                    if html == "<p>Just do it.</p>" && value == "Just do it."
+            ));
+            assert_author(entry);
+        }
+
+        #[test]
+        fn index_is_an_h_feed_of_entries() {
+            let posts = [sample_post(), sample_post()];
+            let html = post_index_display(&posts, &Pagination::new(1, 1, "/blog")).unwrap();
+            let parsed = mf2::parse(&html, Some(&site_url()));
+
+            let feed = parsed.find("h-feed").unwrap();
+            assert_eq!(feed.first_text("name").as_deref(), Some("Posts List"));
+            assert_author(feed);
+
+            assert_eq!(feed.children.len(), 2);
+            for entry in &feed.children {
+                assert!(entry.has_type("h-entry"));
+                assert_eq!(entry.first_text("name").as_deref(), Some("Shave your Head!"));
+                assert_eq!(
+                    entry.first_text("url"),
+                    Some(site_url().join("/blog/bald").unwrap().to_string())
+                );
+                assert_eq!(
+                    entry.first_text("published").as_deref(),
+                    Some("2023-06-21T07:00:00Z")
+                );
+                assert_eq!(entry.properties["category"].len(), 2);
+            }
+        }
     }
 }
@@ -0,0 +1,133 @@
+use crate::{
+    blog::{
+        db::{self, Comment, CommentStatus},
+        render,
+    },
+    common,
+    metrics::TimedRender,
+    route::{require_author, SiteError},
+};
+use axum::{
+    extract::{self, Form, Json},
+    http::StatusCode,
+    response::Redirect,
+};
+use axum_auth::AuthBearer;
+use serde::Deserialize;
+
+pub const MAX_NAME_CHARS: usize = 100;
+pub const MAX_BODY_CHARS: usize = 5000;
+/// Body limit for a submitted form, with room for the body to be percent-encoded
+pub const MAX_REQUEST_BYTES: usize = 64 * 1024;
+
+#[derive(Deserialize)]
+pub struct CommentForm {
+    pub name: String,
+    pub body: String,
+    /// Honeypot: hidden from people by css, so only bots fill it in
+    #[serde(default)]
+    pub website: String,
+}
+
+/// Public comment submission. Comments are held for moderation,
+/// and the reader is sent back to the post with a notice saying so.
+/// How often a client can comment is left to the submission rate limit
+pub async fn submit_comment(
+    extract::Path(slug): extract::Path<String>,
+    Form(form): Form<CommentForm>,
+) -> Result<Redirect, SiteError> {
+    let conn = db::DbConnection::new()?;
+
+    if conn.find(&slug)?.is_none() {
+        return Err(SiteError::from_status(StatusCode::NOT_FOUND));
+    }
+
+    let back_to_post = Redirect::to(&format!("/blog/{slug}?comment=submitted#comments"));
+
+    // Pretend it went through, so bots don't learn to skip the field
+    if !form.website.is_empty() {
+        tracing::info!("Dropping comment on {slug} that filled in the honeypot");
+        return Ok(back_to_post);
+    }
+
+    let name = form.name.trim();
+    let body = form.body.trim();
+
+    if name.is_empty()
+        || body.is_empty()
+        || name.chars().count() > MAX_NAME_CHARS
+        || body.chars().count() > MAX_BODY_CHARS
+    {
+        return Err(SiteError::from_status(StatusCode::BAD_REQUEST));
+    }
+
+    let body_html = render::RenderBuilder::new()
+        .md_content(body)
+        .sanitize_policy(render::sanitize_policy())
+        .sanitize()
+        .timed_render()?;
+
+    conn.add_comment(&Comment {
+        id: 0,
+        post_slug: slug,
+        author_name: name.into(),
+        body_md: body.into(),
+        body_html,
+        timestamp: common::now_timestamp(),
+        status: CommentStatus::Pending,
+    })?;
+
+    Ok(back_to_post)
+}
+
+#[derive(Deserialize)]
+pub struct CommentsQuery {
+    pub status: Option<CommentStatus>,
+}
+
+/// The moderation queue by default, or comments with any other status
+pub async fn list_comments(
+    AuthBearer(token): AuthBearer,
+    extract::Query(query): extract::Query<CommentsQuery>,
+) -> Result<Json<Vec<Comment>>, SiteError> {
+    require_author(token)?;
+
+    let status = query.status.unwrap_or(CommentStatus::Pending);
+
+    Ok(Json(db::DbConnection::new()?.comments_with_status(status)?))
+}
+
+fn set_status(id: i64, status: CommentStatus) -> Result<StatusCode, SiteError> {
+    match db::DbConnection::new()?.set_comment_status(id, status)? {
+        true => Ok(StatusCode::OK),
+        false => Err(SiteError::from_status(StatusCode::NOT_FOUND)),
+    }
+}
+
+pub async fn approve_comment(
+    AuthBearer(token): AuthBearer,
+    extract::Path(id): extract::Path<i64>,
+) -> Result<StatusCode, SiteError> {
+    require_author(token)?;
+    set_status(id, CommentStatus::Approved)
+}
+
+pub async fn reject_comment(
+    AuthBearer(token): AuthBearer,
+    extract::Path(id): extract::Path<i64>,
+) -> Result<StatusCode, SiteError> {
+    require_author(token)?;
+    set_status(id, CommentStatus::Rejected)
+}
+
+pub async fn delete_comment(
+    AuthBearer(token): AuthBearer,
+    extract::Path(id): extract::Path<i64>,
+) -> Result<StatusCode, SiteError> {
+    require_author(token)?;
+
+    match db::DbConnection::new()?.delete_comment(id)? {
+        true => Ok(StatusCode::OK),
+        false => Err(SiteError::from_status(StatusCode::NOT_FOUND)),
+    }
+}
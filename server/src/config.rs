@@ -0,0 +1,246 @@
+use chrono_tz::Tz;
+use serde::Serialize;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+/// Who writes the site, shown as an h-card on posts and the index
+#[derive(Debug, Clone, Serialize)]
+pub struct Author {
+    pub name: String,
+    pub url: String,
+    pub photo: Option<String>,
+    /// A short bio
+    pub note: Option<String>,
+}
+
+/// How often the quote in the page header changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuoteRotation {
+    /// A new pick on every request
+    Request,
+    /// The same quote everywhere for a day, in the site's timezone
+    Day,
+    /// The same quote for a page for a day, but different pages can differ
+    Page,
+}
+
+impl FromStr for QuoteRotation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "request" => Ok(QuoteRotation::Request),
+            "day" => Ok(QuoteRotation::Day),
+            "page" => Ok(QuoteRotation::Page),
+            other => Err(format!("Unknown quote rotation {other:?}")),
+        }
+    }
+}
+
+/// Runtime settings for the site, read once from the environment.
+/// Anything not set falls back to the defaults below.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub host: String,
+    pub port: u16,
+    /// Public url the site is served from, without a trailing slash
+    pub site_url: String,
+    pub posts_page_size: usize,
+    /// strftime style format for dates shown to readers
+    pub date_format: String,
+    /// IANA timezone name dates are displayed in, e.g. "America/New_York"
+    pub timezone: Tz,
+    /// Extra named admin keys, so changes can be attributed to who made them
+    pub admin_keys: Vec<(String, String)>,
+    /// Show the list of revisions at the bottom of each post
+    pub show_changelog: bool,
+    /// Notify sites linked from a post when it's published
+    pub send_webmentions: bool,
+    pub author: Author,
+    /// IndieAuth token endpoint used to verify Micropub tokens that aren't admin keys
+    pub token_endpoint: Option<String>,
+    /// Username of the blog's ActivityPub actor, followed as @username@host
+    pub actor_username: String,
+    /// Port to serve Gemini on. Gemini is off unless this is set
+    pub gemini_port: Option<u16>,
+    /// Certificate and key for the Gemini listener, in PEM format.
+    /// A self-signed pair is generated at these paths if they don't exist
+    pub gemini_cert_path: String,
+    pub gemini_key_path: String,
+    /// Port to serve Gopher on. Gopher is off unless this is set
+    pub gopher_port: Option<u16>,
+    /// Origins allowed to call the JSON API from a browser. `*` allows any
+    pub api_cors_origins: Vec<String>,
+    /// Warn about uploaded posts with lines longer than this. Off unless set
+    pub lint_max_line_length: Option<usize>,
+    /// Render posts without sanitizing them, for sites whose posts use html the
+    /// sanitizer would strip. Comments are always sanitized
+    pub trust_posts: bool,
+    /// How long browsers should only use https for the site, in seconds.
+    /// Only sent when `site_url` is https, and 0 turns it off
+    pub hsts_max_age: u64,
+    /// Report Content-Security-Policy violations without blocking anything,
+    /// for trying out a policy
+    pub csp_report_only: bool,
+    /// How often the header quote changes: "request", "day" or "page"
+    pub quote_rotation: QuoteRotation,
+    /// Port to serve `/metrics` on without a key. Unless this is set,
+    /// metrics are on the main port and need an admin key
+    pub metrics_port: Option<u16>,
+    /// Address the metrics port listens on. Only this machine can reach it by default,
+    /// since anyone who can reach it can read the metrics
+    pub metrics_host: String,
+    /// Count page views, see `analytics`
+    pub analytics: bool,
+    /// Header a reverse proxy puts the client's address in, e.g. "x-forwarded-for".
+    /// Only set this behind a proxy that sets it, since clients could send it themselves
+    pub trusted_proxy_header: Option<String>,
+    /// Requests allowed per client each minute. 0 turns the limit off
+    pub rate_limit: usize,
+    /// Forms like comments allowed per client every 10 minutes. 0 turns the limit off
+    pub submission_rate_limit: usize,
+    /// Bad admin keys a client can send before it's locked out of admin routes.
+    /// 0 turns lockouts off
+    pub auth_failure_limit: usize,
+    /// How long a client is locked out for, in seconds, which is also how long
+    /// bad keys count towards the limit
+    pub auth_lockout_secs: u64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            host: "0.0.0.0".into(),
+            port: 8000,
+            site_url: "https://implicit.computer".into(),
+            posts_page_size: 10,
+            date_format: "%F".into(),
+            timezone: Tz::UTC,
+            admin_keys: Vec::new(),
+            show_changelog: false,
+            send_webmentions: true,
+            author: Author {
+                name: "implicit.computer".into(),
+                url: "https://implicit.computer".into(),
+                photo: None,
+                note: None,
+            },
+            token_endpoint: None,
+            actor_username: "blog".into(),
+            gemini_port: None,
+            gemini_cert_path: "../assets/gemini/cert.pem".into(),
+            gemini_key_path: "../assets/gemini/key.pem".into(),
+            gopher_port: None,
+            api_cors_origins: vec!["*".into()],
+            lint_max_line_length: None,
+            trust_posts: false,
+            hsts_max_age: 365 * 24 * 60 * 60,
+            csp_report_only: false,
+            quote_rotation: QuoteRotation::Request,
+            metrics_port: None,
+            metrics_host: "127.0.0.1".into(),
+            analytics: true,
+            trusted_proxy_header: None,
+            rate_limit: 600,
+            submission_rate_limit: 20,
+            auth_failure_limit: 5,
+            auth_lockout_secs: 15 * 60,
+        }
+    }
+}
+
+fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    match std::env::var(key) {
+        Ok(v) => v.parse().unwrap_or_else(|_| {
+            tracing::warn!("Ignoring invalid value {v:?} for {key}");
+            default
+        }),
+        Err(_) => default,
+    }
+}
+
+/// Parse keys of the form "name:key,othername:otherkey"
+fn parse_admin_keys(keys: &str) -> Vec<(String, String)> {
+    keys.split(',')
+        .filter_map(|entry| entry.trim().split_once(':'))
+        .filter(|(name, key)| !name.is_empty() && !key.is_empty())
+        .map(|(name, key)| (name.to_string(), key.to_string()))
+        .collect()
+}
+
+/// Parse a comma separated list, dropping empty entries
+fn parse_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+impl Settings {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let optional_var = |key| std::env::var(key).ok().filter(|v: &String| !v.is_empty());
+
+        let site_url = env_or("SITE_URL", defaults.site_url)
+            .trim_end_matches('/')
+            .to_string();
+
+        let author = Author {
+            name: env_or("SITE_AUTHOR_NAME", defaults.author.name),
+            url: optional_var("SITE_AUTHOR_URL").unwrap_or_else(|| site_url.clone()),
+            photo: optional_var("SITE_AUTHOR_PHOTO"),
+            note: optional_var("SITE_AUTHOR_NOTE"),
+        };
+
+        Settings {
+            host: env_or("SITE_HOST", defaults.host),
+            port: env_or("SITE_PORT", defaults.port),
+            site_url,
+            posts_page_size: env_or("SITE_POSTS_PAGE_SIZE", defaults.posts_page_size).max(1),
+            date_format: env_or("SITE_DATE_FORMAT", defaults.date_format),
+            timezone: env_or("SITE_TIMEZONE", defaults.timezone),
+            admin_keys: parse_admin_keys(&std::env::var("SITE_ADMIN_KEYS").unwrap_or_default()),
+            show_changelog: env_or("SITE_SHOW_CHANGELOG", defaults.show_changelog),
+            send_webmentions: env_or("SITE_SEND_WEBMENTIONS", defaults.send_webmentions),
+            author,
+            token_endpoint: optional_var("SITE_TOKEN_ENDPOINT"),
+            actor_username: env_or("SITE_ACTOR_USERNAME", defaults.actor_username),
+            gemini_port: optional_var("SITE_GEMINI_PORT").and_then(|p| p.parse().ok()),
+            gemini_cert_path: env_or("SITE_GEMINI_CERT", defaults.gemini_cert_path),
+            gemini_key_path: env_or("SITE_GEMINI_KEY", defaults.gemini_key_path),
+            gopher_port: optional_var("SITE_GOPHER_PORT").and_then(|p| p.parse().ok()),
+            api_cors_origins: match std::env::var("SITE_API_CORS_ORIGINS") {
+                Ok(origins) => parse_list(&origins),
+                Err(_) => defaults.api_cors_origins,
+            },
+            lint_max_line_length: optional_var("SITE_LINT_MAX_LINE_LENGTH")
+                .and_then(|l| l.parse().ok()),
+            trust_posts: env_or("SITE_TRUST_POSTS", defaults.trust_posts),
+            hsts_max_age: env_or("SITE_HSTS_MAX_AGE", defaults.hsts_max_age),
+            csp_report_only: env_or("SITE_CSP_REPORT_ONLY", defaults.csp_report_only),
+            quote_rotation: env_or("SITE_QUOTE_ROTATION", defaults.quote_rotation),
+            metrics_port: optional_var("SITE_METRICS_PORT").and_then(|p| p.parse().ok()),
+            metrics_host: env_or("SITE_METRICS_HOST", defaults.metrics_host),
+            analytics: env_or("SITE_ANALYTICS", defaults.analytics),
+            trusted_proxy_header: optional_var("SITE_TRUSTED_PROXY_HEADER")
+                .map(|h| h.to_ascii_lowercase()),
+            rate_limit: env_or("SITE_RATE_LIMIT", defaults.rate_limit),
+            submission_rate_limit: env_or(
+                "SITE_SUBMISSION_RATE_LIMIT",
+                defaults.submission_rate_limit,
+            ),
+            auth_failure_limit: env_or("SITE_AUTH_FAILURE_LIMIT", defaults.auth_failure_limit),
+            auth_lockout_secs: env_or("SITE_AUTH_LOCKOUT_SECS", defaults.auth_lockout_secs),
+        }
+    }
+
+    pub fn addr(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+}
+
+pub fn settings() -> &'static Settings {
+    static SETTINGS: OnceLock<Settings> = OnceLock::new();
+    SETTINGS.get_or_init(Settings::from_env)
+}
@@ -0,0 +1,187 @@
+//! Fetching documents from other sites, for webmentions and ActivityPub.
+//!
+//! The urls come from whoever sent us a request, so fetches are kept off the site's own
+//! network: hosts have to be public addresses, checked when they're resolved so a name
+//! can't point somewhere else by the time it's connected to, and bodies are only read
+//! up to a limit however they're sent.
+
+use crate::config;
+use anyhow::format_err;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use url::{Host, Url};
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+
+pub const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+pub const MAX_REDIRECTS: usize = 5;
+
+fn is_global_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        || a == 0
+        // Shared address space, used by carrier-grade NAT
+        || (a == 100 && (64..128).contains(&b))
+        // Protocol assignments
+        || (a == 192 && b == 0 && c == 0)
+        // Benchmarking
+        || (a == 198 && (18..20).contains(&b))
+        // Reserved
+        || a >= 240)
+}
+
+fn is_global_v6(ip: Ipv6Addr) -> bool {
+    let segments = ip.segments();
+
+    // Mapped and NAT64 addresses reach whatever IPv4 address they embed
+    if let Some(v4) = ip.to_ipv4_mapped() {
+        return is_global_v4(v4);
+    }
+    if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+        let [a, b] = segments[6].to_be_bytes();
+        let [c, d] = segments[7].to_be_bytes();
+        return is_global_v4(Ipv4Addr::new(a, b, c, d));
+    }
+
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // Unique local
+        || (segments[0] & 0xfe00) == 0xfc00
+        // Link local
+        || (segments[0] & 0xffc0) == 0xfe80
+        // Documentation
+        || (segments[0] == 0x2001 && segments[1] == 0xdb8))
+}
+
+/// Whether `ip` is on the public internet, rather than loopback, a private network or
+/// anything else that isn't meant to be reached from outside
+pub fn is_global(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_global_v4(ip),
+        IpAddr::V6(ip) => is_global_v6(ip),
+    }
+}
+
+/// Refuse urls that aren't http(s), or whose host is an address that isn't public.
+/// Hosts given by name are checked as `http_client` resolves them
+pub fn check_url(url: &Url) -> anyhow::Result<()> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format_err!("Not fetching {url}, it isn't http"));
+    }
+
+    let ip = match url.host() {
+        Some(Host::Ipv4(ip)) => IpAddr::V4(ip),
+        Some(Host::Ipv6(ip)) => IpAddr::V6(ip),
+        Some(Host::Domain(_)) => return Ok(()),
+        None => return Err(format_err!("Not fetching {url}, it has no host")),
+    };
+
+    match is_global(ip) {
+        true => Ok(()),
+        false => Err(format_err!("Not fetching {url}, it isn't a public address")),
+    }
+}
+
+/// Resolves names with the system resolver, dropping addresses that aren't public
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+
+        Box::pin(async move {
+            let addrs = tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .filter(|addr| is_global(addr.ip()))
+                .collect::<Vec<_>>();
+
+            if addrs.is_empty() {
+                return Err(format_err!("{host} has no public addresses").into());
+            }
+
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// The settings both clients share. Clients built from this alone, like in tests,
+/// can reach any address
+pub fn client_builder() -> reqwest::ClientBuilder {
+    reqwest::Client::builder()
+        .user_agent(format!("{} (webmention)", config::settings().site_url))
+        .timeout(FETCH_TIMEOUT)
+}
+
+/// Shared client for fetching urls other sites gave us, which only connects to public
+/// addresses, including after redirects. Urls should go through `check_url` first
+pub fn http_client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+
+    CLIENT.get_or_init(|| {
+        client_builder()
+            .dns_resolver(Arc::new(PublicResolver))
+            .redirect(reqwest::redirect::Policy::custom(|attempt| {
+                if attempt.previous().len() >= MAX_REDIRECTS {
+                    attempt.error(format_err!("Too many redirects"))
+                } else if let Err(e) = check_url(attempt.url()) {
+                    attempt.error(e)
+                } else {
+                    attempt.follow()
+                }
+            }))
+            .build()
+            .expect("Http client config is valid")
+    })
+}
+
+/// Client for urls from the site's own settings, like the token endpoint,
+/// which may well be on a private network
+pub fn settings_client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+
+    CLIENT.get_or_init(|| {
+        client_builder()
+            .redirect(reqwest::redirect::Policy::limited(MAX_REDIRECTS))
+            .build()
+            .expect("Http client config is valid")
+    })
+}
+
+/// Read a response's body, giving up as soon as it's longer than `max_bytes`,
+/// whether or not the response said how long it would be
+pub async fn read_body(
+    mut response: reqwest::Response,
+    max_bytes: usize,
+) -> anyhow::Result<Vec<u8>> {
+    let url = response.url().clone();
+    let too_large = || format_err!("{url} is larger than {max_bytes} bytes");
+
+    if response
+        .content_length()
+        .is_some_and(|l| l > max_bytes as u64)
+    {
+        return Err(too_large());
+    }
+
+    let mut body = Vec::new();
+
+    while let Some(chunk) = response.chunk().await? {
+        if body.len() + chunk.len() > max_bytes {
+            return Err(too_large());
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    Ok(body)
+}
@@ -0,0 +1,297 @@
+//! A Gemini server for the home page, post index and posts, alongside the http one.
+//! See <https://geminiprotocol.net/docs/protocol-specification.gmi>
+//!
+//! Gemini clients trust certificates on first use rather than checking a CA,
+//! so a self-signed certificate is generated if none is configured.
+
+use crate::{
+    blog::{db, render},
+    common::{Post, MEDIA_PATH, MEDIA_URL_PREFIX},
+    config, redirects,
+    view::{display_date, format_timestamp},
+    webmention::post_url,
+};
+use anyhow::format_err;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+use tokio_rustls::{rustls, TlsAcceptor};
+use url::Url;
+
+use std::{fs, io::BufReader, path::Path, sync::Arc, time::Duration};
+
+/// Requests are a url of at most 1024 bytes, then CRLF
+const MAX_REQUEST_LEN: usize = 1026;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+const RECENT_POSTS_ON_HOME: usize = 5;
+
+struct Response {
+    status: u8,
+    meta: String,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn gemtext(body: String) -> Self {
+        Self {
+            status: 20,
+            meta: "text/gemini; charset=utf-8".into(),
+            body: body.into_bytes(),
+        }
+    }
+
+    fn status(status: u8, meta: &str) -> Self {
+        Self {
+            status,
+            meta: meta.into(),
+            body: Vec::new(),
+        }
+    }
+
+    fn not_found() -> Self {
+        Self::status(51, "Not found")
+    }
+}
+
+/// Write a self-signed certificate for the site's host to the configured paths
+fn generate_certificate(cert_path: &Path, key_path: &Path) -> anyhow::Result<()> {
+    let site = Url::parse(&config::settings().site_url)?;
+    let host = site.host_str().unwrap_or("localhost").to_string();
+
+    tracing::info!("Generating a self-signed Gemini certificate for {host}");
+
+    let cert = rcgen::generate_simple_self_signed(vec![host, "localhost".into()])?;
+
+    for path in [cert_path, key_path] {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+    }
+
+    fs::write(cert_path, cert.serialize_pem()?)?;
+    fs::write(key_path, cert.serialize_private_key_pem())?;
+
+    Ok(())
+}
+
+/// TLS config for the listener, from the configured certificate and key
+pub fn tls_acceptor() -> anyhow::Result<TlsAcceptor> {
+    let settings = config::settings();
+    let cert_path = Path::new(&settings.gemini_cert_path);
+    let key_path = Path::new(&settings.gemini_key_path);
+
+    if !cert_path.try_exists()? && !key_path.try_exists()? {
+        generate_certificate(cert_path, key_path)?;
+    }
+
+    let certs = rustls_pemfile::certs(&mut BufReader::new(fs::File::open(cert_path)?))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let key = rustls_pemfile::private_key(&mut BufReader::new(fs::File::open(key_path)?))?
+        .ok_or_else(|| format_err!("No private key in {}", key_path.display()))?;
+
+    let config = rustls::ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+fn post_link(post: &Post) -> String {
+    format!(
+        "=> /blog/{} {} {}",
+        post.slug,
+        format_timestamp(post.timestamp, "%F"),
+        post.title
+    )
+}
+
+fn home() -> anyhow::Result<Response> {
+    let settings = config::settings();
+    let posts = db::DbConnection::new()?.all_posts()?;
+
+    let mut lines = vec![format!("# {}", settings.author.name)];
+
+    if let Some(note) = &settings.author.note {
+        lines.extend([String::new(), note.clone()]);
+    }
+
+    lines.extend([
+        String::new(),
+        "=> /blog Posts".into(),
+        format!("=> {} On the web", settings.site_url),
+        String::new(),
+        "## Recent posts".into(),
+    ]);
+
+    lines.extend(posts.iter().take(RECENT_POSTS_ON_HOME).map(post_link));
+
+    Ok(Response::gemtext(lines.join("\n") + "\n"))
+}
+
+/// Every post, newest first. Dated links make this subscribable as a Gemini feed
+fn index() -> anyhow::Result<Response> {
+    let posts = db::DbConnection::new()?.all_posts()?;
+
+    let mut lines = vec![
+        format!("# {}", config::settings().author.name),
+        String::new(),
+        "## Posts".into(),
+    ];
+
+    lines.extend(posts.iter().map(post_link));
+    lines.extend([String::new(), "=> / Home".into()]);
+
+    Ok(Response::gemtext(lines.join("\n") + "\n"))
+}
+
+fn post(post: &Post) -> anyhow::Result<Response> {
+    let md = render::read_file_contents(post.md_path())?;
+    let mut gemtext = render::md_to_gemtext(&md)?;
+
+    // Notes from micropub don't have a heading of their own
+    if !gemtext.starts_with("# ") {
+        gemtext = format!("# {}\n\n{gemtext}", post.title);
+    }
+
+    let mut footer = vec![
+        String::new(),
+        "---".into(),
+        format!("Published {}", display_date(post.timestamp)),
+    ];
+
+    if !post.tags.is_empty() {
+        footer.push(format!("Tagged {}", post.tags.join(", ")));
+    }
+
+    footer.extend([
+        String::new(),
+        "=> /blog All posts".into(),
+        format!("=> {} View on the web", post_url(&post.slug)),
+    ]);
+
+    Ok(Response::gemtext(gemtext + &footer.join("\n") + "\n"))
+}
+
+fn media(path: &str) -> anyhow::Result<Response> {
+    let file = Path::new(MEDIA_PATH).join(path.trim_start_matches('/'));
+
+    let mime = match file.extension().and_then(|e| e.to_str()) {
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        _ => "application/octet-stream",
+    };
+
+    match fs::read(file) {
+        Ok(body) => Ok(Response {
+            status: 20,
+            meta: mime.into(),
+            body,
+        }),
+        Err(_) => Ok(Response::not_found()),
+    }
+}
+
+fn redirect_or_not_found(conn: &db::DbConnection, path: &str) -> anyhow::Result<Response> {
+    Ok(match conn.redirect_for(redirects::normalize_path(path))? {
+        Some(r) if matches!(r.status_code, 301 | 308) => Response::status(31, &r.to_path),
+        Some(r) => Response::status(30, &r.to_path),
+        None => Response::not_found(),
+    })
+}
+
+fn respond(request: &str) -> anyhow::Result<Response> {
+    let url = match Url::parse(request.trim_end()) {
+        Ok(u) => u,
+        Err(_) => return Ok(Response::status(59, "Bad request")),
+    };
+
+    if url.scheme() != "gemini" {
+        return Ok(Response::status(53, "Only gemini urls are served here"));
+    }
+
+    let path = url.path();
+
+    if let Some(media_path) = path.strip_prefix(MEDIA_URL_PREFIX) {
+        return media(media_path);
+    }
+
+    match redirects::normalize_path(path) {
+        "/" => home(),
+        "/blog" => index(),
+        p => {
+            let conn = db::DbConnection::new()?;
+
+            match p.strip_prefix("/blog/").map(|slug| conn.find(slug)) {
+                Some(Ok(Some(ref p))) => post(p),
+                Some(Err(e)) => Err(e),
+                _ => redirect_or_not_found(&conn, p),
+            }
+        }
+    }
+}
+
+async fn handle_connection(
+    stream: tokio::net::TcpStream,
+    acceptor: TlsAcceptor,
+) -> anyhow::Result<()> {
+    let mut stream = acceptor.accept(stream).await?;
+
+    let mut request = Vec::new();
+    let mut buf = [0u8; 256];
+
+    let read_request = async {
+        while !request.ends_with(b"\r\n") && request.len() <= MAX_REQUEST_LEN {
+            match stream.read(&mut buf).await? {
+                0 => break,
+                n => request.extend_from_slice(&buf[..n]),
+            }
+        }
+        anyhow::Ok(())
+    };
+
+    tokio::time::timeout(REQUEST_TIMEOUT, read_request).await??;
+
+    let response = match String::from_utf8(request) {
+        Ok(r) if r.ends_with("\r\n") && r.len() <= MAX_REQUEST_LEN => {
+            respond(&r).unwrap_or_else(|e| {
+                tracing::error!("Gemini request for {} failed: {e:?}", r.trim_end());
+                Response::status(40, "Temporary failure")
+            })
+        }
+        _ => Response::status(59, "Bad request"),
+    };
+
+    stream
+        .write_all(format!("{} {}\r\n", response.status, response.meta).as_bytes())
+        .await?;
+    stream.write_all(&response.body).await?;
+    stream.shutdown().await?;
+
+    Ok(())
+}
+
+/// Accept Gemini connections until the process exits
+pub async fn serve(listener: TcpListener, acceptor: TlsAcceptor) {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(s) => s,
+            Err(e) => {
+                tracing::warn!("Could not accept a Gemini connection: {e:?}");
+                continue;
+            }
+        };
+
+        let acceptor = acceptor.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, acceptor).await {
+                tracing::debug!("Gemini connection from {peer} failed: {e:?}");
+            }
+        });
+    }
+}
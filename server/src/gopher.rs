@@ -0,0 +1,179 @@
+//! A Gopher server with a menu of posts and a plain text version of each one.
+//! See <https://www.rfc-editor.org/rfc/rfc1436>
+
+use crate::{
+    blog::{db, render},
+    common::Post,
+    config, redirects,
+    view::{display_date, format_timestamp},
+    webmention::post_url,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use url::Url;
+
+use std::time::Duration;
+
+const MAX_SELECTOR_LEN: usize = 1024;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Host and port that menu entries point back to, as readers see them
+fn public_address() -> (String, u16) {
+    let settings = config::settings();
+
+    let host = Url::parse(&settings.site_url)
+        .ok()
+        .and_then(|u| u.host_str().map(String::from))
+        .unwrap_or_else(|| settings.host.clone());
+
+    (host, settings.gopher_port.unwrap_or(70))
+}
+
+fn menu_line(kind: char, display: &str, selector: &str) -> String {
+    let (host, port) = public_address();
+    format!("{kind}{display}\t{selector}\t{host}\t{port}\r\n")
+}
+
+/// A line of text in a menu that isn't a link
+fn info_line(text: &str) -> String {
+    menu_line('i', text, "")
+}
+
+/// Menus and text files both end with a lone `.`, so lines starting with one are doubled
+fn terminate(body: &str) -> String {
+    let mut out = body
+        .lines()
+        .map(|l| match l.starts_with('.') {
+            true => format!(".{l}\r\n"),
+            false => format!("{l}\r\n"),
+        })
+        .collect::<String>();
+
+    out.push_str(".\r\n");
+    out
+}
+
+fn error_menu(message: &str) -> String {
+    format!("3{message}\t\terror.host\t1\r\n.\r\n")
+}
+
+fn posts_menu() -> anyhow::Result<String> {
+    let settings = config::settings();
+    let posts = db::DbConnection::new()?.all_posts()?;
+
+    let mut menu = info_line(&settings.author.name);
+
+    if let Some(note) = &settings.author.note {
+        menu.push_str(&info_line(note));
+    }
+
+    menu.push_str(&info_line(""));
+
+    for post in &posts {
+        let display = format!("{} {}", format_timestamp(post.timestamp, "%F"), post.title);
+        menu.push_str(&menu_line('0', &display, &format!("/blog/{}", post.slug)));
+    }
+
+    menu.push_str(&info_line(""));
+    menu.push_str(&menu_line(
+        'h',
+        "Read on the web",
+        &format!("URL:{}", settings.site_url),
+    ));
+    menu.push_str(".\r\n");
+
+    Ok(menu)
+}
+
+fn post_text(post: &Post) -> anyhow::Result<String> {
+    let md = render::read_file_contents(post.md_path())?;
+    let mut text = render::post_plain_text(post, &md)?;
+
+    text.push_str(&format!("\nPublished {}\n", display_date(post.timestamp)));
+
+    if !post.tags.is_empty() {
+        text.push_str(&format!("Tagged {}\n", post.tags.join(", ")));
+    }
+
+    text.push_str(&format!("Web version: {}\n", post_url(&post.slug)));
+
+    Ok(terminate(&text))
+}
+
+fn respond(selector: &str) -> anyhow::Result<String> {
+    // Anything after a tab is a search string, which nothing here takes
+    let selector = selector.split('\t').next().unwrap_or_default();
+    let path = format!("/{}", selector.trim_start_matches('/'));
+
+    let conn = db::DbConnection::new()?;
+
+    let slug = match redirects::normalize_path(&path) {
+        "/" | "/blog" => return posts_menu(),
+        p => match conn.redirect_for(p)? {
+            // Renamed posts have no redirect in Gopher, so serve where they went
+            Some(r) => r.to_path.strip_prefix("/blog/").map(String::from),
+            None => p.strip_prefix("/blog/").map(String::from),
+        },
+    };
+
+    match slug.map(|s| conn.find(redirects::normalize_path(&s))) {
+        Some(Ok(Some(ref post))) => post_text(post),
+        Some(Err(e)) => Err(e),
+        _ => Ok(error_menu("Not found")),
+    }
+}
+
+async fn handle_connection(mut stream: TcpStream) -> anyhow::Result<()> {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 256];
+
+    let read_request = async {
+        while !request.contains(&b'\n') && request.len() <= MAX_SELECTOR_LEN {
+            match stream.read(&mut buf).await? {
+                0 => break,
+                n => request.extend_from_slice(&buf[..n]),
+            }
+        }
+        anyhow::Ok(())
+    };
+
+    tokio::time::timeout(REQUEST_TIMEOUT, read_request).await??;
+
+    let response = match String::from_utf8(request) {
+        Ok(r) if r.len() <= MAX_SELECTOR_LEN + 2 => {
+            let selector = r.lines().next().unwrap_or_default();
+
+            respond(selector).unwrap_or_else(|e| {
+                tracing::error!("Gopher request for {selector:?} failed: {e:?}");
+                error_menu("Something went wrong")
+            })
+        }
+        _ => error_menu("Bad request"),
+    };
+
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+
+    Ok(())
+}
+
+/// Accept Gopher connections until the process exits
+pub async fn serve(listener: TcpListener) {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(s) => s,
+            Err(e) => {
+                tracing::warn!("Could not accept a Gopher connection: {e:?}");
+                continue;
+            }
+        };
+
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream).await {
+                tracing::debug!("Gopher connection from {peer} failed: {e:?}");
+            }
+        });
+    }
+}
@@ -0,0 +1,74 @@
+//! Log output, and a span for each request tagged with its request id.
+//!
+//! Read straight from the environment rather than `config`, so problems with the rest of
+//! the settings can be logged:
+//! - `SITE_LOG` filters what's logged, like `RUST_LOG` (which is used if it isn't set),
+//!   e.g. "info,server=debug"
+//! - `SITE_LOG_FORMAT` is "text" for people, or "json" for a log collector
+
+use axum::{extract::MatchedPath, http::Request};
+use tower_http::{
+    classify::{ServerErrorsAsFailures, SharedClassifier},
+    trace::{DefaultOnRequest, DefaultOnResponse, MakeSpan, TraceLayer},
+};
+use tracing::{Level, Span};
+use tracing_subscriber::EnvFilter;
+
+const DEFAULT_FILTER: &str = "info,server=debug,tower_http=debug";
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+pub fn init() {
+    let filter = EnvFilter::try_from_env("SITE_LOG")
+        .or_else(|_| EnvFilter::try_from_default_env())
+        .unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER));
+
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+
+    match std::env::var("SITE_LOG_FORMAT").as_deref() {
+        Ok("json") => builder.json().flatten_event(true).init(),
+        Ok("text") | Err(_) => builder.init(),
+        Ok(other) => {
+            builder.init();
+            tracing::warn!("Unknown log format {other:?}, logging as text");
+        }
+    }
+}
+
+/// Makes the span each request is handled in, so everything logged while handling it
+/// carries the request id and route
+#[derive(Debug, Clone, Copy)]
+pub struct RequestSpan;
+
+impl<B> MakeSpan<B> for RequestSpan {
+    fn make_span(&mut self, request: &Request<B>) -> Span {
+        let request_id = request
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|id| id.to_str().ok())
+            .unwrap_or_default();
+
+        let route = request
+            .extensions()
+            .get::<MatchedPath>()
+            .map(MatchedPath::as_str)
+            .unwrap_or_default();
+
+        tracing::info_span!(
+            "request",
+            request_id,
+            method = %request.method(),
+            uri = %request.uri(),
+            route,
+        )
+    }
+}
+
+/// Logs each request and its response, inside the request's span.
+/// Needs a request id set before it, see `main`
+pub fn trace_layer() -> TraceLayer<SharedClassifier<ServerErrorsAsFailures>, RequestSpan> {
+    TraceLayer::new_for_http()
+        .make_span_with(RequestSpan)
+        .on_request(DefaultOnRequest::new().level(Level::DEBUG))
+        .on_response(DefaultOnResponse::new().level(Level::INFO))
+}
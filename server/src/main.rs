@@ -1,9 +1,42 @@
 use axum::{
-    routing::{get, post},
+    extract::DefaultBodyLimit,
+    middleware,
+    routing::{delete, get, post},
     Router, Server,
 };
+use security::SecurityHeaders;
+use std::{net::SocketAddr, sync::Arc};
+use tower::ServiceBuilder;
+use tower_http::{
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    services::ServeDir,
+};
 
+pub mod activitypub;
+pub mod analytics;
+pub mod api;
 pub mod blog;
+pub mod comments;
+pub mod config;
+pub mod fetch;
+pub mod gemini;
+pub mod gopher;
+pub mod logging;
+pub mod media;
+pub mod metrics;
+pub mod mf2;
+pub mod micropub;
+pub mod negotiate;
+pub mod quotes;
+pub mod ratelimit;
+pub mod redirects;
+pub mod revisions;
+pub mod security;
+pub mod view;
+pub mod webmention;
+
+#[cfg(test)]
+mod testing;
 
 pub mod common {
     use base64::engine::general_purpose;
@@ -16,16 +49,20 @@ pub mod common {
 
     use chrono::{DateTime, NaiveDateTime, Utc};
 
-    #[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
+    use sha3::{Digest, Sha3_256};
+
+    #[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone, utoipa::ToSchema)]
     pub struct Post {
         pub title: String,
         pub timestamp: usize,
         pub slug: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub tags: Vec<String>,
     }
 
     impl Post {
         pub fn date_str(&self) -> String {
-            timestamp_date_format(self.timestamp, "%F")
+            crate::view::display_date(self.timestamp)
         }
 
         pub fn md_path(&self) -> PathBuf {
@@ -40,26 +77,93 @@ pub mod common {
     //relative to crate root
     //TODO make this work relative to file rather than cwd,
     //so it can be invoked from anywhere
+    #[cfg(not(test))]
     pub const POSTS_DB_PATH: &str = "../assets/posts.db";
+    #[cfg(not(test))]
     pub const POSTS_JSON_PATH: &str = "../assets/posts.json";
+    pub const QUOTES_JSON_PATH: &str = "../assets/quotes.json";
     pub const POSTS_FILES_PATH: &str = "../assets/posts/html";
+    #[cfg(not(test))]
     pub const POSTS_MARKDOWN_PATH: &str = "../assets/posts/md";
+
+    // Tests get posts of their own, set up by `testing::posts`
+    #[cfg(test)]
+    pub const POSTS_DB_PATH: &str = "../target/test-assets/posts.db";
+    #[cfg(test)]
+    pub const POSTS_JSON_PATH: &str = "../target/test-assets/posts.json";
+    #[cfg(test)]
+    pub const POSTS_MARKDOWN_PATH: &str = "../target/test-assets/md";
     pub const TEMPLATES_PATH: &str = "../assets/templates";
+    pub const MEDIA_PATH: &str = "../assets/media";
+    pub const MEDIA_URL_PREFIX: &str = "/media";
+
+    pub const SECONDS_PER_DAY: i64 = 60 * 60 * 24;
+
+    /// Body limit for posts uploaded as markdown, through `/admin/add` or Micropub
+    pub const MAX_POST_BYTES: usize = 1024 * 1024;
 
     pub const STATIC_PAGES_PATH: &str = "../assets/static";
     pub const HOMEPAGE_PATH: &str = "../assets/static/homepage.html";
 
-    pub fn validate_token(token: impl AsRef<[u8]>) -> bool {
-        if cfg!(debug_assertions) {
-            true
+    /// Name of the admin key matching `token`, if any.
+    /// The build time key is called "admin", the rest come from `SITE_ADMIN_KEYS`
+    pub fn token_key_name(token: impl AsRef<[u8]>) -> Option<String> {
+        // Tests check keys for real, with a fixed key standing in for the build time one
+        if cfg!(debug_assertions) && !cfg!(test) {
+            Some("debug".into())
         } else {
+            #[cfg(test)]
+            let env_token = crate::testing::ADMIN_KEY;
+            #[cfg(not(test))]
             let env_token = std::option_env!("SITE_ADMIN_KEY")
                 .expect("Admin key should be present in release builds");
 
-            env_token.as_bytes() == token.as_ref()
+            if env_token.as_bytes() == token.as_ref() {
+                return Some("admin".into());
+            }
+
+            crate::config::settings()
+                .admin_keys
+                .iter()
+                .find(|(_, key)| key.as_bytes() == token.as_ref())
+                .map(|(name, _)| name.clone())
         }
     }
 
+    pub fn validate_token(token: impl AsRef<[u8]>) -> bool {
+        token_key_name(token).is_some()
+    }
+
+    pub fn content_hash(content: impl AsRef<[u8]>) -> String {
+        hex::encode(Sha3_256::digest(content.as_ref()))
+    }
+
+    pub fn now_timestamp() -> usize {
+        Utc::now().timestamp() as usize
+    }
+
+    /// Trim, de-duplicate and sort tags. Commas separate tags, so "a, b" is two of them
+    pub fn normalize_tags(tags: &[String]) -> Vec<String> {
+        let mut normalized = tags
+            .iter()
+            .flat_map(|t| t.split(','))
+            .map(|t| t.trim().to_string())
+            .filter(|t| !t.is_empty())
+            .collect::<Vec<_>>();
+
+        normalized.sort();
+        normalized.dedup();
+        normalized
+    }
+
+    /// Slugs end up in file paths and urls, so keep them to a safe set of characters
+    pub fn is_valid_slug(slug: &str) -> bool {
+        !slug.is_empty()
+            && slug
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    }
+
     pub fn decode_base64(encoded: &impl AsRef<[u8]>) -> anyhow::Result<String> {
         let decoded_bytes = general_purpose::STANDARD_NO_PAD.decode(encoded)?;
         let decoded_string = String::from_utf8(decoded_bytes)?;
@@ -80,13 +184,16 @@ pub mod route {
     use crate::{
         blog::render::read_file_contents,
         common::{self, Post, POSTS_MARKDOWN_PATH},
+        config,
+        metrics::TimedRender,
+        view::{CommentView, PostView, RevisionView, WebmentionsView},
     };
     use anyhow;
     use anyhow::format_err;
     use axum::{
         extract::{self, Json},
-        http::StatusCode,
-        response::{Html, IntoResponse},
+        http::{header, HeaderMap, StatusCode},
+        response::{Html, IntoResponse, Response},
     };
     use axum_auth::AuthBearer;
 
@@ -100,6 +207,9 @@ pub mod route {
     use serde::{Deserialize, Serialize};
 
     use crate::blog::{db, render};
+    use crate::negotiate::{self, PostFormat};
+    use crate::{activitypub, redirects, view, webmention};
+    use chrono::Datelike;
     use std::fs;
 
     pub struct SiteError(anyhow::Error, Option<StatusCode>);
@@ -109,8 +219,15 @@ pub mod route {
         pub title: String,
         pub timestamp: usize,
         pub slug: String,
+        /// Hex encoded bzip2 of the markdown
+        #[serde(default)]
         pub file_content_compressed: String,
+        /// The markdown as is, for clients that can't compress it, like the browser editor
+        #[serde(default)]
+        pub file_content: Option<String>,
         pub overwrite: bool,
+        #[serde(default)]
+        pub tags: Vec<String>,
     }
 
     impl PostUpload {
@@ -119,34 +236,84 @@ pub mod route {
                 title: self.title.to_owned(),
                 slug: self.slug.to_owned(),
                 timestamp: self.timestamp,
+                tags: common::normalize_tags(&self.tags),
             }
         }
 
-        pub fn save(&self) -> anyhow::Result<()> {
+        /// The uploaded markdown, decompressed if need be
+        pub fn content(&self) -> anyhow::Result<String> {
+            if let Some(content) = &self.file_content {
+                return Ok(content.clone());
+            }
+
             let upload_bytes = hex::decode(&self.file_content_compressed)?;
 
             let mut decoder = BzDecoder::new(upload_bytes.as_slice());
             let mut str_buf = String::new();
             decoder.read_to_string(&mut str_buf)?;
 
-            let filename = format!("{}.md", self.slug);
+            Ok(str_buf)
+        }
+    }
+
+    /// Write a post's markdown to disk and its metadata to the db, recording a revision
+    /// attributed to `author`, queueing webmentions for its links and telling followers
+    pub fn save_post(post: &Post, content: &str, overwrite: bool, author: &str) -> anyhow::Result<()> {
+        let filename = format!("{}.md", post.slug);
+
+        let save_path = PathBuf::from(POSTS_MARKDOWN_PATH).join(&filename);
 
-            let save_path = PathBuf::from(POSTS_MARKDOWN_PATH).join(&filename);
+        match save_path.try_exists() {
+            Err(e) => Err(e.into()),
+            Ok(true) if !overwrite => Err(format_err!("'{filename}' already exists")),
+            _ => Ok(()),
+        }?;
 
-            match save_path.try_exists() {
-                Err(e) => Err(e.into()),
-                Ok(true) if !self.overwrite => Err(format_err!("'{filename}' already exists")),
-                _ => Ok(()),
-            }?;
+        let mut conn = db::DbConnection::new()?;
+        let existed = save_path.try_exists()?;
 
-            fs::File::create(save_path)?.write_all(str_buf.as_bytes())?;
+        // Posts from before revisions were tracked get their current text recorded first,
+        // so the first overwrite doesn't lose it
+        if existed && conn.revisions(&post.slug)?.is_empty() {
+            let previous_content = read_file_contents(&save_path)?;
+            let previous_timestamp = conn
+                .get(&post.slug)
+                .map(|p| p.timestamp)
+                .unwrap_or(post.timestamp);
 
-            let _conn = db::DbConnection::new()?;
+            conn.add_revision(&post.slug, &previous_content, previous_timestamp, "unknown")?;
+        }
+
+        fs::File::create(save_path)?.write_all(content.as_bytes())?;
+
+        conn.add_post_data(post)?;
+        conn.add_revision(&post.slug, content, common::now_timestamp(), author)?;
+
+        // Written out now rather than when `conn` drops, since sending webmentions and
+        // publishing to followers open connections of their own, which would otherwise
+        // load the old posts.json over the changes
+        conn.dump_json(common::POSTS_JSON_PATH)?;
 
-            db::DbConnection::new()?.add_post_data(&self.metadata())?;
+        // The post is saved either way, so failing to queue mentions shouldn't fail the upload
+        let queued = render::post_builder()
+            .md_content(content)
+            .timed_render()
+            .and_then(|html| webmention::queue_for_post(&conn, &post.slug, &html));
 
-            Ok(())
+        if let Err(e) = queued {
+            tracing::warn!("Could not queue webmentions for {}: {e:?}", post.slug);
         }
+
+        if let Err(e) = activitypub::publish(post, existed) {
+            tracing::warn!("Could not send {} to followers: {e:?}", post.slug);
+        }
+
+        Ok(())
+    }
+
+    /// Check an admin bearer token, giving the name of the key it matched
+    pub fn require_author(token: impl AsRef<[u8]>) -> Result<String, SiteError> {
+        common::token_key_name(token).ok_or_else(|| SiteError::from_status(StatusCode::FORBIDDEN))
     }
 
     pub struct StaticPage {
@@ -167,6 +334,10 @@ pub mod route {
         pub fn from_status(s: StatusCode) -> Self {
             Self(format_err!("{:?}", s), Some(s))
         }
+
+        pub fn with_status(self, s: StatusCode) -> Self {
+            Self(self.0, Some(s))
+        }
     }
 
     impl<E> From<E> for SiteError
@@ -191,24 +362,82 @@ pub mod route {
         }
     }
 
-    pub async fn posts_list() -> Result<Html<String>, SiteError> {
+    #[derive(Deserialize, Default)]
+    pub struct PageQuery {
+        pub page: Option<usize>,
+    }
+
+    pub async fn posts_list(
+        extract::Query(query): extract::Query<PageQuery>,
+    ) -> Result<Html<String>, SiteError> {
         let posts = db::DbConnection::new()?.all_posts()?;
-        let posts_list = render::post_index_display(&posts)?;
 
-        let content = render::RenderBuilder::new()
+        let page = query.page.unwrap_or(1);
+        let (page_posts, total_pages) =
+            render::paginate(&posts, page, config::settings().posts_page_size)
+                .ok_or_else(|| SiteError::from_status(StatusCode::NOT_FOUND))?;
+
+        let post_views = page_posts
+            .iter()
+            .map(PostView::load)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let pagination = render::Pagination::new(page, total_pages, "/blog");
+        let posts_list = render::post_index_display(&post_views, &pagination)?;
+
+        let content = render::page_builder("Posts Index")
             .html_content(&posts_list)
-            .into_base_template("Posts Index")
-            .render()?;
+            .timed_render()?;
+        Ok(Html::from(content))
+    }
+
+    async fn archive(year: i32, month: Option<u32>) -> Result<Html<String>, SiteError> {
+        let posts = db::DbConnection::new()?
+            .all_posts()?
+            .into_iter()
+            // In the site's timezone, so posts are filed under the date they're shown with
+            .filter(|p| {
+                view::localized_datetime(p.timestamp).is_ok_and(|date| {
+                    date.year() == year && month.is_none_or(|m| date.month() == m)
+                })
+            })
+            .map(|ref p| PostView::load(p))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let groups = render::group_by_month(&posts);
+
+        let heading = match (month, groups.first()) {
+            (_, None) => return Err(SiteError::from_status(StatusCode::NOT_FOUND)),
+            (Some(_), Some(g)) => format!("Archive: {}", g.label),
+            (None, Some(_)) => format!("Archive: {year}"),
+        };
+
+        let archive_content = render::archive_display(&heading, &groups)?;
+
+        let content = render::page_builder(&heading)
+            .html_content(&archive_content)
+            .timed_render()?;
         Ok(Html::from(content))
     }
 
+    pub async fn archive_year(
+        extract::Path(year): extract::Path<i32>,
+    ) -> Result<Html<String>, SiteError> {
+        archive(year, None).await
+    }
+
+    pub async fn archive_month(
+        extract::Path((year, month)): extract::Path<(i32, u32)>,
+    ) -> Result<Html<String>, SiteError> {
+        archive(year, Some(month)).await
+    }
+
     async fn static_route(page: StaticPage) -> Result<Html<String>, SiteError> {
         let content = render::read_file_contents(page.page_path)
             .and_then(|ref s| {
-                render::RenderBuilder::new()
+                render::page_builder(&page.title)
                     .html_content(s)
-                    .into_base_template(&page.title)
-                    .render()
+                    .timed_render()
             })
             .map(Html::from)?;
 
@@ -223,18 +452,158 @@ pub mod route {
         static_route(StaticPage::new("Home", "homepage.html")).await
     }
 
+    #[derive(Deserialize, Default)]
+    pub struct PostQuery {
+        /// Set after a comment is submitted, to show the moderation notice
+        pub comment: Option<String>,
+        /// Start markdown responses with the post's metadata as YAML front matter
+        #[serde(default)]
+        pub front_matter: bool,
+    }
+
+    /// A post's metadata and rendered html, for `Accept: application/json`
+    #[derive(Serialize)]
+    struct PostJson<'a> {
+        #[serde(flatten)]
+        post: &'a PostView,
+        permalink: String,
+        html: &'a str,
+    }
+
+    /// YAML front matter for a post's markdown. Strings are quoted as JSON, which YAML accepts
+    fn front_matter(post: &Post) -> String {
+        let quote = |s: &str| serde_json::to_string(s).unwrap_or_default();
+
+        let mut lines = vec![
+            "---".to_string(),
+            format!("title: {}", quote(&post.title)),
+            format!("slug: {}", quote(&post.slug)),
+            format!("date: {}", view::iso_datetime(post.timestamp)),
+        ];
+
+        if !post.tags.is_empty() {
+            let tags = post.tags.iter().map(|t| quote(t)).collect::<Vec<_>>();
+            lines.push(format!("tags: [{}]", tags.join(", ")));
+        }
+
+        lines.push("---\n\n".into());
+        lines.join("\n")
+    }
+
+    fn with_content_type(format: PostFormat, body: String, vary: bool) -> Response {
+        let mut response = ([(header::CONTENT_TYPE, format.content_type())], body).into_response();
+
+        if vary {
+            response
+                .headers_mut()
+                .insert(header::VARY, header::HeaderValue::from_static("accept"));
+        }
+
+        response
+    }
+
+    /// A post as html, or as its markdown source, plain text or JSON if the Accept header
+    /// asks for one of those. `/blog/:slug.md` always gives the markdown
     pub async fn get_post(
         extract::Path(slug): extract::Path<String>,
-    ) -> Result<Html<String>, SiteError> {
-        let post = db::DbConnection::new()?.get(&slug)?;
+        extract::Query(query): extract::Query<PostQuery>,
+        headers: HeaderMap,
+    ) -> Result<Response, SiteError> {
+        let (slug, format, vary) = match slug.strip_suffix(".md") {
+            Some(s) => (s.to_string(), Some(PostFormat::Markdown), false),
+            None => (slug, negotiate::post_format(&headers), true),
+        };
+
+        let Some(format) = format else {
+            let mut response = SiteError::from_status(StatusCode::NOT_ACCEPTABLE).into_response();
+            response
+                .headers_mut()
+                .insert(header::VARY, header::HeaderValue::from_static("accept"));
+            return Ok(response);
+        };
+
+        let conn = db::DbConnection::new()?;
+
+        let post = match conn.find(&slug)? {
+            Some(p) => p,
+            None => {
+                let path = match format {
+                    PostFormat::Markdown if !vary => format!("/blog/{slug}.md"),
+                    _ => format!("/blog/{slug}"),
+                };
+                return redirects::redirect_or_not_found(&conn, &path);
+            }
+        };
 
         let md_content = read_file_contents(post.md_path())?;
 
-        render::RenderBuilder::new()
+        match format {
+            PostFormat::Markdown => {
+                let body = match query.front_matter {
+                    true => front_matter(&post) + &md_content,
+                    false => md_content,
+                };
+                return Ok(with_content_type(format, body, vary));
+            }
+            PostFormat::PlainText => {
+                let text = render::post_plain_text(&post, &md_content)?;
+                return Ok(with_content_type(format, text, vary));
+            }
+            PostFormat::Html | PostFormat::Json => (),
+        }
+
+        let images = conn
+            .post_media(&post.slug)?
+            .iter()
+            .map(|m| m.as_image_asset())
+            .collect::<Vec<_>>();
+
+        let post_view = PostView::new(&post, &md_content)?;
+
+        let post_html = render::post_builder()
             .md_content(&md_content)
-            .into_base_template(&post.title)
-            .render()
-            .map(Html::from)
+            .images(&images)
+            .timed_render()?;
+
+        if format == PostFormat::Json {
+            let body = serde_json::to_string(&PostJson {
+                post: &post_view,
+                permalink: webmention::post_url(&post.slug),
+                html: &post_html,
+            })?;
+            return Ok(with_content_type(format, body, vary));
+        }
+
+        let changelog = if config::settings().show_changelog {
+            let revisions = conn
+                .revisions(&post.slug)?
+                .iter()
+                .map(RevisionView::new)
+                .collect::<Vec<_>>();
+
+            render::changelog_display(&revisions)?
+        } else {
+            String::new()
+        };
+
+        let webmentions =
+            render::webmentions_display(&WebmentionsView::new(&conn.post_webmentions(&post.slug)?))?;
+
+        let comments = conn
+            .post_comments(&post.slug, db::CommentStatus::Approved)?
+            .iter()
+            .map(CommentView::new)
+            .collect::<Vec<_>>();
+
+        let comments_section =
+            render::comments_display(&post.slug, &comments, query.comment.is_some())?;
+
+        let entry = render::post_display(&post_view, &post_html, &changelog)?;
+
+        render::page_builder(&post.title)
+            .html_content(&format!("{entry}\n{webmentions}\n{comments_section}"))
+            .timed_render()
+            .map(|content| with_content_type(format, content, vary))
             .map_err(|e| e.into())
     }
 
@@ -242,44 +611,168 @@ pub mod route {
         AuthBearer(token): AuthBearer,
         Json(payload): Json<PostUpload>,
     ) -> Result<StatusCode, SiteError> {
-        if !common::validate_token(token) {
-            Err(SiteError::from_status(StatusCode::FORBIDDEN))
-        } else {
-            payload.save().map(|_| StatusCode::OK).map_err(|e| e.into())
+        let author = require_author(token)?;
+        let content = payload.content()?;
+
+        if let Some(problems) = render::lint_errors(&content)? {
+            return Err(SiteError::from(format_err!(problems))
+                .with_status(StatusCode::UNPROCESSABLE_ENTITY));
         }
+
+        save_post(&payload.metadata(), &content, payload.overwrite, &author)?;
+
+        Ok(StatusCode::OK)
     }
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::DEBUG)
-        .init();
+    logging::init();
 
     //TODO figure out how to use middleware to avoid
     //needing to specify path versions with and without slashes
-    let app = Router::new()
+    let mut app = Router::new()
         .route("/", get(route::home))
         .route("/blog", get(route::posts_list))
         .route("/blog/", get(route::posts_list))
+        .route("/blog/archive/:year", get(route::archive_year))
+        .route("/blog/archive/:year/", get(route::archive_year))
+        .route("/blog/archive/:year/:month", get(route::archive_month))
+        .route("/blog/archive/:year/:month/", get(route::archive_month))
         .route("/about", get(route::about))
         .route("/about/", get(route::about))
-        .route("/admin/add", post(route::add_new_post))
+        .route(
+            "/admin/add",
+            post(route::add_new_post).layer(DefaultBodyLimit::max(common::MAX_POST_BYTES)),
+        )
+        .route(
+            "/admin/media/:slug",
+            post(media::upload_media).layer(DefaultBodyLimit::max(media::MAX_REQUEST_BYTES)),
+        )
+        .route(
+            "/admin/posts/:slug/revisions",
+            get(revisions::list_revisions),
+        )
+        .route("/admin/posts/:slug/diff", get(revisions::diff_revisions))
+        .route(
+            "/admin/posts/:slug/revisions/:id/restore",
+            post(revisions::restore_revision),
+        )
+        .route("/admin/posts/:slug/rename", post(redirects::rename_post))
+        .route(
+            "/admin/redirects",
+            get(redirects::list_redirects).post(redirects::set_redirect),
+        )
+        .route("/admin/redirects/:id", delete(redirects::delete_redirect))
+        .route(
+            "/admin/quotes",
+            get(quotes::list_quotes).post(quotes::add_quote),
+        )
+        .route("/admin/quotes/:id", delete(quotes::delete_quote))
+        .route("/admin/analytics", get(analytics::get_analytics))
+        .route("/admin/comments", get(comments::list_comments))
+        .route("/admin/comments/:id", delete(comments::delete_comment))
+        .route("/admin/comments/:id/approve", post(comments::approve_comment))
+        .route("/admin/comments/:id/reject", post(comments::reject_comment))
+        .nest_service(common::MEDIA_URL_PREFIX, ServeDir::new(common::MEDIA_PATH))
         //.route("/admin/posts", get(route::admin_posts_list))
         .route("/blog/:slug", get(route::get_post))
-        .route("/blog/:slug/", get(route::get_post));
+        .route("/blog/:slug/", get(route::get_post))
+        .route(
+            "/blog/:slug/comments",
+            post(comments::submit_comment)
+                .layer(DefaultBodyLimit::max(comments::MAX_REQUEST_BYTES)),
+        )
+        .route(
+            "/webmention",
+            post(webmention::receive).layer(DefaultBodyLimit::max(webmention::MAX_REQUEST_BYTES)),
+        )
+        .route(
+            "/micropub",
+            get(micropub::get_micropub)
+                .post(micropub::post_micropub)
+                .layer(DefaultBodyLimit::max(common::MAX_POST_BYTES)),
+        )
+        .route(
+            "/admin/webmentions/outgoing",
+            get(webmention::list_outgoing),
+        )
+        .route(
+            "/admin/webmentions/outgoing/:id/retry",
+            post(webmention::retry_outgoing),
+        )
+        .route("/api/quote", get(quotes::get_quote))
+        .nest("/api/v1", api::router())
+        .route("/.well-known/webfinger", get(activitypub::webfinger))
+        .route("/actor", get(activitypub::actor))
+        .route("/actor/inbox", post(activitypub::inbox))
+        .route("/actor/outbox", get(activitypub::outbox))
+        .route("/actor/followers", get(activitypub::followers))
+        .route("/actor/posts/:slug", get(activitypub::post_object_response))
+        .route(
+            security::REPORT_PATH,
+            post(security::report).layer(DefaultBodyLimit::max(security::REPORT_MAX_BYTES)),
+        )
+        .fallback(redirects::fallback);
+
+    // With a port of its own, metrics stay off the main one
+    if config::settings().metrics_port.is_none() {
+        app = app.route("/metrics", get(metrics::get_metrics));
+    }
+
+    let app = app
+        .layer(middleware::from_fn(analytics::record_views))
+        .layer(middleware::from_fn_with_state(
+            Arc::new(SecurityHeaders::pages()),
+            security::set_headers,
+        ))
+        .layer(middleware::from_fn(ratelimit::limit_requests))
+        .layer(middleware::from_fn(metrics::track_requests))
+        .layer(
+            ServiceBuilder::new()
+                .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+                .layer(logging::trace_layer())
+                .layer(PropagateRequestIdLayer::x_request_id()),
+        );
+
+    // Generating the key is slow, so it's done before serving rather than on the first request
+    activitypub::actor_key()?;
+
+    tokio::spawn(webmention::run_sender());
+
+    if let Some(port) = config::settings().gemini_port {
+        let addr = format!("{}:{port}", config::settings().host);
+        let listener = tokio::net::TcpListener::bind(&addr).await?;
+
+        tracing::debug!("Serving Gemini on {addr}");
+        tokio::spawn(gemini::serve(listener, gemini::tls_acceptor()?));
+    }
+
+    if let Some(port) = config::settings().gopher_port {
+        let addr = format!("{}:{port}", config::settings().host);
+        let listener = tokio::net::TcpListener::bind(&addr).await?;
+
+        tracing::debug!("Serving Gopher on {addr}");
+        tokio::spawn(gopher::serve(listener));
+    }
+
+    if let Some(port) = config::settings().metrics_port {
+        let addr = format!("{}:{port}", config::settings().metrics_host);
+        let server =
+            Server::bind(&addr.parse()?).serve(metrics::admin_router().into_make_service());
 
-    let port = "8000";
-    let host = "0.0.0.0";
+        tracing::debug!("Serving metrics on {addr}");
+        tokio::spawn(server);
+    }
 
-    let addr = format!("{host}:{port}");
+    let addr = config::settings().addr();
     tracing::debug!("Listening on {}", &addr);
     let res = Server::bind(&addr.parse()?)
-        .serve(app.into_make_service())
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await;
 
     if let Some(e) = res.err() {
-        eprintln!("ERROR: {:?}", e);
+        tracing::error!("Server stopped: {e:?}");
     }
 
     Ok(())
@@ -0,0 +1,184 @@
+use crate::{
+    blog::db::{self, MediaRecord},
+    common,
+    route::SiteError,
+};
+use anyhow::format_err;
+use axum::{
+    extract::{self, Json, Multipart},
+    http::StatusCode,
+};
+use axum_auth::AuthBearer;
+use image::{
+    codecs::jpeg::JpegEncoder, imageops::FilterType, DynamicImage, ImageDecoder, ImageFormat,
+    ImageReader,
+};
+use md_render::ImageAsset;
+
+use std::{fs, io::Cursor, path::PathBuf};
+
+/// Largest single file accepted by the upload endpoint
+pub const MAX_UPLOAD_BYTES: usize = 10 * 1024 * 1024;
+
+/// Body limit for the whole multipart request, allowing a handful of files at once
+pub const MAX_REQUEST_BYTES: usize = 4 * MAX_UPLOAD_BYTES;
+
+/// Widths of the resized copies generated for each upload.
+/// Only widths smaller than the original are generated.
+pub const VARIANT_WIDTHS: [u32; 3] = [480, 960, 1440];
+
+const JPEG_QUALITY: u8 = 85;
+
+/// Turn a client supplied file name into something safe to put on disk,
+/// dropping the extension (which is picked from the detected format instead)
+fn sanitize_file_stem(file_name: &str) -> Option<String> {
+    let stem = match file_name.rsplit_once('.') {
+        Some((stem, _)) => stem,
+        None => file_name,
+    };
+
+    let sanitized = stem
+        .chars()
+        .map(|c| match c {
+            c if c.is_ascii_alphanumeric() => c.to_ascii_lowercase(),
+            '-' | '_' => c,
+            _ => '-',
+        })
+        .collect::<String>()
+        .trim_matches('-')
+        .to_string();
+
+    (!sanitized.is_empty()).then_some(sanitized)
+}
+
+/// Sniff the image format from the file contents, rather than trusting the client's content type
+pub fn validate_upload(bytes: &[u8]) -> anyhow::Result<ImageFormat> {
+    if bytes.len() > MAX_UPLOAD_BYTES {
+        return Err(format_err!(
+            "Upload is {} bytes, the limit is {MAX_UPLOAD_BYTES}",
+            bytes.len()
+        ));
+    }
+
+    match image::guess_format(bytes)? {
+        f @ (ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::Gif | ImageFormat::WebP) => Ok(f),
+        f => Err(format_err!("Unsupported image format {f:?}")),
+    }
+}
+
+fn format_extension(format: ImageFormat) -> &'static str {
+    match format {
+        ImageFormat::Jpeg => "jpg",
+        ImageFormat::Png => "png",
+        ImageFormat::Gif => "gif",
+        _ => "webp",
+    }
+}
+
+fn encode_to_file(img: &DynamicImage, format: ImageFormat, path: &PathBuf) -> anyhow::Result<()> {
+    match format {
+        ImageFormat::Jpeg => {
+            let file = fs::File::create(path)?;
+            img.write_with_encoder(JpegEncoder::new_with_quality(file, JPEG_QUALITY))?;
+        }
+        _ => img.save_with_format(path, format)?,
+    }
+
+    Ok(())
+}
+
+/// Decode the image, apply its EXIF orientation, and write it back out along with resized variants.
+/// Re-encoding from the decoded pixels is what strips EXIF and other embedded metadata.
+pub fn save_image(slug: &str, file_name: &str, bytes: &[u8]) -> anyhow::Result<MediaRecord> {
+    let format = validate_upload(bytes)?;
+
+    let file_stem = sanitize_file_stem(file_name)
+        .ok_or_else(|| format_err!("Invalid file name {file_name:?}"))?;
+
+    let mut decoder = ImageReader::with_format(Cursor::new(bytes), format).into_decoder()?;
+    let orientation = decoder.orientation()?;
+    let mut img = DynamicImage::from_decoder(decoder)?;
+    img.apply_orientation(orientation);
+
+    let media_dir = PathBuf::from(common::MEDIA_PATH).join(slug);
+    fs::create_dir_all(&media_dir)?;
+
+    let mut record = MediaRecord {
+        post_slug: slug.into(),
+        file_stem,
+        extension: format_extension(format).into(),
+        width: img.width(),
+        height: img.height(),
+        variant_widths: Vec::new(),
+    };
+
+    // GIFs have no EXIF block, and re-encoding would flatten animations
+    if format == ImageFormat::Gif {
+        fs::write(media_dir.join(record.file_name()), bytes)?;
+        return Ok(record);
+    }
+
+    encode_to_file(&img, format, &media_dir.join(record.file_name()))?;
+
+    for width in VARIANT_WIDTHS.into_iter().filter(|&w| w < record.width) {
+        let height = (u64::from(record.height) * u64::from(width) / u64::from(record.width)) as u32;
+        let resized = img.resize_exact(width, height.max(1), FilterType::Lanczos3);
+
+        encode_to_file(
+            &resized,
+            format,
+            &media_dir.join(record.variant_file_name(width)),
+        )?;
+        record.variant_widths.push(width);
+    }
+
+    Ok(record)
+}
+
+/// Accepts a multipart form of image files, stored under the post with the given slug.
+/// Responds with the stored assets, whose `src` can be referenced from the post markdown.
+pub async fn upload_media(
+    AuthBearer(token): AuthBearer,
+    extract::Path(slug): extract::Path<String>,
+    mut multipart: Multipart,
+) -> Result<Json<Vec<ImageAsset>>, SiteError> {
+    if !common::validate_token(token) {
+        return Err(SiteError::from_status(StatusCode::FORBIDDEN));
+    }
+
+    if !common::is_valid_slug(&slug) {
+        return Err(SiteError::from_status(StatusCode::BAD_REQUEST));
+    }
+
+    let mut saved = Vec::new();
+
+    while let Some(field) = multipart.next_field().await? {
+        let file_name = match field.file_name() {
+            Some(f) => f.to_string(),
+            None => continue,
+        };
+
+        let bytes = field.bytes().await?;
+
+        if let Err(e) = validate_upload(&bytes) {
+            let status = match bytes.len() > MAX_UPLOAD_BYTES {
+                true => StatusCode::PAYLOAD_TOO_LARGE,
+                false => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            };
+            return Err(SiteError::from(e).with_status(status));
+        }
+
+        // Decoding and resizing are slow, so they're kept off the async workers
+        let post_slug = slug.clone();
+        let record =
+            tokio::task::spawn_blocking(move || save_image(&post_slug, &file_name, &bytes))
+                .await
+                .map_err(|e| format_err!("Saving an upload panicked: {e}"))?
+                .map_err(|e| SiteError::from(e).with_status(StatusCode::BAD_REQUEST))?;
+
+        db::DbConnection::new()?.add_media(&record)?;
+        saved.push(record.as_image_asset());
+    }
+
+    Ok(Json(saved))
+}
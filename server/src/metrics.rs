@@ -0,0 +1,244 @@
+//! Prometheus metrics for the running site, served at `/metrics`.
+//!
+//! Requests are counted and timed per matched route by the `track_requests` middleware.
+//! The endpoint is on the main port behind an admin key, or on its own port with no
+//! key when `SITE_METRICS_PORT` is set. That port only listens on localhost unless
+//! `SITE_METRICS_HOST` says otherwise, so it stays off the public internet.
+
+use crate::{
+    blog::{db, render::RenderBuilder},
+    common,
+    route::{require_author, SiteError},
+};
+use axum::{
+    body::Body,
+    extract::MatchedPath,
+    http::{header, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use axum_auth::AuthBearer;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+
+use std::{
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+/// Routes aren't known for requests that matched none, so they share a label
+const UNMATCHED_ROUTE: &str = "unmatched";
+/// Label for methods outside the standard set, which clients can make up freely
+const OTHER_METHOD: &str = "other";
+
+/// Finer at the low end, where rendering and queries usually are
+const FAST_BUCKETS: &[f64] = &[
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0,
+];
+
+struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    http_cache: IntCounterVec,
+    render_duration: Histogram,
+    db_query_duration: HistogramVec,
+    posts: IntGauge,
+}
+
+impl Metrics {
+    fn new() -> prometheus::Result<Self> {
+        let registry = Registry::new();
+
+        let http_requests = IntCounterVec::new(
+            Opts::new(
+                "http_requests_total",
+                "Requests handled, by route and status",
+            ),
+            &["method", "route", "status"],
+        )?;
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time to handle a request, by route",
+            ),
+            &["method", "route"],
+        )?;
+        let http_cache = IntCounterVec::new(
+            Opts::new(
+                "http_cache_requests_total",
+                "Conditional requests by route, hits being answered with 304 Not Modified",
+            ),
+            &["route", "result"],
+        )?;
+        let render_duration = Histogram::with_opts(
+            HistogramOpts::new(
+                "render_duration_seconds",
+                "Time spent in RenderBuilder::render",
+            )
+            .buckets(FAST_BUCKETS.to_vec()),
+        )?;
+        let db_query_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "db_query_duration_seconds",
+                "Time spent running SQLite statements, by kind of statement",
+            )
+            .buckets(FAST_BUCKETS.to_vec()),
+            &["statement"],
+        )?;
+        let posts = IntGauge::new("posts", "Published posts")?;
+
+        registry.register(Box::new(http_requests.clone()))?;
+        registry.register(Box::new(http_request_duration.clone()))?;
+        registry.register(Box::new(http_cache.clone()))?;
+        registry.register(Box::new(render_duration.clone()))?;
+        registry.register(Box::new(db_query_duration.clone()))?;
+        registry.register(Box::new(posts.clone()))?;
+
+        Ok(Metrics {
+            registry,
+            http_requests,
+            http_request_duration,
+            http_cache,
+            render_duration,
+            db_query_duration,
+            posts,
+        })
+    }
+}
+
+fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(|| Metrics::new().expect("Metric definitions are valid"))
+}
+
+/// The label for `method`, so made up methods can't add a time series each
+fn method_label(method: &Method) -> &'static str {
+    match *method {
+        Method::GET => "GET",
+        Method::HEAD => "HEAD",
+        Method::POST => "POST",
+        Method::PUT => "PUT",
+        Method::PATCH => "PATCH",
+        Method::DELETE => "DELETE",
+        Method::OPTIONS => "OPTIONS",
+        Method::CONNECT => "CONNECT",
+        Method::TRACE => "TRACE",
+        _ => OTHER_METHOD,
+    }
+}
+
+/// Middleware counting and timing requests, for use with `middleware::from_fn`.
+/// Needs to be a `Router::layer` so the matched route is known
+pub async fn track_requests(request: Request<Body>, next: Next<Body>) -> Response {
+    let start = Instant::now();
+
+    let method = method_label(request.method());
+    // Nested services like the media directory don't set a matched path
+    let route = match request.extensions().get::<MatchedPath>() {
+        Some(path) => path.as_str().to_string(),
+        None if request.uri().path().starts_with(common::MEDIA_URL_PREFIX) => {
+            format!("{}/*", common::MEDIA_URL_PREFIX)
+        }
+        None => UNMATCHED_ROUTE.into(),
+    };
+    let conditional = request.headers().contains_key(header::IF_NONE_MATCH)
+        || request.headers().contains_key(header::IF_MODIFIED_SINCE);
+
+    let response = next.run(request).await;
+    let status = response.status();
+
+    let m = metrics();
+    m.http_requests
+        .with_label_values(&[method, &route, status.as_str()])
+        .inc();
+    m.http_request_duration
+        .with_label_values(&[method, &route])
+        .observe(start.elapsed().as_secs_f64());
+
+    if conditional {
+        let result = match status {
+            StatusCode::NOT_MODIFIED => "hit",
+            _ => "miss",
+        };
+        m.http_cache.with_label_values(&[&route, result]).inc();
+    }
+
+    response
+}
+
+/// `RenderBuilder::render`, recorded in the render time metrics
+pub trait TimedRender {
+    fn timed_render(&self) -> anyhow::Result<String>;
+}
+
+impl TimedRender for RenderBuilder {
+    fn timed_render(&self) -> anyhow::Result<String> {
+        let _span = tracing::debug_span!("render").entered();
+
+        let start = Instant::now();
+        let rendered = self.render();
+        let elapsed = start.elapsed();
+
+        metrics().render_duration.observe(elapsed.as_secs_f64());
+        tracing::debug!(?elapsed, ok = rendered.is_ok(), "Rendered");
+
+        rendered
+    }
+}
+
+/// Profiling callback for SQLite connections. Statements are labelled by their first
+/// keyword, since the full text would make a label for every set of parameters
+pub fn observe_query(sql: &str, duration: Duration) {
+    let statement = sql
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+
+    metrics()
+        .db_query_duration
+        .with_label_values(&[&statement])
+        .observe(duration.as_secs_f64());
+
+    tracing::trace!(target: "server::db", sql, ?duration, "Ran a statement");
+}
+
+/// Everything in the Prometheus text format, with gauges that are read rather than
+/// tracked brought up to date first
+fn encode() -> anyhow::Result<String> {
+    let m = metrics();
+    m.posts
+        .set(db::DbConnection::without_posts_json()?.post_count()? as i64);
+
+    let mut buf = Vec::new();
+    TextEncoder::new().encode(&m.registry.gather(), &mut buf)?;
+
+    Ok(String::from_utf8(buf)?)
+}
+
+fn metrics_response() -> Result<Response, SiteError> {
+    Ok((
+        [(
+            header::CONTENT_TYPE,
+            TextEncoder::new().format_type().to_string(),
+        )],
+        encode()?,
+    )
+        .into_response())
+}
+
+/// `/metrics` on the main port, for admins only
+pub async fn get_metrics(AuthBearer(token): AuthBearer) -> Result<Response, SiteError> {
+    require_author(token)?;
+    metrics_response()
+}
+
+/// The router for the separate metrics port, which doesn't need a key
+pub fn admin_router() -> Router {
+    Router::new().route("/metrics", get(|| async { metrics_response() }))
+}
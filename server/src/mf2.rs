@@ -0,0 +1,465 @@
+//! A small microformats2 parser, covering the parts of the spec we need:
+//! `h-*` items, `p-`/`u-`/`dt-`/`e-` properties, nested items and implied names.
+//! See <https://microformats.org/wiki/microformats2-parsing>
+
+use scraper::{ElementRef, Html};
+use serde::Serialize;
+use url::Url;
+
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum PropertyValue {
+    Text(String),
+    Html { html: String, value: String },
+    Item(Box<Item>),
+}
+
+impl PropertyValue {
+    /// Plain text form of the value, regardless of what kind it is
+    pub fn as_text(&self) -> String {
+        match self {
+            PropertyValue::Text(t) => t.clone(),
+            PropertyValue::Html { value, .. } => value.clone(),
+            PropertyValue::Item(item) => item.value.clone().unwrap_or_default(),
+        }
+    }
+
+    pub fn as_item(&self) -> Option<&Item> {
+        match self {
+            PropertyValue::Item(item) => Some(item),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Item {
+    #[serde(rename = "type")]
+    pub types: Vec<String>,
+    pub properties: HashMap<String, Vec<PropertyValue>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<Item>,
+    /// For an item nested as a property, the value the property would have had
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+}
+
+impl Item {
+    pub fn has_type(&self, t: &str) -> bool {
+        self.types.iter().any(|x| x == t)
+    }
+
+    pub fn first(&self, property: &str) -> Option<&PropertyValue> {
+        self.properties.get(property).and_then(|v| v.first())
+    }
+
+    pub fn first_text(&self, property: &str) -> Option<String> {
+        self.first(property).map(|v| v.as_text())
+    }
+
+    /// All items in the tree rooted here (including this one), depth first
+    pub fn descendants(&self) -> Vec<&Item> {
+        let mut found = vec![self];
+
+        for values in self.properties.values() {
+            for item in values.iter().filter_map(|v| v.as_item()) {
+                found.extend(item.descendants());
+            }
+        }
+
+        for child in &self.children {
+            found.extend(child.descendants());
+        }
+
+        found
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Document {
+    pub items: Vec<Item>,
+}
+
+impl Document {
+    /// Every item of type `t`, at any depth
+    pub fn find_all(&self, t: &str) -> Vec<&Item> {
+        self.items
+            .iter()
+            .flat_map(|i| i.descendants())
+            .filter(|i| i.has_type(t))
+            .collect()
+    }
+
+    pub fn find(&self, t: &str) -> Option<&Item> {
+        self.find_all(t).into_iter().next()
+    }
+}
+
+fn classes_with_prefix<'a>(el: &'a ElementRef, prefix: &'a str) -> Vec<&'a str> {
+    el.value()
+        .classes()
+        .filter(|c| {
+            c.strip_prefix(prefix)
+                .is_some_and(|rest| !rest.is_empty() && rest.chars().all(|ch| ch.is_ascii_lowercase() || ch == '-'))
+        })
+        .collect()
+}
+
+fn text_content(el: &ElementRef) -> String {
+    el.text()
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn resolve(base: Option<&Url>, href: &str) -> String {
+    match base.and_then(|b| b.join(href).ok()) {
+        Some(u) => u.to_string(),
+        None => href.to_string(),
+    }
+}
+
+fn attr<'a>(el: &'a ElementRef, name: &str) -> Option<&'a str> {
+    el.value().attr(name)
+}
+
+fn p_value(el: &ElementRef) -> String {
+    let tag = el.value().name();
+
+    match tag {
+        "abbr" | "link" => attr(el, "title").map(String::from),
+        "data" | "input" => attr(el, "value").map(String::from),
+        "img" | "area" => attr(el, "alt").map(String::from),
+        _ => None,
+    }
+    .unwrap_or_else(|| text_content(el))
+}
+
+fn u_value(el: &ElementRef, base: Option<&Url>) -> String {
+    let tag = el.value().name();
+
+    let url_attr = match tag {
+        "a" | "area" | "link" => attr(el, "href"),
+        "img" | "audio" | "video" | "source" | "iframe" => attr(el, "src"),
+        "object" => attr(el, "data"),
+        _ => None,
+    };
+
+    match url_attr {
+        Some(u) => resolve(base, u),
+        None => match tag {
+            "abbr" => attr(el, "title").map(String::from),
+            "data" | "input" => attr(el, "value").map(String::from),
+            _ => None,
+        }
+        .unwrap_or_else(|| text_content(el)),
+    }
+}
+
+fn dt_value(el: &ElementRef) -> String {
+    let tag = el.value().name();
+
+    match tag {
+        "time" | "ins" | "del" => attr(el, "datetime").map(String::from),
+        "abbr" => attr(el, "title").map(String::from),
+        "data" | "input" => attr(el, "value").map(String::from),
+        _ => None,
+    }
+    .unwrap_or_else(|| text_content(el))
+}
+
+/// Which explicit properties an item has, which decides what's implied
+#[derive(Default)]
+struct Explicit {
+    /// `p-*` or `e-*` properties
+    text: bool,
+    /// `u-*` properties
+    url: bool,
+    nested: bool,
+}
+
+fn find_explicit(el: &ElementRef, found: &mut Explicit) {
+    for child in el.children().filter_map(ElementRef::wrap) {
+        found.text |= !classes_with_prefix(&child, "p-").is_empty()
+            || !classes_with_prefix(&child, "e-").is_empty();
+        found.url |= !classes_with_prefix(&child, "u-").is_empty();
+
+        // A nested item's own properties belong to it
+        if classes_with_prefix(&child, "h-").is_empty() {
+            find_explicit(&child, found);
+        } else {
+            found.nested = true;
+        }
+    }
+}
+
+/// Parse the item rooted at `el`, which must have at least one `h-*` class
+fn parse_item(el: &ElementRef, base: Option<&Url>) -> Item {
+    let mut item = Item {
+        types: classes_with_prefix(el, "h-")
+            .into_iter()
+            .map(String::from)
+            .collect(),
+        ..Item::default()
+    };
+
+    for child in el.children().filter_map(ElementRef::wrap) {
+        parse_properties(&child, base, &mut item);
+    }
+
+    let mut explicit = Explicit::default();
+    find_explicit(el, &mut explicit);
+
+    // Implied name: an item with no text properties or nested items is named by its text
+    if !item.properties.contains_key("name") && !explicit.text && !explicit.nested {
+        let name = match el.value().name() {
+            "img" | "area" => attr(el, "alt").map(String::from),
+            "abbr" => attr(el, "title").map(String::from),
+            _ => None,
+        }
+        .unwrap_or_else(|| text_content(el));
+
+        item.properties
+            .insert("name".into(), vec![PropertyValue::Text(name)]);
+    }
+
+    // Implied url, for the common case of an h-card that is itself a link
+    if !item.properties.contains_key("url") && !explicit.url && !explicit.nested {
+        if let Some(href) = (el.value().name() == "a").then(|| attr(el, "href")).flatten() {
+            item.properties
+                .insert("url".into(), vec![PropertyValue::Text(resolve(base, href))]);
+        }
+    }
+
+    item.types.sort();
+    item
+}
+
+fn parse_properties(el: &ElementRef, base: Option<&Url>, parent: &mut Item) {
+    let property_classes = ["p-", "u-", "dt-", "e-"]
+        .iter()
+        .flat_map(|prefix| {
+            classes_with_prefix(el, prefix)
+                .into_iter()
+                .map(move |c| (*prefix, c[prefix.len()..].to_string()))
+        })
+        .collect::<Vec<_>>();
+
+    let is_item = !classes_with_prefix(el, "h-").is_empty();
+
+    if is_item {
+        let mut nested = parse_item(el, base);
+
+        if property_classes.is_empty() {
+            parent.children.push(nested);
+        } else {
+            for (prefix, name) in property_classes {
+                nested.value = Some(match prefix {
+                    "u-" => u_value(el, base),
+                    "p-" => nested.first_text("name").unwrap_or_default(),
+                    _ => text_content(el),
+                });
+
+                parent
+                    .properties
+                    .entry(name)
+                    .or_default()
+                    .push(PropertyValue::Item(Box::new(nested.clone())));
+            }
+        }
+
+        return;
+    }
+
+    for (prefix, name) in property_classes {
+        let value = match prefix {
+            "p-" => PropertyValue::Text(p_value(el)),
+            "u-" => PropertyValue::Text(u_value(el, base)),
+            "dt-" => PropertyValue::Text(dt_value(el)),
+            _ => PropertyValue::Html {
+                html: el.inner_html().trim().to_string(),
+                value: text_content(el),
+            },
+        };
+
+        parent.properties.entry(name).or_default().push(value);
+    }
+
+    for child in el.children().filter_map(ElementRef::wrap) {
+        parse_properties(&child, base, parent);
+    }
+}
+
+fn find_top_level_items(el: &ElementRef, base: Option<&Url>, items: &mut Vec<Item>) {
+    for child in el.children().filter_map(ElementRef::wrap) {
+        if classes_with_prefix(&child, "h-").is_empty() {
+            find_top_level_items(&child, base, items);
+        } else {
+            items.push(parse_item(&child, base));
+        }
+    }
+}
+
+/// Parse all the top level microformats items in an html document.
+/// Relative urls are resolved against `base_url` when it's given
+pub fn parse(html: &str, base_url: Option<&Url>) -> Document {
+    let document = Html::parse_document(html);
+    let root = document.root_element();
+    let mut items = Vec::new();
+
+    if classes_with_prefix(&root, "h-").is_empty() {
+        find_top_level_items(&root, base_url, &mut items);
+    } else {
+        items.push(parse_item(&root, base_url));
+    }
+
+    Document { items }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn items(html: &str, base: Option<&str>) -> Value {
+        let base = base.map(|b| Url::parse(b).unwrap());
+        serde_json::to_value(parse(html, base.as_ref()).items).unwrap()
+    }
+
+    // The examples below are from <https://microformats.org/wiki/microformats2>
+    // and <https://microformats.org/wiki/microformats2-parsing>
+
+    #[test]
+    fn implies_name_and_url() {
+        assert_eq!(
+            items(
+                r#"<a class="h-card" href="http://benward.me">Ben Ward</a>"#,
+                None
+            ),
+            json!([{
+                "type": ["h-card"],
+                "properties": {"name": ["Ben Ward"], "url": ["http://benward.me"]},
+            }])
+        );
+
+        assert_eq!(
+            items(r#"<span class="h-card">Frances Berriman</span>"#, None),
+            json!([{"type": ["h-card"], "properties": {"name": ["Frances Berriman"]}}])
+        );
+
+        let doc = parse(
+            r#"<img class="h-card" src="https://example.org/photo.png" alt="Chris Messina" />"#,
+            None,
+        );
+        assert_eq!(
+            doc.find("h-card").unwrap().first_text("name").as_deref(),
+            Some("Chris Messina")
+        );
+    }
+
+    #[test]
+    fn parses_an_entry_with_a_nested_author() {
+        let html = r#"
+            <article class="h-entry">
+              <h1 class="p-name">Microformats are amazing</h1>
+              <p>Published by <a class="p-author h-card" href="http://example.com">W. Developer</a>
+                 on <time class="dt-published" datetime="2013-06-13 12:00:00">13<sup>th</sup> June 2013</time></p>
+              <p class="p-summary">In which I extoll the virtues of using microformats.</p>
+              <div class="e-content">
+                <p>Blah blah blah</p>
+              </div>
+            </article>"#;
+
+        assert_eq!(
+            items(html, None),
+            json!([{
+                "type": ["h-entry"],
+                "properties": {
+                    "name": ["Microformats are amazing"],
+                    "author": [{
+                        "type": ["h-card"],
+                        "properties": {"name": ["W. Developer"], "url": ["http://example.com"]},
+                        "value": "W. Developer",
+                    }],
+                    "published": ["2013-06-13 12:00:00"],
+                    "summary": ["In which I extoll the virtues of using microformats."],
+                    "content": [{"html": "<p>Blah blah blah</p>", "value": "Blah blah blah"}],
+                },
+            }])
+        );
+    }
+
+    #[test]
+    fn only_implies_without_explicit_properties() {
+        // Text properties or nested items stop the name being implied,
+        // and url properties stop the url being implied
+        assert_eq!(
+            items(
+                r#"<a class="h-entry" href="/implied"><span class="e-content">Hello</span> <span class="u-photo">/me.png</span></a>"#,
+                Some("https://example.com/"),
+            ),
+            json!([{
+                "type": ["h-entry"],
+                "properties": {
+                    "content": [{"html": "Hello", "value": "Hello"}],
+                    "photo": ["/me.png"],
+                },
+            }])
+        );
+
+        assert_eq!(
+            items(
+                r#"<div class="h-feed"><div class="h-entry"><span class="p-name">One</span></div></div>"#,
+                None,
+            ),
+            json!([{
+                "type": ["h-feed"],
+                "properties": {},
+                "children": [{"type": ["h-entry"], "properties": {"name": ["One"]}}],
+            }])
+        );
+    }
+
+    #[test]
+    fn reads_values_from_the_right_attributes() {
+        let html = r#"
+            <div class="h-card">
+              <abbr class="p-name" title="Tantek Çelik">TC</abbr>
+              <img class="u-photo p-nickname" src="photo.jpg" alt="Tantek">
+              <data class="p-note" value="A note">ignored</data>
+              <a class="u-url" href="/about">About</a>
+              <span class="u-uid">https://tantek.com/</span>
+            </div>"#;
+
+        assert_eq!(
+            items(html, Some("https://tantek.com/people/")),
+            json!([{
+                "type": ["h-card"],
+                "properties": {
+                    "name": ["Tantek Çelik"],
+                    "photo": ["https://tantek.com/people/photo.jpg"],
+                    "nickname": ["Tantek"],
+                    "note": ["A note"],
+                    "url": ["https://tantek.com/about"],
+                    "uid": ["https://tantek.com/"],
+                },
+            }])
+        );
+    }
+
+    #[test]
+    fn ignores_invalid_class_names() {
+        assert_eq!(
+            items(
+                r#"<div class="h-entry h- H-card"><span class="p-Name p-">x</span><span class="p-summary">y</span></div>"#,
+                None,
+            ),
+            json!([{"type": ["h-entry"], "properties": {"summary": ["y"]}}])
+        );
+
+        assert_eq!(items(r#"<div class="hentry">Old</div>"#, None), json!([]));
+    }
+}
@@ -0,0 +1,915 @@
+//! Micropub endpoint, so posts can be published from standard clients.
+//! See <https://micropub.spec.indieweb.org/>
+//!
+//! Posts are h-entries: `name` is the title, `content` the markdown,
+//! `category` the tags and `published` the timestamp.
+
+use crate::{
+    blog::{
+        db,
+        render::{self, read_file_contents},
+    },
+    common::{self, Post},
+    config, fetch,
+    ratelimit::{self, ClientIp},
+    route::{save_post, SiteError},
+    webmention,
+};
+use axum::{
+    body::Bytes,
+    extract::RawQuery,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use url::{form_urlencoded, Url};
+
+use std::{fs, net::IpAddr, path::PathBuf};
+
+/// Longest slug generated from a post's name or content
+const MAX_SLUG_CHARS: usize = 60;
+
+/// Longest title taken from the content of a post without a name
+const MAX_IMPLIED_TITLE_CHARS: usize = 60;
+
+/// Micropub errors are json, with one of the error codes from the spec.
+/// Anything unexpected is reported the same way as the rest of the site
+pub enum MicropubError {
+    Request {
+        status: StatusCode,
+        error: &'static str,
+        description: String,
+    },
+    Site(SiteError),
+}
+
+impl MicropubError {
+    fn new(status: StatusCode, error: &'static str, description: impl Into<String>) -> Self {
+        MicropubError::Request {
+            status,
+            error,
+            description: description.into(),
+        }
+    }
+}
+
+fn invalid_request(description: impl Into<String>) -> MicropubError {
+    MicropubError::new(StatusCode::BAD_REQUEST, "invalid_request", description)
+}
+
+impl<E> From<E> for MicropubError
+where
+    E: Into<anyhow::Error>,
+{
+    fn from(err: E) -> Self {
+        MicropubError::Site(SiteError::from(err))
+    }
+}
+
+impl IntoResponse for MicropubError {
+    fn into_response(self) -> Response {
+        match self {
+            MicropubError::Request {
+                status,
+                error,
+                description,
+            } => {
+                let body = json!({
+                    "error": error,
+                    "error_description": description,
+                });
+
+                (status, Json(body)).into_response()
+            }
+            MicropubError::Site(e) => e.into_response(),
+        }
+    }
+}
+
+/// What a token is allowed to do, and who it belongs to
+struct Authorization {
+    author: String,
+    scopes: Option<Vec<String>>,
+}
+
+impl Authorization {
+    /// Admin keys can do anything, IndieAuth tokens only what they were granted.
+    /// "post" is the old name for "create"
+    fn require(&self, scope: &str) -> Result<(), MicropubError> {
+        match &self.scopes {
+            None => Ok(()),
+            Some(scopes)
+                if scopes
+                    .iter()
+                    .any(|s| s == scope || (scope == "create" && s == "post")) =>
+            {
+                Ok(())
+            }
+            Some(_) => Err(MicropubError::new(
+                StatusCode::FORBIDDEN,
+                "insufficient_scope",
+                format!("This token doesn't have the {scope:?} scope"),
+            )),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenInfo {
+    me: String,
+    #[serde(default)]
+    client_id: Option<String>,
+    #[serde(default)]
+    scope: String,
+}
+
+/// Ask the configured token endpoint about a token, the way an IndieAuth client would
+async fn verify_with_token_endpoint(
+    endpoint: &str,
+    token: &str,
+) -> anyhow::Result<Option<Authorization>> {
+    let response = fetch::settings_client()
+        .get(endpoint)
+        .bearer_auth(token)
+        .header(reqwest::header::ACCEPT, "application/json")
+        .send()
+        .await?;
+
+    if !response.status().is_success() {
+        return Ok(None);
+    }
+
+    let info = serde_json::from_slice::<TokenInfo>(&response.bytes().await?)?;
+    let site = Url::parse(&config::settings().site_url)?;
+
+    if Url::parse(&info.me)?.host_str() != site.host_str() {
+        return Ok(None);
+    }
+
+    Ok(Some(Authorization {
+        author: info.client_id.unwrap_or(info.me),
+        scopes: Some(info.scope.split_whitespace().map(String::from).collect()),
+    }))
+}
+
+/// Tokens come in the Authorization header, or as `access_token` in a form body.
+/// Tokens that are neither an admin key nor accepted by the token endpoint count towards
+/// the admin lockout, since admin keys can be guessed here too
+async fn authorize(
+    ip: IpAddr,
+    headers: &HeaderMap,
+    form_token: Option<&str>,
+) -> Result<Authorization, MicropubError> {
+    if let Some(left) = ratelimit::admin_locked_for(&ip) {
+        return Err(MicropubError::new(
+            StatusCode::TOO_MANY_REQUESTS,
+            "too_many_requests",
+            format!(
+                "Too many invalid access tokens, try again in {} seconds",
+                left.as_secs().max(1)
+            ),
+        ));
+    }
+
+    let header_token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+
+    let token = match header_token.or(form_token) {
+        Some(t) if !t.trim().is_empty() => t.trim(),
+        _ => {
+            return Err(MicropubError::new(
+                StatusCode::UNAUTHORIZED,
+                "unauthorized",
+                "No access token was given",
+            ))
+        }
+    };
+
+    if let Some(author) = common::token_key_name(token) {
+        ratelimit::record_admin_key(&ip, true);
+        return Ok(Authorization {
+            author,
+            scopes: None,
+        });
+    }
+
+    let forbidden =
+        || MicropubError::new(StatusCode::FORBIDDEN, "forbidden", "Invalid access token");
+
+    let rejected = || {
+        ratelimit::record_admin_key(&ip, false);
+        forbidden()
+    };
+
+    match &config::settings().token_endpoint {
+        Some(endpoint) => match verify_with_token_endpoint(endpoint, token).await {
+            Ok(Some(auth)) => {
+                ratelimit::record_admin_key(&ip, true);
+                Ok(auth)
+            }
+            Ok(None) => Err(rejected()),
+            // Not the client's fault, so it doesn't count against them
+            Err(e) => {
+                tracing::warn!("Could not verify token with {endpoint}: {e:?}");
+                Err(forbidden())
+            }
+        },
+        None => Err(rejected()),
+    }
+}
+
+/// Plain text form of a property value. Content may be an object with `html` or `value`
+fn value_text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Object(o) => o
+            .get("html")
+            .or_else(|| o.get("value"))
+            .and_then(|v| v.as_str())
+            .map(String::from),
+        _ => None,
+    }
+}
+
+fn first_text(properties: &Map<String, Value>, name: &str) -> Option<String> {
+    properties
+        .get(name)
+        .and_then(|v| v.as_array())
+        .and_then(|values| values.first())
+        .and_then(value_text)
+        .filter(|t| !t.trim().is_empty())
+}
+
+fn all_text(properties: &Map<String, Value>, name: &str) -> Vec<String> {
+    properties
+        .get(name)
+        .and_then(|v| v.as_array())
+        .map(|values| values.iter().filter_map(value_text).collect())
+        .unwrap_or_default()
+}
+
+/// Turn some text into something usable as a slug
+fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+
+    for c in text.chars() {
+        match c {
+            c if c.is_ascii_alphanumeric() => slug.push(c.to_ascii_lowercase()),
+            _ if slug.ends_with('-') || slug.is_empty() => (),
+            _ => slug.push('-'),
+        }
+
+        if slug.len() >= MAX_SLUG_CHARS {
+            break;
+        }
+    }
+
+    slug.trim_end_matches('-').to_string()
+}
+
+/// A post as described by micropub properties, before it's saved
+struct Draft {
+    title: String,
+    content: String,
+    tags: Vec<String>,
+    timestamp: usize,
+}
+
+impl Draft {
+    fn from_properties(properties: &Map<String, Value>) -> Result<Self, MicropubError> {
+        let name = first_text(properties, "name");
+        let body = first_text(properties, "content").unwrap_or_default();
+
+        if name.is_none() && body.trim().is_empty() {
+            return Err(invalid_request("A post needs a name or some content"));
+        }
+
+        let title = name.clone().unwrap_or_else(|| {
+            let first_line = body
+                .lines()
+                .find(|l| !l.trim().is_empty())
+                .unwrap_or_default();
+            first_line.chars().take(MAX_IMPLIED_TITLE_CHARS).collect()
+        });
+
+        // Existing posts start with their title as a heading, so micropub ones do too
+        let content = match &name {
+            Some(n) => format!("# {n}\n\n{body}\n"),
+            None => format!("{body}\n"),
+        };
+
+        let timestamp = match first_text(properties, "published") {
+            Some(p) => DateTime::parse_from_rfc3339(&p)
+                .map_err(|_| invalid_request(format!("Invalid published date {p:?}")))?
+                .timestamp() as usize,
+            None => common::now_timestamp(),
+        };
+
+        Ok(Draft {
+            title,
+            content,
+            tags: common::normalize_tags(&all_text(properties, "category")),
+            timestamp,
+        })
+    }
+
+    fn post(&self, slug: &str) -> Post {
+        Post {
+            title: self.title.clone(),
+            timestamp: self.timestamp,
+            slug: slug.into(),
+            tags: self.tags.clone(),
+        }
+    }
+}
+
+/// The post's properties as a micropub client would see them, with markdown as the content
+fn post_properties(post: &Post) -> anyhow::Result<Map<String, Value>> {
+    let md = read_file_contents(post.md_path())?;
+    let heading = format!("# {}", post.title);
+
+    let content = match md.strip_prefix(&heading) {
+        Some(rest) => rest.trim(),
+        None => md.trim(),
+    };
+
+    let published = DateTime::<Utc>::from_timestamp(post.timestamp as i64, 0)
+        .map(|dt| dt.to_rfc3339_opts(SecondsFormat::Secs, true))
+        .unwrap_or_default();
+
+    let mut properties = Map::new();
+    properties.insert("name".into(), json!([post.title]));
+    properties.insert("content".into(), json!([content]));
+    properties.insert("published".into(), json!([published]));
+    properties.insert("url".into(), json!([webmention::post_url(&post.slug)]));
+    properties.insert("mp-slug".into(), json!([post.slug]));
+
+    if !post.tags.is_empty() {
+        properties.insert("category".into(), json!(post.tags));
+    }
+
+    Ok(properties)
+}
+
+/// Find the post a micropub `url` refers to
+fn post_for_url(conn: &db::DbConnection, url: &str) -> Result<Post, MicropubError> {
+    let slug = Url::parse(url)
+        .ok()
+        .and_then(|u| webmention::target_post_slug(&u, conn).ok().flatten())
+        .ok_or_else(|| invalid_request(format!("{url:?} isn't a post on this site")))?;
+
+    conn.get(&slug).map_err(|e| invalid_request(e.to_string()))
+}
+
+/// A slug for a new post that doesn't clash with an existing one
+fn unique_slug(conn: &db::DbConnection, base: &str) -> anyhow::Result<String> {
+    let mut slug = base.to_string();
+    let mut n = 2;
+
+    let md_path =
+        |slug: &str| PathBuf::from(common::POSTS_MARKDOWN_PATH).join(format!("{slug}.md"));
+
+    while conn.find(&slug)?.is_some() || md_path(&slug).try_exists()? {
+        slug = format!("{base}-{n}");
+        n += 1;
+    }
+
+    Ok(slug)
+}
+
+enum Action {
+    Create(Map<String, Value>),
+    Update {
+        url: String,
+        replace: Map<String, Value>,
+        add: Map<String, Value>,
+        delete: Value,
+    },
+    Delete(String),
+    Undelete,
+}
+
+fn parse_json(body: &[u8]) -> Result<Action, MicropubError> {
+    let request: Value =
+        serde_json::from_slice(body).map_err(|e| invalid_request(format!("Invalid json: {e}")))?;
+
+    let url = || {
+        request
+            .get("url")
+            .and_then(|u| u.as_str())
+            .map(String::from)
+            .ok_or_else(|| invalid_request("Missing url"))
+    };
+
+    let object = |key: &str| {
+        request
+            .get(key)
+            .and_then(|v| v.as_object())
+            .cloned()
+            .unwrap_or_default()
+    };
+
+    match request.get("action").and_then(|a| a.as_str()) {
+        None | Some("create") => {
+            let is_entry = request
+                .get("type")
+                .and_then(|t| t.as_array())
+                .is_some_and(|t| t.iter().any(|t| t == "h-entry"));
+
+            if !is_entry {
+                return Err(invalid_request("Only h-entry posts are supported"));
+            }
+
+            Ok(Action::Create(object("properties")))
+        }
+        Some("update") => Ok(Action::Update {
+            url: url()?,
+            replace: object("replace"),
+            add: object("add"),
+            delete: request.get("delete").cloned().unwrap_or(Value::Null),
+        }),
+        Some("delete") => Ok(Action::Delete(url()?)),
+        Some("undelete") => Ok(Action::Undelete),
+        Some(a) => Err(invalid_request(format!("Unknown action {a:?}"))),
+    }
+}
+
+/// Form requests can create, delete and undelete. Giving the token in the form is allowed too
+fn parse_form(body: &[u8]) -> Result<(Action, Option<String>), MicropubError> {
+    let mut properties = Map::new();
+    let mut h = None;
+    let mut action = None;
+    let mut url = None;
+    let mut token = None;
+
+    for (key, value) in form_urlencoded::parse(body) {
+        match key.as_ref() {
+            "h" => h = Some(value.into_owned()),
+            "action" => action = Some(value.into_owned()),
+            "url" => url = Some(value.into_owned()),
+            "access_token" => token = Some(value.into_owned()),
+            k => {
+                let name = k.trim_end_matches("[]");
+
+                if let Value::Array(values) = properties
+                    .entry(name.to_string())
+                    .or_insert_with(|| Value::Array(Vec::new()))
+                {
+                    values.push(Value::String(value.into_owned()));
+                }
+            }
+        }
+    }
+
+    let url = || url.clone().ok_or_else(|| invalid_request("Missing url"));
+
+    let parsed = match action.as_deref() {
+        None | Some("create") => match h.as_deref() {
+            Some("entry") | None => Action::Create(properties),
+            Some(h) => return Err(invalid_request(format!("Unsupported type h-{h}"))),
+        },
+        Some("delete") => Action::Delete(url()?),
+        Some("undelete") => Action::Undelete,
+        Some("update") => return Err(invalid_request("Updates must be sent as json")),
+        Some(a) => return Err(invalid_request(format!("Unknown action {a:?}"))),
+    };
+
+    Ok((parsed, token))
+}
+
+/// Apply a json update's replace, add and delete to a post's properties
+fn apply_update(
+    properties: &mut Map<String, Value>,
+    replace: Map<String, Value>,
+    add: Map<String, Value>,
+    delete: Value,
+) -> Result<(), MicropubError> {
+    for (name, values) in replace {
+        properties.insert(name, values);
+    }
+
+    for (name, values) in add {
+        let added = values
+            .as_array()
+            .cloned()
+            .ok_or_else(|| invalid_request(format!("Values to add to {name:?} must be a list")))?;
+
+        if let Value::Array(existing) = properties
+            .entry(name)
+            .or_insert_with(|| Value::Array(Vec::new()))
+        {
+            existing.extend(added);
+        }
+    }
+
+    match delete {
+        Value::Null => (),
+        Value::Array(names) => {
+            for name in names.iter().filter_map(|n| n.as_str()) {
+                properties.remove(name);
+            }
+        }
+        Value::Object(removals) => {
+            for (name, values) in removals {
+                let removed = values.as_array().cloned().unwrap_or_default();
+
+                if let Some(Value::Array(existing)) = properties.get_mut(&name) {
+                    existing.retain(|v| !removed.contains(v));
+                }
+            }
+        }
+        _ => return Err(invalid_request("Invalid delete")),
+    }
+
+    Ok(())
+}
+
+async fn create(
+    auth: &Authorization,
+    properties: Map<String, Value>,
+) -> Result<Response, MicropubError> {
+    auth.require("create")?;
+
+    let draft = Draft::from_properties(&properties)?;
+
+    if let Some(problems) = render::lint_errors(&draft.content)? {
+        return Err(invalid_request(problems));
+    }
+
+    let requested_slug = first_text(&properties, "mp-slug")
+        .map(|s| slugify(&s))
+        .filter(|s| common::is_valid_slug(s));
+
+    let base_slug = requested_slug
+        .or_else(|| Some(slugify(&draft.title)).filter(|s| common::is_valid_slug(s)))
+        .unwrap_or_else(|| draft.timestamp.to_string());
+
+    let slug = unique_slug(&db::DbConnection::new()?, &base_slug)?;
+
+    save_post(&draft.post(&slug), &draft.content, false, &auth.author)?;
+
+    Ok((
+        StatusCode::CREATED,
+        [(header::LOCATION, webmention::post_url(&slug))],
+    )
+        .into_response())
+}
+
+async fn update(
+    auth: &Authorization,
+    url: &str,
+    replace: Map<String, Value>,
+    add: Map<String, Value>,
+    delete: Value,
+) -> Result<Response, MicropubError> {
+    auth.require("update")?;
+
+    let post = post_for_url(&db::DbConnection::new()?, url)?;
+
+    let mut properties = post_properties(&post)?;
+
+    apply_update(&mut properties, replace, add, delete)?;
+
+    let draft = Draft::from_properties(&properties)?;
+
+    if let Some(problems) = render::lint_errors(&draft.content)? {
+        return Err(invalid_request(problems));
+    }
+
+    save_post(&draft.post(&post.slug), &draft.content, true, &auth.author)?;
+
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+async fn delete(auth: &Authorization, url: &str) -> Result<Response, MicropubError> {
+    auth.require("delete")?;
+
+    let conn = db::DbConnection::new()?;
+
+    let post = post_for_url(&conn, url)?;
+
+    // The file goes first, so if it can't be removed nothing has changed. A row removed
+    // without its file would come back the next time the database is opened
+    fs::remove_file(post.md_path())?;
+    conn.delete_post(&post.slug)?;
+
+    tracing::info!("{} deleted post {}", auth.author, post.slug);
+
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+/// Micropub create, update and delete, as either json or a form
+pub async fn post_micropub(
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, MicropubError> {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+
+    let parsed = if content_type.starts_with("application/json") {
+        parse_json(&body).map(|a| (a, None))
+    } else if content_type.starts_with("application/x-www-form-urlencoded") {
+        parse_form(&body)
+    } else {
+        Err(invalid_request(format!(
+            "Unsupported content type {content_type:?}"
+        )))
+    };
+
+    let (action, form_token) = parsed?;
+
+    let auth = authorize(ip, &headers, form_token.as_deref()).await?;
+
+    match action {
+        Action::Create(properties) => create(&auth, properties).await,
+        Action::Update {
+            url,
+            replace,
+            add,
+            delete: removals,
+        } => update(&auth, &url, replace, add, removals).await,
+        Action::Delete(url) => delete(&auth, &url).await,
+        Action::Undelete => Err(invalid_request(
+            "Deleted posts can't be undeleted, restore a revision from the admin api instead",
+        )),
+    }
+}
+
+/// Micropub queries: `q=config`, `q=syndicate-to` and `q=source`
+pub async fn get_micropub(
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
+    RawQuery(query): RawQuery,
+) -> Result<Response, MicropubError> {
+    let pairs = form_urlencoded::parse(query.unwrap_or_default().as_bytes())
+        .into_owned()
+        .collect::<Vec<_>>();
+
+    let param = |name: &str| {
+        pairs
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    };
+
+    authorize(ip, &headers, param("access_token")).await?;
+
+    match param("q") {
+        Some("config") => Ok(Json(json!({
+            "q": ["config", "source", "syndicate-to"],
+            "syndicate-to": [],
+            "post-types": [{"type": "article", "name": "Post"}, {"type": "note", "name": "Note"}],
+        }))
+        .into_response()),
+
+        Some("syndicate-to") => Ok(Json(json!({ "syndicate-to": [] })).into_response()),
+
+        Some("source") => {
+            let url = param("url").ok_or_else(|| invalid_request("Missing url"))?;
+
+            let post = post_for_url(&db::DbConnection::new()?, url)?;
+
+            let mut properties = post_properties(&post)?;
+
+            let wanted = pairs
+                .iter()
+                .filter(|(k, _)| k == "properties[]" || k == "properties")
+                .map(|(_, v)| v.as_str())
+                .collect::<Vec<_>>();
+
+            if wanted.is_empty() {
+                Ok(Json(json!({ "type": ["h-entry"], "properties": properties })).into_response())
+            } else {
+                properties.retain(|name, _| wanted.contains(&name.as_str()));
+                Ok(Json(json!({ "properties": properties })).into_response())
+            }
+        }
+
+        Some(q) => Err(invalid_request(format!("Unsupported query {q:?}"))),
+        None => Err(invalid_request("Missing q")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, json_body, ADMIN_KEY};
+
+    /// Clients that aren't locked out, unlike the one `bad_tokens_lock_out_the_client` uses
+    const CLIENT: [u8; 4] = [192, 0, 2, 60];
+
+    fn admin_headers(content_type: Option<&str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            format!("Bearer {ADMIN_KEY}").parse().unwrap(),
+        );
+        if let Some(content_type) = content_type {
+            headers.insert(header::CONTENT_TYPE, content_type.parse().unwrap());
+        }
+        headers
+    }
+
+    async fn post_form(body: &str) -> Response {
+        post_micropub(
+            ClientIp(CLIENT.into()),
+            admin_headers(Some("application/x-www-form-urlencoded")),
+            Bytes::from(body.to_string()),
+        )
+        .await
+        .into_response()
+    }
+
+    async fn post_json(body: Value) -> Response {
+        post_micropub(
+            ClientIp(CLIENT.into()),
+            admin_headers(Some("application/json")),
+            Bytes::from(body.to_string()),
+        )
+        .await
+        .into_response()
+    }
+
+    async fn query(query: &str) -> Response {
+        get_micropub(
+            ClientIp(CLIENT.into()),
+            admin_headers(None),
+            RawQuery(Some(query.into())),
+        )
+        .await
+        .into_response()
+    }
+
+    fn location(response: &Response) -> String {
+        response.headers()[header::LOCATION]
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    async fn source(url: &str) -> Value {
+        let encoded = form_urlencoded::byte_serialize(url.as_bytes()).collect::<String>();
+        let response = query(&format!("q=source&url={encoded}")).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        json_body(response).await["properties"].clone()
+    }
+
+    #[tokio::test]
+    async fn config_lists_queries_and_post_types() {
+        let response = query("q=config").await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let config = json_body(response).await;
+        assert_eq!(config["q"], json!(["config", "source", "syndicate-to"]));
+        assert_eq!(config["post-types"][0]["type"], "article");
+    }
+
+    #[tokio::test]
+    async fn form_posts_are_created_and_deleted() {
+        let _posts = testing::posts().await;
+
+        let response = post_form(
+            "h=entry&name=From+a+form&content=Posted+as+a+form&category[]=rust&category[]=web&mp-slug=form-post",
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let url = location(&response);
+        assert_eq!(url, webmention::post_url("form-post"));
+
+        let properties = source(&url).await;
+        assert_eq!(properties["name"], json!(["From a form"]));
+        assert_eq!(properties["content"], json!(["Posted as a form"]));
+        assert_eq!(properties["category"], json!(["rust", "web"]));
+
+        let post = db::DbConnection::new().unwrap().get("form-post").unwrap();
+        assert_eq!(
+            fs::read_to_string(post.md_path()).unwrap(),
+            "# From a form\n\nPosted as a form\n"
+        );
+
+        let response = post_form(&format!(
+            "action=delete&url={}",
+            form_urlencoded::byte_serialize(url.as_bytes()).collect::<String>()
+        ))
+        .await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        assert!(!post.md_path().exists());
+        assert!(db::DbConnection::new()
+            .unwrap()
+            .find("form-post")
+            .unwrap()
+            .is_none());
+
+        let encoded = form_urlencoded::byte_serialize(url.as_bytes()).collect::<String>();
+        let response = query(&format!("q=source&url={encoded}")).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn json_posts_are_created_updated_and_deleted() {
+        let _posts = testing::posts().await;
+
+        let response = post_json(json!({
+            "type": ["h-entry"],
+            "properties": {
+                "content": ["A note without a name"],
+                "published": ["2024-01-02T03:04:05Z"],
+            },
+        }))
+        .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        // Notes are titled, and so slugged, by their first line
+        let url = location(&response);
+        assert_eq!(url, webmention::post_url("a-note-without-a-name"));
+
+        let properties = source(&url).await;
+        assert_eq!(properties["name"], json!(["A note without a name"]));
+        assert_eq!(properties["published"], json!(["2024-01-02T03:04:05Z"]));
+        assert!(properties.get("category").is_none());
+
+        let response = post_json(json!({
+            "action": "update",
+            "url": url,
+            "replace": {"content": ["Rewritten"]},
+            "add": {"category": ["notes"]},
+        }))
+        .await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let properties = source(&url).await;
+        assert_eq!(properties["content"], json!(["Rewritten"]));
+        assert_eq!(properties["category"], json!(["notes"]));
+        assert_eq!(properties["published"], json!(["2024-01-02T03:04:05Z"]));
+
+        // Only the properties asked for
+        let encoded = form_urlencoded::byte_serialize(url.as_bytes()).collect::<String>();
+        let response = query(&format!("q=source&url={encoded}&properties[]=category")).await;
+        assert_eq!(
+            json_body(response).await,
+            json!({"properties": {"category": ["notes"]}})
+        );
+
+        let response = post_json(json!({"action": "delete", "url": url})).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(db::DbConnection::new()
+            .unwrap()
+            .find("a-note-without-a-name")
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn bad_tokens_lock_out_the_client() {
+        let ip = IpAddr::from([192, 0, 2, 50]);
+        let query = || RawQuery(Some("q=config&access_token=not-a-key".into()));
+
+        for _ in 0..config::settings().auth_failure_limit {
+            let response = get_micropub(ClientIp(ip), HeaderMap::new(), query())
+                .await
+                .into_response();
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        }
+
+        let response = get_micropub(ClientIp(ip), HeaderMap::new(), query())
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        // Posting is locked out too, even before the token is looked at
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::CONTENT_TYPE,
+            "application/x-www-form-urlencoded".parse().unwrap(),
+        );
+        let response = post_micropub(
+            ClientIp(ip),
+            headers,
+            Bytes::from("h=entry&content=hi&access_token=not-a-key"),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        // Other clients aren't affected
+        let response = get_micropub(
+            ClientIp(IpAddr::from([192, 0, 2, 51])),
+            HeaderMap::new(),
+            query(),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+}
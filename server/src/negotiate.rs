@@ -0,0 +1,204 @@
+//! Picking a representation of a post from the request's `Accept` header.
+//! See <https://httpwg.org/specs/rfc9110.html#field.accept>
+
+use axum::http::{header, HeaderMap};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostFormat {
+    Html,
+    Markdown,
+    PlainText,
+    Json,
+}
+
+impl PostFormat {
+    /// In order of preference, for when the client likes several equally
+    const ALL: [PostFormat; 4] = [
+        PostFormat::Html,
+        PostFormat::Markdown,
+        PostFormat::PlainText,
+        PostFormat::Json,
+    ];
+
+    pub fn media_type(&self) -> &'static str {
+        match self {
+            PostFormat::Html => "text/html",
+            PostFormat::Markdown => "text/markdown",
+            PostFormat::PlainText => "text/plain",
+            PostFormat::Json => "application/json",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            PostFormat::Html => "text/html; charset=utf-8",
+            PostFormat::Markdown => "text/markdown; charset=utf-8",
+            PostFormat::PlainText => "text/plain; charset=utf-8",
+            PostFormat::Json => "application/json",
+        }
+    }
+}
+
+/// One entry of an Accept header, e.g. `text/*;q=0.5`
+struct MediaRange<'a> {
+    kind: &'a str,
+    subtype: &'a str,
+    q: f32,
+}
+
+impl<'a> MediaRange<'a> {
+    fn parse(entry: &'a str) -> Option<Self> {
+        let mut params = entry.split(';').map(str::trim);
+        let (kind, subtype) = params.next()?.split_once('/')?;
+
+        let q = params
+            .filter_map(|p| p.split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+            .and_then(|(_, v)| v.trim().parse::<f32>().ok())
+            .filter(|q| !q.is_nan())
+            .map_or(1.0, |q| q.clamp(0.0, 1.0));
+
+        Some(Self { kind, subtype, q })
+    }
+
+    /// How specific the match is, so `text/plain;q=0` beats `text/*`
+    fn matches(&self, media_type: &str) -> Option<u8> {
+        let (kind, subtype) = media_type.split_once('/')?;
+
+        match (self.kind, self.subtype) {
+            ("*", "*") => Some(0),
+            (k, "*") if k.eq_ignore_ascii_case(kind) => Some(1),
+            (k, s) if k.eq_ignore_ascii_case(kind) && s.eq_ignore_ascii_case(subtype) => Some(2),
+            _ => None,
+        }
+    }
+}
+
+/// How much the client wants `media_type`, from the most specific range that matches it
+fn quality(ranges: &[MediaRange], media_type: &str) -> f32 {
+    ranges
+        .iter()
+        .filter_map(|r| r.matches(media_type).map(|specificity| (specificity, r.q)))
+        .max_by_key(|(specificity, _)| *specificity)
+        .map(|(_, q)| q)
+        .unwrap_or(0.0)
+}
+
+/// The format the client prefers, or `None` if it accepts none of them.
+/// No Accept header at all means anything goes, which gets html
+pub fn post_format(headers: &HeaderMap) -> Option<PostFormat> {
+    let accept = match headers.get(header::ACCEPT).and_then(|v| v.to_str().ok()) {
+        None => return Some(PostFormat::Html),
+        Some(a) if a.trim().is_empty() => return Some(PostFormat::Html),
+        Some(a) => a,
+    };
+
+    let ranges = accept
+        .split(',')
+        .filter_map(MediaRange::parse)
+        .collect::<Vec<_>>();
+
+    // Ties go to the format earlier in ALL
+    PostFormat::ALL
+        .iter()
+        .enumerate()
+        .map(|(preference, f)| (preference, *f, quality(&ranges, f.media_type())))
+        .filter(|(_, _, q)| *q > 0.0)
+        .max_by(|(pa, _, a), (pb, _, b)| a.total_cmp(b).then(pb.cmp(pa)))
+        .map(|(_, f, _)| f)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn format_for(accept: &str) -> Option<PostFormat> {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, HeaderValue::from_str(accept).unwrap());
+        post_format(&headers)
+    }
+
+    #[test]
+    fn missing_or_empty_accept_gets_html() {
+        assert_eq!(post_format(&HeaderMap::new()), Some(PostFormat::Html));
+        assert_eq!(format_for(""), Some(PostFormat::Html));
+        assert_eq!(format_for("  "), Some(PostFormat::Html));
+    }
+
+    #[test]
+    fn exact_types_are_picked() {
+        assert_eq!(format_for("text/markdown"), Some(PostFormat::Markdown));
+        assert_eq!(format_for("TEXT/Plain"), Some(PostFormat::PlainText));
+        assert_eq!(format_for("application/json"), Some(PostFormat::Json));
+        assert_eq!(format_for("image/png"), None);
+        assert_eq!(format_for("not a media type"), None);
+    }
+
+    #[test]
+    fn wildcards_match_by_preference() {
+        assert_eq!(format_for("*/*"), Some(PostFormat::Html));
+        assert_eq!(format_for("application/*"), Some(PostFormat::Json));
+        assert_eq!(format_for("text/*, image/*"), Some(PostFormat::Html));
+    }
+
+    #[test]
+    fn zero_quality_excludes() {
+        assert_eq!(format_for("text/html;q=0"), None);
+        assert_eq!(format_for("*/*, text/html;q=0"), Some(PostFormat::Markdown));
+        assert_eq!(
+            format_for("text/*;q=0, application/json;q=0.1"),
+            Some(PostFormat::Json)
+        );
+    }
+
+    #[test]
+    fn specific_ranges_outrank_wildcards() {
+        // text/* only decides for text/plain, which has no range of its own
+        assert_eq!(
+            format_for("text/*;q=0.9, text/html;q=0.1, text/markdown;q=0.2"),
+            Some(PostFormat::PlainText)
+        );
+        assert_eq!(
+            format_for("*/*;q=0.1, application/json"),
+            Some(PostFormat::Json)
+        );
+    }
+
+    #[test]
+    fn higher_quality_wins() {
+        assert_eq!(
+            format_for("text/html;q=0.5, text/markdown;q=0.8"),
+            Some(PostFormat::Markdown)
+        );
+        assert_eq!(
+            format_for("text/plain; Q=0.9, application/json"),
+            Some(PostFormat::Json)
+        );
+    }
+
+    #[test]
+    fn ties_go_to_the_preferred_format() {
+        assert_eq!(
+            format_for("application/json, text/markdown"),
+            Some(PostFormat::Markdown)
+        );
+        assert_eq!(
+            format_for("text/plain;q=0.5, text/html;q=0.5"),
+            Some(PostFormat::Html)
+        );
+    }
+
+    #[test]
+    fn quality_is_clamped() {
+        assert_eq!(
+            format_for("application/json;q=5, text/html"),
+            Some(PostFormat::Html)
+        );
+        assert_eq!(format_for("text/html;q=-1"), None);
+        assert_eq!(
+            format_for("text/html;q=NaN, application/json;q=0.5"),
+            Some(PostFormat::Html)
+        );
+    }
+}
@@ -0,0 +1,165 @@
+//! The rotating quote in the page header. Quotes are picked on the server, so pages
+//! don't need JavaScript or the whole list to show one.
+
+use crate::{
+    blog::db::{self, Quote},
+    common,
+    config::{self, QuoteRotation},
+    route::{require_author, SiteError},
+};
+use axum::{
+    extract::{self, Json, Query},
+    http::StatusCode,
+};
+use axum_auth::AuthBearer;
+use chrono::Utc;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use std::sync::Arc;
+
+pub const MAX_QUOTE_CHARS: usize = 300;
+
+/// Every quote, kept in memory so pages don't go to the database for one.
+/// Emptied whenever quotes are added or deleted, and loaded again when next needed
+static QUOTES: Mutex<Option<Arc<Vec<Quote>>>> = parking_lot::const_mutex(None);
+
+fn cached_quotes() -> anyhow::Result<Arc<Vec<Quote>>> {
+    let mut cached = QUOTES.lock();
+
+    if let Some(quotes) = &*cached {
+        return Ok(quotes.clone());
+    }
+
+    let quotes = Arc::new(db::DbConnection::without_posts_json()?.quotes()?);
+    *cached = Some(quotes.clone());
+
+    Ok(quotes)
+}
+
+fn forget_quotes() {
+    *QUOTES.lock() = None;
+}
+
+/// Whether `quote` can be picked at `timestamp`
+fn is_current(quote: &Quote, timestamp: usize) -> bool {
+    quote.weight > 0
+        && quote.starts.is_none_or(|starts| starts <= timestamp)
+        && quote.ends.is_none_or(|ends| ends > timestamp)
+}
+
+/// Pick from `quotes` with a chance proportional to each one's weight.
+/// The same `seed` always picks the same quote from the same list
+pub fn pick(quotes: &[Quote], seed: Option<u64>) -> Option<&Quote> {
+    let total = quotes.iter().map(|q| q.weight as u64).sum::<u64>();
+
+    if total == 0 {
+        return None;
+    }
+
+    let mut remaining = seed.unwrap_or_else(rand::random) % total;
+
+    for quote in quotes {
+        if remaining < quote.weight as u64 {
+            return Some(quote);
+        }
+        remaining -= quote.weight as u64;
+    }
+
+    None
+}
+
+/// A seed that's stable for a day in the site's timezone, and for `page` if it's given
+fn daily_seed(page: Option<&str>) -> u64 {
+    let day = Utc::now()
+        .with_timezone(&config::settings().timezone)
+        .format("%F")
+        .to_string();
+
+    let digest = Sha256::new()
+        .chain_update(day)
+        .chain_update(page.unwrap_or_default())
+        .finalize();
+
+    u64::from_be_bytes(digest[..8].try_into().expect("Digest is long enough"))
+}
+
+/// The quote to show on `page`, following the configured rotation
+pub fn quote_for_page(page: &str) -> anyhow::Result<Option<String>> {
+    let now = common::now_timestamp();
+    let quotes = cached_quotes()?
+        .iter()
+        .filter(|q| is_current(q, now))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    let seed = match config::settings().quote_rotation {
+        QuoteRotation::Request => None,
+        QuoteRotation::Day => Some(daily_seed(None)),
+        QuoteRotation::Page => Some(daily_seed(Some(page))),
+    };
+
+    Ok(pick(&quotes, seed).map(|q| q.text.clone()))
+}
+
+#[derive(Deserialize)]
+pub struct QuoteQuery {
+    /// The page the quote is for, which matters when quotes rotate per page
+    #[serde(default)]
+    pub page: String,
+}
+
+#[derive(Serialize)]
+pub struct QuoteResponse {
+    pub text: String,
+}
+
+/// A quote, picked the same way as for page headers
+pub async fn get_quote(Query(query): Query<QuoteQuery>) -> Result<Json<QuoteResponse>, SiteError> {
+    match quote_for_page(&query.page)? {
+        Some(text) => Ok(Json(QuoteResponse { text })),
+        None => Err(SiteError::from_status(StatusCode::NOT_FOUND)),
+    }
+}
+
+pub async fn list_quotes(AuthBearer(token): AuthBearer) -> Result<Json<Vec<Quote>>, SiteError> {
+    require_author(token)?;
+
+    Ok(Json(db::DbConnection::new()?.quotes()?))
+}
+
+pub async fn add_quote(
+    AuthBearer(token): AuthBearer,
+    Json(mut quote): Json<Quote>,
+) -> Result<(StatusCode, Json<Quote>), SiteError> {
+    require_author(token)?;
+
+    quote.text = quote.text.trim().to_string();
+
+    let empty_range = matches!((quote.starts, quote.ends), (Some(s), Some(e)) if s >= e);
+
+    if quote.text.is_empty() || quote.text.chars().count() > MAX_QUOTE_CHARS || empty_range {
+        return Err(SiteError::from_status(StatusCode::BAD_REQUEST));
+    }
+
+    quote.id = db::DbConnection::new()?.add_quote(&quote)?;
+    forget_quotes();
+
+    Ok((StatusCode::CREATED, Json(quote)))
+}
+
+pub async fn delete_quote(
+    AuthBearer(token): AuthBearer,
+    extract::Path(id): extract::Path<i64>,
+) -> Result<StatusCode, SiteError> {
+    require_author(token)?;
+
+    match db::DbConnection::new()?.delete_quote(id)? {
+        true => {
+            forget_quotes();
+            Ok(StatusCode::OK)
+        }
+        false => Err(SiteError::from_status(StatusCode::NOT_FOUND)),
+    }
+}
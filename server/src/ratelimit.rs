@@ -0,0 +1,602 @@
+//! Limits on how much each client can ask of the site.
+//!
+//! `limit_requests` gives every client a budget for general traffic and a smaller one for
+//! public form submissions, and locks clients out of admin routes for a while after
+//! repeated bad admin keys. Clients are told apart by IP, taken from
+//! `SITE_TRUSTED_PROXY_HEADER` when the site is behind a proxy that sets it.
+
+use crate::{common, config};
+use axum::{
+    async_trait,
+    body::Body,
+    extract::{ConnectInfo, FromRequestParts},
+    http::{header, request::Parts, HeaderMap, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use parking_lot::Mutex;
+
+use std::{
+    collections::{HashMap, VecDeque},
+    hash::Hash,
+    net::{IpAddr, SocketAddr},
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+pub const GENERAL_RATE_WINDOW: Duration = Duration::from_secs(60);
+pub const SUBMISSION_RATE_WINDOW: Duration = Duration::from_secs(10 * 60);
+
+/// How often keys with nothing recent are dropped, so the maps don't grow forever.
+/// Pruning goes over every key, so it's done now and then rather than on every hit
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Where limits get the time from, so tests can move it along
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+#[derive(Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Per-key state that's pruned every `PRUNE_INTERVAL`
+struct Entries<K, V> {
+    map: HashMap<K, V>,
+    last_pruned: Instant,
+}
+
+impl<K: Eq + Hash, V> Entries<K, V> {
+    fn new(now: Instant) -> Self {
+        Entries {
+            map: HashMap::new(),
+            last_pruned: now,
+        }
+    }
+
+    fn prune(&mut self, now: Instant, keep: impl FnMut(&K, &mut V) -> bool) {
+        if now.duration_since(self.last_pruned) >= PRUNE_INTERVAL {
+            self.map.retain(keep);
+            self.last_pruned = now;
+        }
+    }
+}
+
+/// Drop times that have slid out of `window`
+fn expire(times: &mut VecDeque<Instant>, now: Instant, window: Duration) {
+    while times
+        .front()
+        .is_some_and(|t| now.duration_since(*t) >= window)
+    {
+        times.pop_front();
+    }
+}
+
+/// Sliding window limiter: at most `max_hits` per `window` for each key
+pub struct RateLimiter<K, C = SystemClock> {
+    max_hits: usize,
+    window: Duration,
+    hits: Mutex<Entries<K, VecDeque<Instant>>>,
+    clock: C,
+}
+
+impl<K: Eq + Hash + Clone> RateLimiter<K> {
+    pub fn new(max_hits: usize, window: Duration) -> Self {
+        Self::with_clock(max_hits, window, SystemClock)
+    }
+}
+
+impl<K: Eq + Hash + Clone, C: Clock> RateLimiter<K, C> {
+    pub fn with_clock(max_hits: usize, window: Duration, clock: C) -> Self {
+        RateLimiter {
+            max_hits,
+            window,
+            hits: Mutex::new(Entries::new(clock.now())),
+            clock,
+        }
+    }
+
+    /// Record a hit for `key`, or give how long until it's allowed another if it's over
+    /// the limit. Rejected hits aren't recorded, so a blocked client recovers once the
+    /// window passes
+    pub fn hit(&self, key: &K) -> Result<(), Duration> {
+        let now = self.clock.now();
+        let mut hits = self.hits.lock();
+
+        hits.prune(now, |_, times| {
+            times
+                .back()
+                .is_some_and(|t| now.duration_since(*t) < self.window)
+        });
+
+        let times = hits.map.entry(key.clone()).or_default();
+        expire(times, now, self.window);
+
+        if times.len() < self.max_hits {
+            times.push_back(now);
+            return Ok(());
+        }
+
+        // Another hit is allowed once the oldest one slides out of the window
+        let oldest = times.front().copied().unwrap_or(now);
+        Err(self.window.saturating_sub(now.duration_since(oldest)))
+    }
+
+    /// Record a hit for `key`, returning false if it's over the limit
+    pub fn check(&self, key: &K) -> bool {
+        self.hit(key).is_ok()
+    }
+}
+
+#[derive(Default)]
+struct Failures {
+    times: VecDeque<Instant>,
+    locked_until: Option<Instant>,
+}
+
+/// Locks a key out for `lockout` once it fails `max_failures` times within `window`
+pub struct Lockout<K, C = SystemClock> {
+    max_failures: usize,
+    window: Duration,
+    lockout: Duration,
+    failures: Mutex<Entries<K, Failures>>,
+    clock: C,
+}
+
+impl<K: Eq + Hash + Clone> Lockout<K> {
+    pub fn new(max_failures: usize, window: Duration, lockout: Duration) -> Self {
+        Self::with_clock(max_failures, window, lockout, SystemClock)
+    }
+}
+
+impl<K: Eq + Hash + Clone, C: Clock> Lockout<K, C> {
+    pub fn with_clock(max_failures: usize, window: Duration, lockout: Duration, clock: C) -> Self {
+        Lockout {
+            max_failures,
+            window,
+            lockout,
+            failures: Mutex::new(Entries::new(clock.now())),
+            clock,
+        }
+    }
+
+    /// How much longer `key` is locked out for, if it is
+    pub fn locked_for(&self, key: &K) -> Option<Duration> {
+        let now = self.clock.now();
+
+        self.failures
+            .lock()
+            .map
+            .get(key)
+            .and_then(|f| f.locked_until)
+            .and_then(|until| until.checked_duration_since(now))
+            .filter(|left| !left.is_zero())
+    }
+
+    /// Record a failure for `key`, giving the lockout if this one started it
+    pub fn fail(&self, key: &K) -> Option<Duration> {
+        let now = self.clock.now();
+        let mut failures = self.failures.lock();
+
+        // Forget keys that are neither locked out nor have recent failures
+        failures.prune(now, |_, f| {
+            f.locked_until.is_some_and(|until| until > now)
+                || f.times
+                    .back()
+                    .is_some_and(|t| now.duration_since(*t) < self.window)
+        });
+
+        let entry = failures.map.entry(key.clone()).or_default();
+        expire(&mut entry.times, now, self.window);
+        entry.times.push_back(now);
+
+        if entry.times.len() >= self.max_failures {
+            entry.times.clear();
+            entry.locked_until = Some(now + self.lockout);
+            Some(self.lockout)
+        } else {
+            None
+        }
+    }
+
+    /// Forget the failures for `key`, e.g. once it's got something right
+    pub fn clear(&self, key: &K) {
+        self.failures.lock().map.remove(key);
+    }
+}
+
+/// The client's address: from the trusted proxy header if one's configured and
+/// present, otherwise the address the connection came from.
+/// Proxies add to the end of `X-Forwarded-For`, so the last entry is the one the
+/// trusted proxy saw, and anything before it could have been made up by the client
+pub fn client_ip(headers: &HeaderMap, addr: SocketAddr) -> IpAddr {
+    client_ip_with(
+        headers,
+        addr,
+        config::settings().trusted_proxy_header.as_deref(),
+    )
+}
+
+fn client_ip_with(headers: &HeaderMap, addr: SocketAddr, proxy_header: Option<&str>) -> IpAddr {
+    proxy_header
+        .and_then(|name| headers.get(name))
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.rsplit(',').next())
+        .and_then(|ip| ip.trim().parse().ok())
+        .unwrap_or_else(|| addr.ip())
+}
+
+/// Extracts the client's address, see `client_ip`.
+/// Needs the server to be run with connect info
+pub struct ClientIp(pub IpAddr);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ClientIp {
+    type Rejection = <ConnectInfo<SocketAddr> as FromRequestParts<S>>::Rejection;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let ConnectInfo(addr) = ConnectInfo::<SocketAddr>::from_request_parts(parts, state).await?;
+        Ok(ClientIp(client_ip(&parts.headers, addr)))
+    }
+}
+
+fn general_limiter() -> &'static RateLimiter<IpAddr> {
+    static LIMITER: OnceLock<RateLimiter<IpAddr>> = OnceLock::new();
+    LIMITER.get_or_init(|| RateLimiter::new(config::settings().rate_limit, GENERAL_RATE_WINDOW))
+}
+
+fn submission_limiter() -> &'static RateLimiter<IpAddr> {
+    static LIMITER: OnceLock<RateLimiter<IpAddr>> = OnceLock::new();
+    LIMITER.get_or_init(|| {
+        RateLimiter::new(
+            config::settings().submission_rate_limit,
+            SUBMISSION_RATE_WINDOW,
+        )
+    })
+}
+
+fn admin_lockout() -> &'static Lockout<IpAddr> {
+    static LOCKOUT: OnceLock<Lockout<IpAddr>> = OnceLock::new();
+    LOCKOUT.get_or_init(|| {
+        let lockout = Duration::from_secs(config::settings().auth_lockout_secs);
+        Lockout::new(config::settings().auth_failure_limit, lockout, lockout)
+    })
+}
+
+/// How much longer `ip` is locked out of admin routes for, if it is
+pub fn admin_locked_for(ip: &IpAddr) -> Option<Duration> {
+    match config::settings().auth_failure_limit {
+        0 => None,
+        _ => admin_lockout().locked_for(ip),
+    }
+}
+
+/// Count a bad admin key from `ip` towards its lockout, or forget its failures once it
+/// gets one right
+pub fn record_admin_key(ip: &IpAddr, valid: bool) {
+    if config::settings().auth_failure_limit == 0 {
+        return;
+    }
+
+    if valid {
+        admin_lockout().clear(ip);
+    } else if let Some(lockout) = admin_lockout().fail(ip) {
+        tracing::warn!(%ip, ?lockout, "Locking out a client for bad admin keys");
+    }
+}
+
+/// Routes that take an admin key as a bearer token. Micropub isn't one, since it
+/// also takes IndieAuth tokens that `validate_token` doesn't know about, so its
+/// handlers check the lockout themselves
+fn needs_admin_key(path: &str) -> bool {
+    path.starts_with("/admin") || path == "/metrics"
+}
+
+/// Forms posted by readers, like comments and webmentions
+fn is_submission(request: &Request<Body>) -> bool {
+    let content_type = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+
+    request.method() == Method::POST
+        && request.uri().path() != "/micropub"
+        && (content_type.starts_with("application/x-www-form-urlencoded")
+            || content_type.starts_with("multipart/form-data"))
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+fn too_many_requests(retry_after: Duration) -> Response {
+    // Rounded up, so clients don't come back a moment too soon
+    let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(header::RETRY_AFTER, secs.max(1).to_string())],
+        "Too many requests, try again later",
+    )
+        .into_response()
+}
+
+/// Middleware applying the rate limits and admin lockouts, for use with `middleware::from_fn`.
+/// A limit of 0 turns that limit off
+pub async fn limit_requests(request: Request<Body>, next: Next<Body>) -> Response {
+    let Some(ConnectInfo(addr)) = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .copied()
+    else {
+        return next.run(request).await;
+    };
+
+    let settings = config::settings();
+    let ip = client_ip(request.headers(), addr);
+
+    if settings.rate_limit > 0 {
+        if let Err(wait) = general_limiter().hit(&ip) {
+            tracing::debug!(%ip, "Over the general rate limit");
+            return too_many_requests(wait);
+        }
+    }
+
+    if needs_admin_key(request.uri().path()) && settings.auth_failure_limit > 0 {
+        if let Some(left) = admin_locked_for(&ip) {
+            return too_many_requests(left);
+        }
+
+        // Requests with a bad key still go through, so they get the usual 403
+        if let Some(token) = bearer_token(request.headers()) {
+            record_admin_key(&ip, common::validate_token(token));
+        }
+    } else if settings.submission_rate_limit > 0 && is_submission(&request) {
+        if let Err(wait) = submission_limiter().hit(&ip) {
+            tracing::debug!(%ip, "Over the submission rate limit");
+            return too_many_requests(wait);
+        }
+    }
+
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A clock that only moves when it's told to
+    struct TestClock(Mutex<Instant>);
+
+    impl TestClock {
+        fn new() -> Self {
+            TestClock(Mutex::new(Instant::now()))
+        }
+
+        fn advance(&self, by: Duration) {
+            *self.0.lock() += by;
+        }
+    }
+
+    impl Clock for &TestClock {
+        fn now(&self) -> Instant {
+            *self.0.lock()
+        }
+    }
+
+    const WINDOW: Duration = Duration::from_secs(60);
+    const SECOND: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn waits_until_the_oldest_hit_expires() {
+        let clock = TestClock::new();
+        let limiter = RateLimiter::with_clock(2, WINDOW, &clock);
+
+        assert_eq!(limiter.hit(&1), Ok(()));
+        clock.advance(10 * SECOND);
+        assert_eq!(limiter.hit(&1), Ok(()));
+
+        // The first hit leaves the window first, so the wait is counted from it
+        clock.advance(5 * SECOND);
+        assert_eq!(limiter.hit(&1), Err(45 * SECOND));
+
+        // Other keys have their own budget
+        assert_eq!(limiter.hit(&2), Ok(()));
+
+        // Rejected hits weren't counted, so one is allowed as soon as the first expires
+        clock.advance(45 * SECOND);
+        assert_eq!(limiter.hit(&1), Ok(()));
+        assert_eq!(limiter.hit(&1), Err(10 * SECOND));
+        assert!(!limiter.check(&1));
+    }
+
+    #[test]
+    fn locks_out_after_repeated_failures() {
+        let clock = TestClock::new();
+        let lockout = Lockout::with_clock(3, WINDOW, 10 * WINDOW, &clock);
+
+        assert_eq!(lockout.fail(&1), None);
+        assert_eq!(lockout.fail(&1), None);
+        assert_eq!(lockout.locked_for(&1), None);
+
+        assert_eq!(lockout.fail(&1), Some(10 * WINDOW));
+        assert_eq!(lockout.locked_for(&1), Some(10 * WINDOW));
+        assert_eq!(lockout.locked_for(&2), None);
+
+        clock.advance(WINDOW);
+        assert_eq!(lockout.locked_for(&1), Some(9 * WINDOW));
+
+        clock.advance(9 * WINDOW);
+        assert_eq!(lockout.locked_for(&1), None);
+
+        // The failures that led to the lockout don't count towards the next one
+        assert_eq!(lockout.fail(&1), None);
+    }
+
+    #[test]
+    fn only_counts_failures_within_the_window() {
+        let clock = TestClock::new();
+        let lockout = Lockout::with_clock(3, WINDOW, WINDOW, &clock);
+
+        lockout.fail(&1);
+        lockout.fail(&1);
+        clock.advance(WINDOW);
+        assert_eq!(lockout.fail(&1), None);
+
+        // Getting it right starts the count again
+        lockout.fail(&1);
+        lockout.clear(&1);
+        assert_eq!(lockout.fail(&1), None);
+        assert_eq!(lockout.fail(&1), None);
+        assert_eq!(lockout.fail(&1), Some(WINDOW));
+    }
+
+    #[test]
+    fn prunes_stale_keys_now_and_then() {
+        let clock = TestClock::new();
+        let limiter = RateLimiter::with_clock(5, SECOND, &clock);
+        let lockout = Lockout::with_clock(5, SECOND, SECOND, &clock);
+
+        for key in 0..10 {
+            limiter.hit(&key).unwrap();
+            lockout.fail(&key);
+        }
+
+        // Stale keys stay until the interval's passed, so a hit doesn't go over them all
+        clock.advance(2 * SECOND);
+        limiter.hit(&100).unwrap();
+        lockout.fail(&100);
+        assert_eq!(limiter.hits.lock().map.len(), 11);
+        assert_eq!(lockout.failures.lock().map.len(), 11);
+
+        clock.advance(PRUNE_INTERVAL);
+        limiter.hit(&200).unwrap();
+        lockout.fail(&200);
+        assert_eq!(limiter.hits.lock().map.len(), 1);
+        assert_eq!(lockout.failures.lock().map.len(), 1);
+    }
+
+    #[test]
+    fn keeps_locked_out_keys_when_pruning() {
+        let clock = TestClock::new();
+        let lockout = Lockout::with_clock(1, SECOND, 10 * PRUNE_INTERVAL, &clock);
+
+        lockout.fail(&1);
+        clock.advance(2 * PRUNE_INTERVAL);
+        lockout.fail(&2);
+
+        assert!(lockout.locked_for(&1).is_some());
+    }
+
+    fn addr() -> SocketAddr {
+        "203.0.113.9:4000".parse().unwrap()
+    }
+
+    fn forwarded(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", value.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn takes_the_client_from_the_trusted_header() {
+        let proxy = Some("x-forwarded-for");
+        let client = IpAddr::from([198, 51, 100, 7]);
+
+        assert_eq!(
+            client_ip_with(&forwarded("198.51.100.7"), addr(), proxy),
+            client
+        );
+
+        // Clients can start the header off with whatever they like, but the proxy adds
+        // the address it saw to the end
+        assert_eq!(
+            client_ip_with(
+                &forwarded("10.0.0.1, 192.0.2.1, 198.51.100.7"),
+                addr(),
+                proxy
+            ),
+            client
+        );
+    }
+
+    #[test]
+    fn falls_back_to_the_connection() {
+        let proxy = Some("x-forwarded-for");
+
+        // Without the header, or with one that isn't an address
+        assert_eq!(
+            client_ip_with(&HeaderMap::new(), addr(), proxy),
+            addr().ip()
+        );
+        assert_eq!(
+            client_ip_with(&forwarded("not an address"), addr(), proxy),
+            addr().ip()
+        );
+
+        // Without a trusted proxy, the header's made up by the client
+        assert_eq!(
+            client_ip_with(&forwarded("198.51.100.7"), addr(), None),
+            addr().ip()
+        );
+    }
+
+    fn request(method: Method, path: &str, content_type: Option<&str>) -> Request<Body> {
+        let mut request = Request::builder().method(method).uri(path);
+
+        if let Some(content_type) = content_type {
+            request = request.header(header::CONTENT_TYPE, content_type);
+        }
+
+        request.body(Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn classifies_submissions() {
+        let form = Some("application/x-www-form-urlencoded; charset=utf-8");
+        let multipart = Some("multipart/form-data; boundary=x");
+
+        assert!(is_submission(&request(
+            Method::POST,
+            "/blog/post/comments",
+            form
+        )));
+        assert!(is_submission(&request(Method::POST, "/webmention", form)));
+        assert!(is_submission(&request(Method::POST, "/upload", multipart)));
+
+        // Micropub has its own lockout, and other requests aren't reader submissions
+        assert!(!is_submission(&request(Method::POST, "/micropub", form)));
+        assert!(!is_submission(&request(
+            Method::GET,
+            "/blog/post/comments",
+            form
+        )));
+        assert!(!is_submission(&request(
+            Method::POST,
+            "/api/posts",
+            Some("application/json")
+        )));
+        assert!(!is_submission(&request(Method::POST, "/webmention", None)));
+    }
+
+    #[test]
+    fn classifies_admin_routes() {
+        assert!(needs_admin_key("/admin/quotes"));
+        assert!(needs_admin_key("/admin"));
+        assert!(needs_admin_key("/metrics"));
+
+        assert!(!needs_admin_key("/micropub"));
+        assert!(!needs_admin_key("/blog/admin"));
+        assert!(!needs_admin_key("/metrics/extra"));
+    }
+}
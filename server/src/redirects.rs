@@ -0,0 +1,161 @@
+use crate::{
+    blog::{
+        db::{self, Redirect},
+        render::read_file_contents,
+    },
+    common::{self, Post},
+    route::{require_author, SiteError},
+};
+use anyhow::format_err;
+use axum::{
+    extract::{self, Json},
+    http::{header, StatusCode, Uri},
+    response::{IntoResponse, Response},
+};
+use axum_auth::AuthBearer;
+use serde::Deserialize;
+
+use std::{fs, path::PathBuf};
+
+pub const REDIRECT_STATUS_CODES: [u16; 5] = [301, 302, 303, 307, 308];
+
+/// Paths are stored without a trailing slash, matching how routes accept both forms
+pub fn normalize_path(path: &str) -> &str {
+    match path.trim_end_matches('/') {
+        "" => "/",
+        p => p,
+    }
+}
+
+fn redirect_response(redirect: &Redirect) -> Result<Response, SiteError> {
+    let status = StatusCode::from_u16(redirect.status_code)?;
+
+    Ok((status, [(header::LOCATION, redirect.to_path.clone())]).into_response())
+}
+
+pub fn redirect_or_not_found(conn: &db::DbConnection, path: &str) -> Result<Response, SiteError> {
+    match conn.redirect_for(normalize_path(path))? {
+        Some(ref r) => redirect_response(r),
+        None => Err(SiteError::from_status(StatusCode::NOT_FOUND)),
+    }
+}
+
+/// Anything that didn't match a route gets a last chance at the redirect table
+pub async fn fallback(uri: Uri) -> Result<Response, SiteError> {
+    redirect_or_not_found(&db::DbConnection::new()?, uri.path())
+}
+
+pub async fn list_redirects(
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<Redirect>>, SiteError> {
+    require_author(token)?;
+
+    Ok(Json(db::DbConnection::new()?.redirects()?))
+}
+
+/// Add a redirect, or replace the one with the same `from_path`
+pub async fn set_redirect(
+    AuthBearer(token): AuthBearer,
+    Json(mut redirect): Json<Redirect>,
+) -> Result<StatusCode, SiteError> {
+    require_author(token)?;
+
+    if !REDIRECT_STATUS_CODES.contains(&redirect.status_code) || !redirect.from_path.starts_with('/')
+    {
+        return Err(SiteError::from_status(StatusCode::BAD_REQUEST));
+    }
+
+    redirect.from_path = normalize_path(&redirect.from_path).into();
+    db::DbConnection::new()?.set_redirect(&redirect)?;
+
+    Ok(StatusCode::OK)
+}
+
+pub async fn delete_redirect(
+    AuthBearer(token): AuthBearer,
+    extract::Path(id): extract::Path<i64>,
+) -> Result<StatusCode, SiteError> {
+    require_author(token)?;
+
+    match db::DbConnection::new()?.delete_redirect(id)? {
+        true => Ok(StatusCode::OK),
+        false => Err(SiteError::from_status(StatusCode::NOT_FOUND)),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RenameRequest {
+    pub slug: String,
+}
+
+/// Change a post's slug, moving its markdown and media to match.
+/// References to the post's media in its markdown are rewritten to the new location
+pub async fn rename_post(
+    AuthBearer(token): AuthBearer,
+    extract::Path(old_slug): extract::Path<String>,
+    Json(RenameRequest { slug: new_slug }): Json<RenameRequest>,
+) -> Result<Json<Post>, SiteError> {
+    let author = require_author(token)?;
+
+    if !common::is_valid_slug(&new_slug) || new_slug == old_slug {
+        return Err(SiteError::from_status(StatusCode::BAD_REQUEST));
+    }
+
+    let mut conn = db::DbConnection::new()?;
+    let Some(old_post) = conn.find(&old_slug)? else {
+        return Err(SiteError::from_status(StatusCode::NOT_FOUND));
+    };
+
+    if conn.find(&new_slug)?.is_some() {
+        return Err(SiteError::from(format_err!("'{new_slug}' already exists"))
+            .with_status(StatusCode::CONFLICT));
+    }
+
+    let new_post = Post {
+        slug: new_slug.clone(),
+        ..old_post.clone()
+    };
+
+    let old_media_url = format!("{}/{old_slug}/", common::MEDIA_URL_PREFIX);
+    let new_media_url = format!("{}/{new_slug}/", common::MEDIA_URL_PREFIX);
+
+    let content = read_file_contents(old_post.md_path())?;
+    let new_content = content.replace(&old_media_url, &new_media_url);
+
+    // The old markdown stays until the database has moved over, so a failed rename
+    // can be undone by removing what was added
+    fs::write(new_post.md_path(), &new_content)?;
+
+    let old_media_dir = PathBuf::from(common::MEDIA_PATH).join(&old_slug);
+    let new_media_dir = PathBuf::from(common::MEDIA_PATH).join(&new_slug);
+    let has_media = old_media_dir.try_exists()?;
+
+    let moved = match has_media {
+        true => fs::rename(&old_media_dir, &new_media_dir).map_err(anyhow::Error::from),
+        false => Ok(()),
+    }
+    .and_then(|_| conn.rename_post(&old_slug, &new_slug));
+
+    if let Err(e) = moved {
+        if let Err(undo) = fs::remove_file(new_post.md_path()) {
+            tracing::error!("Could not remove {:?}: {undo:?}", new_post.md_path());
+        }
+        if has_media && new_media_dir.try_exists().unwrap_or(false) {
+            if let Err(undo) = fs::rename(&new_media_dir, &old_media_dir) {
+                tracing::error!("Could not move {new_media_dir:?} back: {undo:?}");
+            }
+        }
+
+        return Err(e.into());
+    }
+
+    if let Err(e) = fs::remove_file(old_post.md_path()) {
+        tracing::warn!("Could not remove {:?}: {e:?}", old_post.md_path());
+    }
+
+    if new_content != content {
+        conn.add_revision(&new_slug, &new_content, common::now_timestamp(), &author)?;
+    }
+
+    Ok(Json(new_post))
+}
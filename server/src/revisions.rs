@@ -0,0 +1,124 @@
+use crate::{
+    blog::{
+        db::{self, Revision},
+        render,
+    },
+    common,
+    metrics::TimedRender,
+    route::{require_author, SiteError},
+};
+use axum::{
+    extract::{self, Json},
+    http::{header, StatusCode},
+    response::{Html, IntoResponse, Response},
+};
+use axum_auth::AuthBearer;
+use serde::Deserialize;
+use similar::{ChangeTag, TextDiff};
+
+use std::fs;
+
+pub async fn list_revisions(
+    AuthBearer(token): AuthBearer,
+    extract::Path(slug): extract::Path<String>,
+) -> Result<Json<Vec<Revision>>, SiteError> {
+    require_author(token)?;
+
+    Ok(Json(db::DbConnection::new()?.revisions(&slug)?))
+}
+
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffFormat {
+    #[default]
+    Unified,
+    Rendered,
+}
+
+#[derive(Deserialize)]
+pub struct DiffQuery {
+    pub from: i64,
+    pub to: i64,
+    #[serde(default)]
+    pub format: DiffFormat,
+}
+
+/// Diff two revisions of a post, either as a plain text unified diff
+/// or as an html page with the changed lines highlighted
+pub async fn diff_revisions(
+    AuthBearer(token): AuthBearer,
+    extract::Path(slug): extract::Path<String>,
+    extract::Query(query): extract::Query<DiffQuery>,
+) -> Result<Response, SiteError> {
+    require_author(token)?;
+
+    let conn = db::DbConnection::new()?;
+    let (Some(from), Some(to)) = (
+        conn.revision(&slug, query.from)?,
+        conn.revision(&slug, query.to)?,
+    ) else {
+        return Err(SiteError::from_status(StatusCode::NOT_FOUND));
+    };
+
+    let diff = TextDiff::from_lines(&from.content, &to.content);
+
+    match query.format {
+        DiffFormat::Unified => {
+            let unified = diff
+                .unified_diff()
+                .header(
+                    &format!("{slug}@{}", from.short_hash()),
+                    &format!("{slug}@{}", to.short_hash()),
+                )
+                .to_string();
+
+            Ok(([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], unified).into_response())
+        }
+
+        DiffFormat::Rendered => {
+            let lines = diff
+                .iter_all_changes()
+                .map(|change| render::DiffLine {
+                    kind: match change.tag() {
+                        ChangeTag::Insert => "insert",
+                        ChangeTag::Delete => "delete",
+                        ChangeTag::Equal => "equal",
+                    },
+                    text: change.to_string_lossy().trim_end_matches('\n').to_string(),
+                })
+                .collect::<Vec<_>>();
+
+            let heading = format!(
+                "{slug}: {} \u{2192} {}",
+                from.short_hash(),
+                to.short_hash()
+            );
+
+            let content = render::page_builder(&heading)
+                .html_content(&render::diff_display(&heading, &lines)?)
+                .timed_render()?;
+
+            Ok(Html::from(content).into_response())
+        }
+    }
+}
+
+/// Put an old revision's content back as the current post text.
+/// The restore is itself recorded as a new revision
+pub async fn restore_revision(
+    AuthBearer(token): AuthBearer,
+    extract::Path((slug, id)): extract::Path<(String, i64)>,
+) -> Result<Json<Revision>, SiteError> {
+    let author = require_author(token)?;
+
+    let conn = db::DbConnection::new()?;
+    let (Some(post), Some(old)) = (conn.find(&slug)?, conn.revision(&slug, id)?) else {
+        return Err(SiteError::from_status(StatusCode::NOT_FOUND));
+    };
+
+    fs::write(post.md_path(), &old.content)?;
+
+    let restored = conn.add_revision(&slug, &old.content, common::now_timestamp(), &author)?;
+
+    Ok(Json(restored))
+}
@@ -0,0 +1,244 @@
+//! Security headers for responses, including a Content-Security-Policy whose nonce lets
+//! the base template's inline style and script through without `'unsafe-inline'`.
+//!
+//! Each layer only sets headers a response doesn't already have, so a layer on a nested
+//! router overrides the site-wide one for its routes.
+
+use crate::{
+    config,
+    ratelimit::{ClientIp, RateLimiter},
+};
+use axum::{
+    body::{Body, Bytes},
+    extract::State,
+    http::{header, HeaderName, HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use base64::{engine::general_purpose, Engine};
+use serde_json::Value;
+
+use std::{
+    net::IpAddr,
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+
+/// Where browsers send Content-Security-Policy violation reports
+pub const REPORT_PATH: &str = "/csp-report";
+pub const REPORT_MAX_BYTES: usize = 64 * 1024;
+
+/// Reports logged per client in each `REPORT_RATE_WINDOW`, so one page can't flood the logs
+const REPORT_RATE_LIMIT: usize = 20;
+const REPORT_RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Stands in for the request's nonce in policy directives
+const NONCE_PLACEHOLDER: &str = "{nonce}";
+
+tokio::task_local! {
+    static NONCE: String;
+}
+
+/// The nonce for the request being handled, if its policy uses one
+pub fn nonce() -> Option<String> {
+    NONCE.try_with(Clone::clone).ok()
+}
+
+fn generate_nonce() -> String {
+    // No padding, so it goes into the template without escaping
+    general_purpose::URL_SAFE_NO_PAD.encode(rand::random::<[u8; 16]>())
+}
+
+#[derive(Debug, Clone)]
+pub struct SecurityHeaders {
+    /// Content-Security-Policy directives, in order. `{nonce}` in a value is replaced
+    /// with a fresh nonce for each request
+    csp: Vec<(String, String)>,
+    report_only: bool,
+    referrer_policy: String,
+    permissions_policy: String,
+}
+
+impl SecurityHeaders {
+    /// For html pages: only same-origin resources, and inline style and script only
+    /// with the request's nonce
+    pub fn pages() -> Self {
+        let nonce_source = format!("'nonce-{NONCE_PLACEHOLDER}'");
+
+        let mut headers = Self::api();
+        headers
+            .directive("default-src", "'self'")
+            .directive("script-src", &nonce_source)
+            .directive("style-src", &nonce_source)
+            // Posts can embed images from elsewhere
+            .directive("img-src", "'self' https: data:")
+            .directive("object-src", "'none'")
+            .directive("base-uri", "'none'")
+            .directive("form-action", "'self'")
+            .directive("frame-ancestors", "'none'");
+        headers
+    }
+
+    /// For JSON and anything else that's never rendered as a page
+    pub fn api() -> Self {
+        SecurityHeaders {
+            csp: vec![
+                ("default-src".into(), "'none'".into()),
+                ("frame-ancestors".into(), "'none'".into()),
+            ],
+            report_only: config::settings().csp_report_only,
+            referrer_policy: "strict-origin-when-cross-origin".into(),
+            permissions_policy: "camera=(), microphone=(), geolocation=(), payment=(), usb=()"
+                .into(),
+        }
+    }
+
+    /// Set a Content-Security-Policy directive, replacing it if it's already set
+    pub fn directive(&mut self, name: &str, value: &str) -> &mut Self {
+        match self.csp.iter_mut().find(|(n, _)| n == name) {
+            Some((_, v)) => *v = value.into(),
+            None => self.csp.push((name.into(), value.into())),
+        }
+        self
+    }
+
+    pub fn report_only(&mut self, report_only: bool) -> &mut Self {
+        self.report_only = report_only;
+        self
+    }
+
+    pub fn referrer_policy(&mut self, policy: &str) -> &mut Self {
+        self.referrer_policy = policy.into();
+        self
+    }
+
+    pub fn permissions_policy(&mut self, policy: &str) -> &mut Self {
+        self.permissions_policy = policy.into();
+        self
+    }
+
+    fn uses_nonce(&self) -> bool {
+        self.csp.iter().any(|(_, v)| v.contains(NONCE_PLACEHOLDER))
+    }
+
+    fn csp(&self, nonce: Option<&str>) -> String {
+        self.csp
+            .iter()
+            .map(|(name, value)| match nonce {
+                Some(nonce) => format!("{name} {}", value.replace(NONCE_PLACEHOLDER, nonce)),
+                None => format!("{name} {value}"),
+            })
+            .chain([format!("report-uri {REPORT_PATH}")])
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+
+    fn csp_header(&self) -> HeaderName {
+        match self.report_only {
+            true => header::CONTENT_SECURITY_POLICY_REPORT_ONLY,
+            false => header::CONTENT_SECURITY_POLICY,
+        }
+    }
+}
+
+/// Strict-Transport-Security, when the site is served over https
+fn hsts() -> Option<String> {
+    let settings = config::settings();
+
+    (settings.site_url.starts_with("https://") && settings.hsts_max_age > 0)
+        .then(|| format!("max-age={}", settings.hsts_max_age))
+}
+
+/// Middleware adding `headers` to responses, for use with `middleware::from_fn_with_state`.
+/// Handlers rendering the base template pick the nonce up with `nonce()`
+pub async fn set_headers(
+    State(headers): State<Arc<SecurityHeaders>>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let nonce = headers.uses_nonce().then(generate_nonce);
+
+    let mut response = match &nonce {
+        Some(nonce) => NONCE.scope(nonce.clone(), next.run(request)).await,
+        None => next.run(request).await,
+    };
+
+    let response_headers = response.headers_mut();
+
+    let has_csp = response_headers.contains_key(header::CONTENT_SECURITY_POLICY)
+        || response_headers.contains_key(header::CONTENT_SECURITY_POLICY_REPORT_ONLY);
+
+    let values = [
+        (!has_csp).then(|| (headers.csp_header(), headers.csp(nonce.as_deref()))),
+        hsts().map(|h| (header::STRICT_TRANSPORT_SECURITY, h)),
+        Some((header::X_CONTENT_TYPE_OPTIONS, "nosniff".into())),
+        Some((header::REFERRER_POLICY, headers.referrer_policy.clone())),
+        Some((
+            HeaderName::from_static("permissions-policy"),
+            headers.permissions_policy.clone(),
+        )),
+    ];
+
+    for (name, value) in values.into_iter().flatten() {
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            response_headers.entry(name).or_insert(value);
+        }
+    }
+
+    response
+}
+
+fn report_limiter() -> &'static RateLimiter<IpAddr> {
+    static LIMITER: OnceLock<RateLimiter<IpAddr>> = OnceLock::new();
+    LIMITER.get_or_init(|| RateLimiter::new(REPORT_RATE_LIMIT, REPORT_RATE_WINDOW))
+}
+
+/// The first of `names` that's set, since the two report formats name fields differently
+fn field<'a>(violation: &'a Value, names: &[&str]) -> &'a str {
+    names
+        .iter()
+        .find_map(|n| violation.get(n).and_then(Value::as_str))
+        .unwrap_or("unknown")
+}
+
+/// Log Content-Security-Policy violations. Takes both the `report-uri` format, a single
+/// `{"csp-report": {...}}`, and the Reporting API's list of `{"body": {...}}`
+pub async fn report(ClientIp(ip): ClientIp, body: Bytes) -> StatusCode {
+    if !report_limiter().check(&ip) {
+        return StatusCode::TOO_MANY_REQUESTS;
+    }
+
+    let Ok(report) = serde_json::from_slice::<Value>(&body) else {
+        return StatusCode::BAD_REQUEST;
+    };
+
+    let violations = match report {
+        Value::Array(reports) => reports
+            .into_iter()
+            .filter_map(|r| r.get("body").cloned())
+            .collect(),
+        other => other
+            .get("csp-report")
+            .cloned()
+            .into_iter()
+            .collect::<Vec<_>>(),
+    };
+
+    for violation in &violations {
+        tracing::warn!(
+            "Content-Security-Policy violation on {}: {} blocked {}",
+            field(violation, &["document-uri", "documentURL"]),
+            field(
+                violation,
+                &[
+                    "effective-directive",
+                    "effectiveDirective",
+                    "violated-directive"
+                ]
+            ),
+            field(violation, &["blocked-uri", "blockedURL"]),
+        );
+    }
+
+    StatusCode::NO_CONTENT
+}
@@ -0,0 +1,74 @@
+//! Fixtures shared by the tests: local stand-ins for other sites, a client that can
+//! reach them, and posts of their own to write to.
+
+use crate::{common, fetch};
+use axum::{body::HttpBody, response::Response, Router};
+use serde_json::Value;
+use tokio::sync::{Mutex, MutexGuard};
+use url::Url;
+
+use std::{fs, path::Path, sync::OnceLock};
+
+/// The admin key tests authenticate with, in place of the one set at build time
+pub const ADMIN_KEY: &str = "test-admin-key";
+
+/// Exclusive use of the tests' posts, which start out empty for each run. Tests that
+/// read or write posts hold this, since they share one database and posts.json
+pub async fn posts() -> MutexGuard<'static, ()> {
+    static POSTS: OnceLock<Mutex<()>> = OnceLock::new();
+
+    POSTS
+        .get_or_init(|| {
+            let dir = Path::new(common::POSTS_JSON_PATH).parent().unwrap();
+
+            if dir.exists() {
+                fs::remove_dir_all(dir).unwrap();
+            }
+            fs::create_dir_all(common::POSTS_MARKDOWN_PATH).unwrap();
+            fs::write(common::POSTS_JSON_PATH, "[]").unwrap();
+
+            Mutex::new(())
+        })
+        .lock()
+        .await
+}
+
+/// A client that can reach local stand-ins, unlike `fetch::http_client`
+pub fn test_client() -> reqwest::Client {
+    fetch::client_builder().build().unwrap()
+}
+
+/// Serve the router `app` makes on a local port, giving its url. It's made once the port
+/// is known, since documents can refer to their own urls. The host is a name rather than
+/// 127.0.0.1, since `fetch::check_url` refuses loopback addresses outright
+pub fn serve_with(app: impl FnOnce(&Url) -> Router) -> Url {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let base = Url::parse(&format!("http://localhost:{port}/")).unwrap();
+
+    let app = app(&base);
+    tokio::spawn(
+        axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.into_make_service()),
+    );
+
+    base
+}
+
+/// Serve `app` on a local port, giving its url
+pub fn serve(app: Router) -> Url {
+    serve_with(|_| app)
+}
+
+/// The JSON a handler responded with
+pub async fn json_body(response: Response) -> Value {
+    let mut body = response.into_body();
+    let mut bytes = Vec::new();
+
+    while let Some(chunk) = body.data().await {
+        bytes.extend_from_slice(&chunk.unwrap());
+    }
+
+    serde_json::from_slice(&bytes).unwrap()
+}
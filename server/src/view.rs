@@ -0,0 +1,228 @@
+use crate::{
+    blog::{
+        db::{Comment, Revision, Webmention},
+        render::{self, read_file_contents},
+    },
+    common::{self, Post},
+    config,
+};
+use anyhow::format_err;
+use chrono::{DateTime, SecondsFormat, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::Serialize;
+
+/// Average adult silent reading speed, used for the reading time estimate
+pub const WORDS_PER_MINUTE: usize = 200;
+
+/// What the templates get to see of a post.
+/// Dates are pre-formatted here so the templates don't need any helpers.
+#[derive(Debug, Clone, Serialize)]
+pub struct PostView {
+    pub title: String,
+    pub slug: String,
+    pub url: String,
+    pub timestamp: usize,
+    /// Display date, in the configured format and timezone
+    pub date: String,
+    /// ISO 8601 value for `<time datetime=...>`
+    pub datetime: String,
+    /// e.g. "3 days ago"
+    pub relative_date: String,
+    pub word_count: usize,
+    pub reading_time_minutes: usize,
+    pub tags: Vec<String>,
+}
+
+impl PostView {
+    pub fn new(post: &Post, md_content: &str) -> anyhow::Result<Self> {
+        let word_count = render::word_count(md_content)?;
+
+        Ok(PostView {
+            title: post.title.clone(),
+            slug: post.slug.clone(),
+            url: format!("/blog/{}", post.slug),
+            timestamp: post.timestamp,
+            date: post.date_str(),
+            datetime: iso_datetime(post.timestamp),
+            relative_date: relative_date(post.timestamp, Utc::now().timestamp()),
+            word_count,
+            reading_time_minutes: word_count.div_ceil(WORDS_PER_MINUTE).max(1),
+            tags: post.tags.clone(),
+        })
+    }
+
+    /// Build the view, reading the markdown for the word count from the post's file
+    pub fn load(post: &Post) -> anyhow::Result<Self> {
+        Self::new(post, &read_file_contents(post.md_path())?)
+    }
+}
+
+/// A revision as listed in a post's public changelog
+#[derive(Debug, Clone, Serialize)]
+pub struct RevisionView {
+    pub date: String,
+    pub datetime: String,
+    pub short_hash: String,
+}
+
+impl RevisionView {
+    pub fn new(revision: &Revision) -> Self {
+        RevisionView {
+            date: display_date(revision.timestamp),
+            datetime: iso_datetime(revision.timestamp),
+            short_hash: revision.short_hash().into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CommentView {
+    pub author_name: String,
+    pub date: String,
+    pub datetime: String,
+    pub body_html: String,
+}
+
+impl CommentView {
+    pub fn new(comment: &Comment) -> Self {
+        CommentView {
+            author_name: comment.author_name.clone(),
+            date: display_date(comment.timestamp),
+            datetime: iso_datetime(comment.timestamp),
+            body_html: comment.body_html.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WebmentionView {
+    pub author_name: String,
+    pub author_url: Option<String>,
+    pub author_photo: Option<String>,
+    pub content: Option<String>,
+    pub url: String,
+    pub date: String,
+    pub datetime: String,
+}
+
+impl WebmentionView {
+    pub fn new(mention: &Webmention) -> Self {
+        WebmentionView {
+            author_name: mention.author_name.clone(),
+            author_url: mention.author_url.clone(),
+            author_photo: mention.author_photo.clone(),
+            content: mention.content.clone(),
+            url: mention.url.clone(),
+            date: display_date(mention.timestamp),
+            datetime: iso_datetime(mention.timestamp),
+        }
+    }
+}
+
+/// A post's webmentions, split up by kind for display
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct WebmentionsView {
+    pub likes: Vec<WebmentionView>,
+    pub reposts: Vec<WebmentionView>,
+    pub replies: Vec<WebmentionView>,
+    pub mentions: Vec<WebmentionView>,
+    pub any: bool,
+}
+
+impl WebmentionsView {
+    pub fn new(mentions: &[Webmention]) -> Self {
+        let mut view = WebmentionsView {
+            any: !mentions.is_empty(),
+            ..Self::default()
+        };
+
+        for mention in mentions {
+            let list = match mention.kind.as_str() {
+                "like" => &mut view.likes,
+                "repost" => &mut view.reposts,
+                "reply" => &mut view.replies,
+                _ => &mut view.mentions,
+            };
+
+            list.push(WebmentionView::new(mention));
+        }
+
+        view
+    }
+}
+
+/// A timestamp in the configured timezone. Timestamps come from uploads and Micropub
+/// as well as the clock, so they aren't necessarily ones chrono can represent
+pub fn localized_datetime(timestamp: usize) -> anyhow::Result<DateTime<Tz>> {
+    i64::try_from(timestamp)
+        .ok()
+        .and_then(|t| config::settings().timezone.timestamp_opt(t, 0).single())
+        .ok_or_else(|| format_err!("Timestamp {timestamp} is out of range"))
+}
+
+/// Format a timestamp in the configured timezone, or show it as is if it's out of range
+pub fn format_timestamp(timestamp: usize, format: &str) -> String {
+    localized_datetime(timestamp)
+        .map(|dt| dt.format(format).to_string())
+        .unwrap_or_else(|_| timestamp.to_string())
+}
+
+pub fn display_date(timestamp: usize) -> String {
+    format_timestamp(timestamp, &config::settings().date_format)
+}
+
+/// ISO 8601 value for `<time datetime=...>`, or the timestamp as is if it's out of range
+pub fn iso_datetime(timestamp: usize) -> String {
+    localized_datetime(timestamp)
+        .map(|dt| dt.to_rfc3339_opts(SecondsFormat::Secs, true))
+        .unwrap_or_else(|_| timestamp.to_string())
+}
+
+fn plural(n: i64, unit: &str) -> String {
+    match n {
+        1 => format!("1 {unit} ago"),
+        _ => format!("{n} {unit}s ago"),
+    }
+}
+
+/// Coarse human readable age of `timestamp` as of `now`, both unix seconds
+pub fn relative_date(timestamp: usize, now: i64) -> String {
+    let timestamp = i64::try_from(timestamp).unwrap_or(i64::MAX);
+    let days = now
+        .saturating_sub(timestamp)
+        .div_euclid(common::SECONDS_PER_DAY);
+
+    match days {
+        d if d < 0 => "in the future".into(),
+        0 => "today".into(),
+        1 => "yesterday".into(),
+        d if d < 30 => plural(d, "day"),
+        d if d < 365 => plural(d / 30, "month"),
+        d => plural(d / 365, "year"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn out_of_range_timestamps_are_shown_as_is() {
+        let huge = usize::MAX;
+
+        assert!(localized_datetime(huge).is_err());
+        assert_eq!(display_date(huge), huge.to_string());
+        assert_eq!(iso_datetime(huge), huge.to_string());
+        assert_eq!(relative_date(huge, 0), "in the future");
+    }
+
+    #[test]
+    fn timestamps_are_formatted() {
+        assert_eq!(
+            format_timestamp(0, "%Y"),
+            localized_datetime(0).unwrap().format("%Y").to_string()
+        );
+        assert!(iso_datetime(1_700_000_000).starts_with("2023-11-1"));
+        assert_eq!(relative_date(0, 2 * common::SECONDS_PER_DAY), "2 days ago");
+    }
+}
@@ -0,0 +1,740 @@
+use crate::{
+    blog::db::{self, DeliveryStatus, OutgoingWebmention, Webmention},
+    common, config,
+    fetch::{self, check_url},
+    mf2,
+    redirects::normalize_path,
+    route::{require_author, SiteError},
+};
+use anyhow::format_err;
+use axum::{
+    extract::{self, Form, Json},
+    http::StatusCode,
+};
+use axum_auth::AuthBearer;
+use chrono::DateTime;
+use scraper::{Html, Selector};
+use serde::Deserialize;
+use tokio::sync::Notify;
+use url::Url;
+
+use std::{collections::BTreeSet, sync::OnceLock, time::Duration};
+
+/// Sources bigger than this aren't worth parsing
+pub const MAX_SOURCE_BYTES: usize = 1024 * 1024;
+/// Body limit for received webmentions, which are only a pair of urls
+pub const MAX_REQUEST_BYTES: usize = 16 * 1024;
+
+/// Longest reply or mention text kept for display
+pub const MAX_CONTENT_CHARS: usize = 1000;
+
+/// Attempts at sending a mention before giving up on it
+pub const MAX_SEND_ATTEMPTS: u32 = 6;
+
+/// Wait before the first retry, doubled for each one after
+pub const RETRY_BASE_DELAY_SECS: usize = 60;
+
+/// How long the sender sleeps when there's nothing queued
+const SENDER_IDLE_SECS: usize = 60 * 60;
+
+#[derive(Deserialize)]
+pub struct WebmentionForm {
+    pub source: String,
+    pub target: String,
+}
+
+/// Compare urls the way readers would: ignoring fragments and trailing slashes
+fn same_url(a: &Url, b: &Url) -> bool {
+    let strip = |u: &Url| {
+        let mut u = u.clone();
+        u.set_fragment(None);
+        u.to_string().trim_end_matches('/').to_string()
+    };
+
+    strip(a) == strip(b)
+}
+
+fn http_url(s: &str) -> Option<Url> {
+    Url::parse(s)
+        .ok()
+        .filter(|u| matches!(u.scheme(), "http" | "https"))
+}
+
+/// The slug of the post `target` points to, if it's one of ours
+pub fn target_post_slug(target: &Url, conn: &db::DbConnection) -> anyhow::Result<Option<String>> {
+    let site = Url::parse(&config::settings().site_url)?;
+
+    if target.host_str() != site.host_str() {
+        return Ok(None);
+    }
+
+    let slug = match normalize_path(target.path()).strip_prefix("/blog/") {
+        Some(s) if !s.contains('/') => s,
+        _ => return Ok(None),
+    };
+
+    Ok(conn.find(slug)?.map(|p| p.slug))
+}
+
+/// A mention as found in the source document, before it's tied to a post
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedMention {
+    pub kind: &'static str,
+    pub author_name: String,
+    pub author_url: Option<String>,
+    pub author_photo: Option<String>,
+    pub content: Option<String>,
+    pub url: String,
+    pub published: Option<usize>,
+}
+
+fn links_to(document: &Html, source: &Url, target: &Url) -> bool {
+    let selector = Selector::parse("[href], [src]").unwrap();
+
+    document.select(&selector).any(|el| {
+        ["href", "src"]
+            .iter()
+            .filter_map(|a| el.value().attr(a))
+            .filter_map(|href| source.join(href).ok())
+            .any(|u| same_url(&u, target))
+    })
+}
+
+fn property_links_to(entry: &mf2::Item, property: &str, target: &Url) -> bool {
+    entry
+        .properties
+        .get(property)
+        .into_iter()
+        .flatten()
+        .filter_map(|v| match v.as_item() {
+            Some(item) => item.first_text("url").or_else(|| item.value.clone()),
+            None => Some(v.as_text()),
+        })
+        .filter_map(|u| Url::parse(&u).ok())
+        .any(|u| same_url(&u, target))
+}
+
+/// Work out what kind of mention `html` makes of `target`, and who made it.
+/// Gives None when the source doesn't actually link to the target
+pub fn parse_mention(html: &str, source: &Url, target: &Url) -> Option<ParsedMention> {
+    if !links_to(&Html::parse_document(html), source, target) {
+        return None;
+    }
+
+    let parsed = mf2::parse(html, Some(source));
+    let entry = parsed.find("h-entry");
+
+    let kind = match entry {
+        Some(e) if property_links_to(e, "like-of", target) => "like",
+        Some(e) if property_links_to(e, "repost-of", target) => "repost",
+        Some(e) if property_links_to(e, "in-reply-to", target) => "reply",
+        _ => "mention",
+    };
+
+    let author_card = entry
+        .and_then(|e| e.first("author"))
+        .and_then(|a| a.as_item())
+        .or_else(|| parsed.find("h-card"));
+
+    let author_name = entry
+        .and_then(|e| e.first("author"))
+        .map(|a| match a.as_item() {
+            Some(card) => card.first_text("name").unwrap_or_default(),
+            None => a.as_text(),
+        })
+        .or_else(|| author_card.and_then(|c| c.first_text("name")))
+        .filter(|n| !n.is_empty())
+        .unwrap_or_else(|| source.host_str().unwrap_or_default().to_string());
+
+    let author_url = author_card
+        .and_then(|c| c.first_text("url"))
+        .and_then(|u| http_url(&u))
+        .map(String::from);
+
+    let author_photo = author_card
+        .and_then(|c| c.first_text("photo"))
+        .and_then(|u| http_url(&u))
+        .map(String::from);
+
+    let content = entry
+        .and_then(|e| e.first_text("content").or_else(|| e.first_text("summary")))
+        .filter(|c| !c.is_empty())
+        .map(|c| c.chars().take(MAX_CONTENT_CHARS).collect());
+
+    let url = entry
+        .and_then(|e| e.first_text("url"))
+        .and_then(|u| http_url(&u))
+        .map(String::from)
+        .unwrap_or_else(|| source.to_string());
+
+    let published = entry
+        .and_then(|e| e.first_text("published"))
+        .and_then(|p| DateTime::parse_from_rfc3339(&p).ok())
+        .map(|dt| dt.timestamp() as usize);
+
+    Some(ParsedMention {
+        kind,
+        author_name,
+        author_url,
+        author_photo,
+        content,
+        url,
+        published,
+    })
+}
+
+/// Fetch `source` and check it for a mention of `target`.
+/// Gives None when the source is gone or no longer links to the target
+pub async fn verify(
+    client: &reqwest::Client,
+    source: &Url,
+    target: &Url,
+) -> anyhow::Result<Option<ParsedMention>> {
+    check_url(source)?;
+
+    let response = client.get(source.clone()).send().await?;
+
+    match response.status() {
+        reqwest::StatusCode::GONE | reqwest::StatusCode::NOT_FOUND => return Ok(None),
+        s if !s.is_success() => return Err(format_err!("Fetching {source} gave {s}")),
+        _ => (),
+    }
+
+    let body = fetch::read_body(response, MAX_SOURCE_BYTES).await?;
+
+    Ok(parse_mention(
+        &String::from_utf8_lossy(&body),
+        source,
+        target,
+    ))
+}
+
+/// Verify a mention and store it, or remove an earlier one the source no longer makes
+pub async fn process(
+    client: &reqwest::Client,
+    source: &Url,
+    target: &Url,
+    post_slug: &str,
+) -> anyhow::Result<()> {
+    let parsed = verify(client, source, target).await?;
+    let conn = db::DbConnection::new()?;
+
+    match parsed {
+        None => {
+            tracing::info!("Removing webmention from {source}, it no longer links to {target}");
+            conn.delete_webmention(source.as_str(), target.as_str())
+        }
+        Some(m) => conn.set_webmention(&Webmention {
+            id: 0,
+            source: source.to_string(),
+            target: target.to_string(),
+            post_slug: post_slug.into(),
+            kind: m.kind.into(),
+            author_name: m.author_name,
+            author_url: m.author_url,
+            author_photo: m.author_photo,
+            content: m.content,
+            url: m.url,
+            timestamp: m.published.unwrap_or_else(common::now_timestamp),
+        }),
+    }
+}
+
+/// Webmention receiving endpoint. Requests are checked for basic validity here,
+/// and the source is fetched and verified in the background
+pub async fn receive(Form(form): Form<WebmentionForm>) -> Result<StatusCode, SiteError> {
+    let (source, target) = match (http_url(&form.source), http_url(&form.target)) {
+        (Some(s), Some(t)) if !same_url(&s, &t) && check_url(&s).is_ok() => (s, t),
+        _ => return Err(SiteError::from_status(StatusCode::BAD_REQUEST)),
+    };
+
+    let post_slug = target_post_slug(&target, &db::DbConnection::new()?)?
+        .ok_or_else(|| SiteError::from_status(StatusCode::BAD_REQUEST))?;
+
+    tokio::spawn(async move {
+        if let Err(e) = process(fetch::http_client(), &source, &target, &post_slug).await {
+            tracing::warn!("Could not verify webmention from {source}: {e:?}");
+        }
+    });
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// The public url of a post, as used for the source of outgoing mentions
+pub fn post_url(slug: &str) -> String {
+    format!("{}/blog/{slug}", config::settings().site_url)
+}
+
+/// Links in `html` to other sites, without fragments or duplicates
+pub fn outbound_links(html: &str, source: &Url) -> Vec<Url> {
+    let selector = Selector::parse("a[href]").unwrap();
+
+    Html::parse_fragment(html)
+        .select(&selector)
+        .filter_map(|el| el.value().attr("href"))
+        .filter_map(|href| source.join(href).ok())
+        .filter(|u| matches!(u.scheme(), "http" | "https") && u.host_str() != source.host_str())
+        .map(|mut u| {
+            u.set_fragment(None);
+            u
+        })
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+fn sender_wakeup() -> &'static Notify {
+    static WAKEUP: OnceLock<Notify> = OnceLock::new();
+    WAKEUP.get_or_init(Notify::new)
+}
+
+/// Queue mentions for every link in a post's rendered html. Targets mentioned by an earlier
+/// version are queued again too, so they can find out a link has been removed
+pub fn queue_for_post(conn: &db::DbConnection, slug: &str, html: &str) -> anyhow::Result<usize> {
+    if !config::settings().send_webmentions {
+        return Ok(0);
+    }
+
+    let source = Url::parse(&post_url(slug))?;
+
+    let mut targets = outbound_links(html, &source)
+        .into_iter()
+        .map(String::from)
+        .collect::<BTreeSet<_>>();
+
+    targets.extend(
+        conn.outgoing_webmentions(Some(slug), None)?
+            .into_iter()
+            .filter(|d| d.source == source.as_str())
+            .map(|d| d.target),
+    );
+
+    let now = common::now_timestamp();
+
+    for target in &targets {
+        conn.queue_outgoing_webmention(slug, source.as_str(), target, now)?;
+    }
+
+    sender_wakeup().notify_one();
+
+    Ok(targets.len())
+}
+
+/// Find the `rel="webmention"` url in a Link header value, if there is one
+fn link_header_endpoint(value: &str, base: &Url) -> Option<Url> {
+    value.split(',').find_map(|link| {
+        let (url_part, params) = link.split_once(';')?;
+        let url = url_part.trim().strip_prefix('<')?.strip_suffix('>')?;
+
+        let is_webmention = params
+            .split(';')
+            .any(|param| match param.trim().split_once('=') {
+                Some((name, rels)) if name.trim().eq_ignore_ascii_case("rel") => rels
+                    .trim()
+                    .trim_matches('"')
+                    .split_whitespace()
+                    .any(|r| r.eq_ignore_ascii_case("webmention")),
+                _ => false,
+            });
+
+        is_webmention.then(|| base.join(url).ok()).flatten()
+    })
+}
+
+/// Webmention endpoint discovery, as described in the spec: a Link header takes priority,
+/// then the first `<link>` or `<a>` with `rel="webmention"` in the document.
+/// Relative endpoints resolve against the target's url after redirects
+pub async fn discover_endpoint(
+    client: &reqwest::Client,
+    target: &Url,
+) -> anyhow::Result<Option<Url>> {
+    check_url(target)?;
+
+    let response = client
+        .get(target.clone())
+        .send()
+        .await?
+        .error_for_status()?;
+    let base = response.url().clone();
+
+    let from_header = response
+        .headers()
+        .get_all(reqwest::header::LINK)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .find_map(|v| link_header_endpoint(v, &base));
+
+    if from_header.is_some() {
+        return Ok(from_header);
+    }
+
+    let is_html = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("html"));
+
+    if !is_html
+        || response
+            .content_length()
+            .is_some_and(|l| l as usize > MAX_SOURCE_BYTES)
+    {
+        return Ok(None);
+    }
+
+    let body = fetch::read_body(response, MAX_SOURCE_BYTES).await?;
+    let document = Html::parse_document(&String::from_utf8_lossy(&body));
+    let selector =
+        Selector::parse(r#"link[rel~="webmention"][href], a[rel~="webmention"][href]"#).unwrap();
+
+    Ok(document
+        .select(&selector)
+        .next()
+        .and_then(|el| el.value().attr("href"))
+        .and_then(|href| base.join(href).ok()))
+}
+
+/// Whether a failed attempt is worth trying again later
+enum SendError {
+    Transient(anyhow::Error),
+    Permanent(anyhow::Error),
+}
+
+fn is_transient(status: reqwest::StatusCode) -> bool {
+    status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+}
+
+impl From<reqwest::Error> for SendError {
+    fn from(e: reqwest::Error) -> Self {
+        match e.status() {
+            Some(s) if !is_transient(s) => SendError::Permanent(e.into()),
+            _ => SendError::Transient(e.into()),
+        }
+    }
+}
+
+/// Make one attempt at a delivery, updating it with the outcome
+async fn attempt_delivery(client: &reqwest::Client, delivery: &mut OutgoingWebmention) {
+    let now = common::now_timestamp();
+    delivery.attempts += 1;
+    delivery.last_attempt = Some(now);
+    delivery.next_attempt = None;
+    delivery.response_code = None;
+    delivery.error = None;
+
+    let result: Result<(), SendError> = async {
+        let target = Url::parse(&delivery.target).map_err(|e| SendError::Permanent(e.into()))?;
+
+        let endpoint = match discover_endpoint(client, &target).await {
+            Ok(Some(e)) => e,
+            Ok(None) => {
+                delivery.status = DeliveryStatus::NoEndpoint;
+                return Ok(());
+            }
+            Err(e) => {
+                return Err(match e.downcast::<reqwest::Error>() {
+                    Ok(e) => e.into(),
+                    Err(e) => SendError::Transient(e),
+                })
+            }
+        };
+
+        delivery.endpoint = Some(endpoint.to_string());
+        check_url(&endpoint).map_err(SendError::Permanent)?;
+
+        let response = client
+            .post(endpoint)
+            .form(&[("source", &delivery.source), ("target", &delivery.target)])
+            .send()
+            .await?;
+
+        delivery.response_code = Some(response.status().as_u16());
+        response.error_for_status()?;
+
+        delivery.status = DeliveryStatus::Sent;
+        Ok(())
+    }
+    .await;
+
+    match result {
+        Ok(()) => (),
+        Err(SendError::Transient(e)) if delivery.attempts < MAX_SEND_ATTEMPTS => {
+            let delay = RETRY_BASE_DELAY_SECS << (delivery.attempts - 1);
+            delivery.status = DeliveryStatus::Pending;
+            delivery.next_attempt = Some(now + delay);
+            delivery.error = Some(format!("{e:#}"));
+        }
+        Err(SendError::Transient(e) | SendError::Permanent(e)) => {
+            delivery.status = DeliveryStatus::Failed;
+            delivery.error = Some(format!("{e:#}"));
+        }
+    }
+}
+
+/// Attempt every delivery that's due, giving the time the next one will be
+async fn send_due(client: &reqwest::Client) -> anyhow::Result<Option<usize>> {
+    // One connection for the batch, since each open and close rereads and rewrites posts.json
+    let conn = db::DbConnection::new()?;
+    let due = conn.due_outgoing_webmentions(common::now_timestamp())?;
+
+    for mut delivery in due {
+        attempt_delivery(client, &mut delivery).await;
+
+        match &delivery.error {
+            Some(e) => tracing::warn!(
+                "Webmention to {} is {} after {} attempts: {e}",
+                delivery.target,
+                delivery.status.as_str(),
+                delivery.attempts
+            ),
+            None => tracing::info!(
+                "Webmention to {} is {}",
+                delivery.target,
+                delivery.status.as_str()
+            ),
+        }
+
+        conn.update_outgoing_webmention(&delivery)?;
+    }
+
+    conn.next_outgoing_attempt()
+}
+
+/// Background job sending queued mentions, woken when a post is saved
+/// and otherwise whenever the next retry is due
+pub async fn run_sender() {
+    loop {
+        let next_attempt = send_due(fetch::http_client()).await.unwrap_or_else(|e| {
+            tracing::warn!("Could not send webmentions: {e:?}");
+            None
+        });
+
+        let wait = next_attempt
+            .map(|t| t.saturating_sub(common::now_timestamp()))
+            .unwrap_or(SENDER_IDLE_SECS)
+            .clamp(1, SENDER_IDLE_SECS);
+
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(wait as u64)) => (),
+            _ = sender_wakeup().notified() => (),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct OutgoingQuery {
+    pub slug: Option<String>,
+    pub status: Option<DeliveryStatus>,
+}
+
+/// Delivery status of outgoing mentions, optionally filtered by post or status
+pub async fn list_outgoing(
+    AuthBearer(token): AuthBearer,
+    extract::Query(query): extract::Query<OutgoingQuery>,
+) -> Result<Json<Vec<OutgoingWebmention>>, SiteError> {
+    require_author(token)?;
+
+    let deliveries =
+        db::DbConnection::new()?.outgoing_webmentions(query.slug.as_deref(), query.status)?;
+
+    Ok(Json(deliveries))
+}
+
+/// Queue a delivery to be attempted again straight away
+pub async fn retry_outgoing(
+    AuthBearer(token): AuthBearer,
+    extract::Path(id): extract::Path<i64>,
+) -> Result<StatusCode, SiteError> {
+    require_author(token)?;
+
+    match db::DbConnection::new()?.retry_outgoing_webmention(id, common::now_timestamp())? {
+        true => {
+            sender_wakeup().notify_one();
+            Ok(StatusCode::ACCEPTED)
+        }
+        false => Err(SiteError::from_status(StatusCode::NOT_FOUND)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{serve, test_client};
+    use axum::{
+        body::{Body, Bytes},
+        http::header,
+        response::{Html as HtmlResponse, Redirect, Response},
+        routing::get,
+        Router,
+    };
+
+    async fn discover_at(client: &reqwest::Client, site: &Url, path: &str) -> Option<Url> {
+        discover_endpoint(client, &site.join(path).unwrap())
+            .await
+            .unwrap()
+    }
+
+    async fn verify_at(
+        client: &reqwest::Client,
+        site: &Url,
+        path: &str,
+        target: &Url,
+    ) -> anyhow::Result<Option<ParsedMention>> {
+        verify(client, &site.join(path).unwrap(), target).await
+    }
+
+    #[test]
+    fn link_header_endpoints() {
+        let base = Url::parse("https://example.com/post/1").unwrap();
+
+        assert_eq!(
+            link_header_endpoint(r#"<https://hooks.example/wm>; rel="webmention""#, &base),
+            Url::parse("https://hooks.example/wm").ok()
+        );
+        assert_eq!(
+            link_header_endpoint(
+                r#"</style.css>; rel=stylesheet, </wm?a=b>; rel="other webmention""#,
+                &base
+            ),
+            Url::parse("https://example.com/wm?a=b").ok()
+        );
+        assert_eq!(
+            link_header_endpoint("<endpoint>; REL=webmention", &base),
+            Url::parse("https://example.com/post/endpoint").ok()
+        );
+        assert_eq!(
+            link_header_endpoint(r#"</feed>; rel="alternate""#, &base),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn discovers_endpoints() {
+        let site = serve(
+            Router::new()
+                .route(
+                    "/header",
+                    get(|| async {
+                        (
+                            [(header::LINK, r#"</from-header>; rel="webmention""#)],
+                            HtmlResponse(r#"<link rel="webmention" href="/from-link">"#),
+                        )
+                    }),
+                )
+                .route(
+                    "/link",
+                    get(|| async {
+                        HtmlResponse(
+                            r#"<html><head><link rel="stylesheet" href="/s.css">
+                            <link rel="webmention" href="https://hooks.example/wm"></head></html>"#,
+                        )
+                    }),
+                )
+                .route(
+                    "/anchor",
+                    get(|| async {
+                        HtmlResponse(r#"<p><a rel="me webmention" href="../endpoint">wm</a></p>"#)
+                    }),
+                )
+                .route("/moved", get(|| async { Redirect::permanent("/new/page") }))
+                .route(
+                    "/new/page",
+                    get(|| async { HtmlResponse(r#"<link rel="webmention" href="endpoint">"#) }),
+                )
+                .route(
+                    "/none",
+                    get(|| async { HtmlResponse("<p>No endpoint</p>") }),
+                ),
+        );
+
+        let client = test_client();
+        let discover = |path| discover_at(&client, &site, path);
+
+        assert_eq!(discover("/header").await, site.join("/from-header").ok());
+        assert_eq!(
+            discover("/link").await,
+            Url::parse("https://hooks.example/wm").ok()
+        );
+        assert_eq!(discover("/anchor").await, site.join("/endpoint").ok());
+        // Relative to where the redirect ended up
+        assert_eq!(discover("/moved").await, site.join("/new/endpoint").ok());
+        assert_eq!(discover("/none").await, None);
+    }
+
+    #[tokio::test]
+    async fn verifies_sources() {
+        let target = Url::parse("https://implicit.computer/blog/bald").unwrap();
+
+        let site = serve(
+            Router::new()
+                .route(
+                    "/reply",
+                    get(|| async {
+                        HtmlResponse(
+                            r#"<div class="h-entry">
+                                <a class="p-author h-card" href="https://friend.example">Friend</a>
+                                <a class="u-in-reply-to" href="https://implicit.computer/blog/bald/">Re</a>
+                                <p class="e-content">Great post</p>
+                            </div>"#,
+                        )
+                    }),
+                )
+                .route(
+                    "/unlinked",
+                    get(|| async { HtmlResponse("<p>Nothing to see</p>") }),
+                )
+                .route("/gone", get(|| async { StatusCode::GONE }))
+                .route(
+                    "/huge",
+                    get(|| async {
+                        // Streamed, so there's no Content-Length to refuse it by
+                        let (mut tx, body) = Body::channel();
+                        tokio::spawn(async move {
+                            let chunk = Bytes::from(vec![b'a'; 64 * 1024]);
+                            while tx.send_data(chunk.clone()).await.is_ok() {}
+                        });
+                        Response::new(axum::body::boxed(body))
+                    }),
+                ),
+        );
+
+        let client = test_client();
+        let verify_path = |path| verify_at(&client, &site, path, &target);
+
+        let reply = verify_path("/reply").await.unwrap().unwrap();
+        assert_eq!(reply.kind, "reply");
+        assert_eq!(reply.author_name, "Friend");
+        assert_eq!(reply.content.as_deref(), Some("Great post"));
+
+        assert_eq!(verify_path("/unlinked").await.unwrap(), None);
+        assert_eq!(verify_path("/gone").await.unwrap(), None);
+        let too_large = verify_path("/huge").await.unwrap_err();
+        assert!(
+            too_large.to_string().contains("larger than"),
+            "{too_large:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn refuses_private_sources() {
+        let site = serve(Router::new().route("/", get(|| async { "hi" })));
+        let target = Url::parse("https://implicit.computer/blog/bald").unwrap();
+
+        let loopback = format!("http://127.0.0.1:{}/", site.port().unwrap());
+        for source in [
+            loopback.as_str(),
+            site.as_str(),
+            "http://10.0.0.1/",
+            "http://[::1]/",
+        ] {
+            let source = Url::parse(source).unwrap();
+
+            assert!(
+                verify(fetch::http_client(), &source, &target)
+                    .await
+                    .is_err(),
+                "{source} should be refused"
+            );
+        }
+    }
+}